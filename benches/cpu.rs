@@ -0,0 +1,45 @@
+use std::sync::{atomic::AtomicBool, Arc, Mutex};
+
+use chip8_rust::{
+    cpu::{Cpu, CpuConfig},
+    frontend::NoFrontend,
+};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+const STEPS: u64 = 1_000_000;
+
+// arithmetic on V0 to V2 in an endless loop, without drawing or waiting for anything
+const ARITHMETIC_LOOP: [u8; 12] = [
+    0x60, 0x01, // 200: V0 = 1
+    0x80, 0x14, // 202: V0 += V1
+    0x81, 0x05, // 204: V1 -= V0
+    0x82, 0x0E, // 206: V2 <<= 1
+    0x72, 0x01, // 208: V2 += 1
+    0x12, 0x02, // 20A: jump to 202
+];
+
+fn instruction_loop(c: &mut Criterion) {
+    let mut cpu = Cpu::new(
+        ARITHMETIC_LOOP.to_vec(),
+        CpuConfig::default(),
+        NoFrontend,
+        NoFrontend,
+        NoFrontend,
+        Arc::new(Mutex::new(0)),
+        Arc::new(Mutex::new(0)),
+        Arc::new(AtomicBool::new(false)),
+    )
+    .unwrap();
+
+    // reported as instructions per second
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(STEPS));
+    group.sample_size(10);
+    group.bench_function("arithmetic loop", |b| {
+        b.iter(|| (0..STEPS).for_each(|_| cpu.step().unwrap()))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, instruction_loop);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+use chip8_rust::{cpu::CpuScreenMem, palette::PALETTES};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+// a screen with both planes partly lit, so that every color shows up
+fn busy_screen(hires: bool) -> CpuScreenMem {
+    let mut screen = CpuScreenMem::new(hires);
+    (0..screen.height()).for_each(|y| {
+        screen.planes[0][y] = 0x0123_4567_89AB_CDEF_FEDC_BA98_7654_3210u128.rotate_left(y as u32);
+        screen.planes[1][y] = 0xF0F0_0F0F_CCCC_3333_AAAA_5555_FF00_00FFu128.rotate_right(y as u32);
+    });
+    screen
+}
+
+fn screen_to_rgba(c: &mut Criterion) {
+    let plane_colors = PALETTES[0].1.plane_colors();
+
+    [false, true].into_iter().for_each(|hires| {
+        let screen = busy_screen(hires);
+        let name = if hires { "hires" } else { "lores" };
+
+        // how the screen was converted before write_rgba
+        c.bench_function(&format!("{} pixel by pixel", name), |b| {
+            b.iter(|| {
+                let mut pixels = Vec::with_capacity(screen.width() * screen.height() * 4);
+                (0..screen.height()).for_each(|y| {
+                    (0..screen.width()).for_each(|x| {
+                        pixels.extend_from_slice(&plane_colors[screen.pixel(x, y) as usize]);
+                    });
+                });
+                black_box(pixels)
+            })
+        });
+
+        let mut pixels = vec![0; screen.width() * screen.height() * 4];
+        c.bench_function(&format!("{} write_rgba", name), |b| {
+            b.iter(|| {
+                black_box(&screen).write_rgba(0..screen.height(), &plane_colors, &mut pixels);
+                black_box(&pixels);
+            })
+        });
+    });
+}
+
+criterion_group!(benches, screen_to_rgba);
+criterion_main!(benches);
@@ -0,0 +1,306 @@
+#[cfg(feature = "audio")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "audio")]
+use std::time::Duration;
+use std::{
+    f32::consts::PI,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "audio")]
+use rodio::{OutputStream, PlayError, Sink, Source, StreamError};
+
+use crate::cpu::{AudioPattern, AUDIO_PATTERN_SIZE};
+#[cfg(feature = "audio")]
+use crate::frontend::Buzzer;
+
+const SAMPLE_RATE: u32 = 44100;
+const AMPLITUDE: f32 = 0.5;
+pub const DEFAULT_BEEP_HZ: f32 = 250f32;
+// the range that most people can hear
+pub const MIN_BEEP_HZ: f32 = 20f32;
+pub const MAX_BEEP_HZ: f32 = 20000f32;
+// how many samples are played from the copy of the audio pattern before looking for a new one,
+// under 6ms worth
+const PATTERN_REFRESH_SAMPLES: u32 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Sine,
+    Square,
+    Triangle,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "sine" => Ok(Self::Sine),
+            "square" => Ok(Self::Square),
+            "triangle" => Ok(Self::Triangle),
+            _ => Err("valid waveforms are: sine, square, triangle".to_string()),
+        }
+    }
+}
+
+// what the sound timer plays, unless an XO-CHIP program loads its own pattern
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Beep {
+    pub frequency: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for Beep {
+    fn default() -> Self {
+        Self {
+            frequency: DEFAULT_BEEP_HZ,
+            waveform: Waveform::Sine,
+        }
+    }
+}
+
+pub struct BeepSource {
+    beep: Beep,
+    // in cycles, between 0 and 1
+    phase: f32,
+}
+
+impl BeepSource {
+    pub fn new(beep: Beep) -> Self {
+        Self { beep, phase: 0f32 }
+    }
+}
+
+impl Iterator for BeepSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = match self.beep.waveform {
+            Waveform::Sine => (2f32 * PI * self.phase).sin(),
+            Waveform::Square if self.phase < 0.5 => 1f32,
+            Waveform::Square => -1f32,
+            Waveform::Triangle => 1f32 - 4f32 * (self.phase - 0.5).abs(),
+        };
+        self.phase = (self.phase + self.beep.frequency / SAMPLE_RATE as f32) % 1f32;
+
+        Some(AMPLITUDE * sample)
+    }
+}
+
+#[cfg(feature = "audio")]
+impl Source for BeepSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl AudioPattern {
+    // bits played per second
+    pub fn playback_rate(&self) -> f32 {
+        4000f32 * 2f32.powf((self.pitch as f32 - 64f32) / 48f32)
+    }
+
+    fn bit(&self, position: usize) -> bool {
+        self.bits[position / 8] & (0x80 >> (position % 8)) != 0
+    }
+}
+
+pub struct PatternSource {
+    // None until the program loads a pattern
+    pattern: Arc<Mutex<Option<AudioPattern>>>,
+    // The pattern that is played, along with its playback rate. Copied every
+    // PATTERN_REFRESH_SAMPLES samples, so that the audio thread rarely touches the lock that it
+    // shares with the cpu.
+    playing: Option<(AudioPattern, f32)>,
+    samples_until_refresh: u32,
+    // in bits, fractional as the playback rate rarely divides the sample rate
+    position: f32,
+    // played until then
+    beep: BeepSource,
+}
+
+impl PatternSource {
+    pub fn new(pattern: Arc<Mutex<Option<AudioPattern>>>, beep: Beep) -> Self {
+        Self {
+            pattern,
+            playing: None,
+            samples_until_refresh: 0,
+            position: 0f32,
+            beep: BeepSource::new(beep),
+        }
+    }
+}
+
+impl Iterator for PatternSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.samples_until_refresh == 0 {
+            // the audio thread must never wait for the cpu, the old copy is played a bit longer
+            // instead
+            if let Ok(pattern) = self.pattern.try_lock() {
+                self.playing = pattern.map(|pattern| (pattern, pattern.playback_rate()));
+            }
+            self.samples_until_refresh = PATTERN_REFRESH_SAMPLES;
+        }
+        self.samples_until_refresh -= 1;
+
+        let (pattern, playback_rate) = match &self.playing {
+            Some(playing) => playing,
+            None => return self.beep.next(),
+        };
+        let pattern_bits = (AUDIO_PATTERN_SIZE * 8) as f32;
+
+        let sample = if pattern.bit(self.position as usize) {
+            AMPLITUDE
+        } else {
+            -AMPLITUDE
+        };
+        self.position = (self.position + playback_rate / SAMPLE_RATE as f32) % pattern_bits;
+
+        Some(sample)
+    }
+}
+
+#[cfg(feature = "audio")]
+impl Source for PatternSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// such as on a machine without a sound device
+#[cfg(feature = "audio")]
+#[derive(Debug)]
+pub enum BuzzerInitError {
+    Stream(StreamError),
+    Sink(PlayError),
+}
+
+#[cfg(feature = "audio")]
+pub struct RodioBuzzer {
+    // NOTE: We must keep the stream around, dropping it also drops the sound device that the sink
+    // plays on.
+    _stream: OutputStream,
+    sink: Sink,
+    // can be toggled from other threads while playing
+    muted: Arc<AtomicBool>,
+}
+
+#[cfg(feature = "audio")]
+impl RodioBuzzer {
+    // the stream cannot be sent to another thread, so this has to run on the timer thread
+    pub fn new(
+        beep: Beep,
+        audio_pattern: Option<Arc<Mutex<Option<AudioPattern>>>>,
+        volume: f32,
+        muted: Arc<AtomicBool>,
+    ) -> Result<Self, BuzzerInitError> {
+        let (stream, stream_handle) =
+            OutputStream::try_default().map_err(BuzzerInitError::Stream)?;
+        let sink = Sink::try_new(&stream_handle).map_err(BuzzerInitError::Sink)?;
+        sink.pause();
+        sink.set_volume(volume);
+        match audio_pattern {
+            Some(audio_pattern) => sink.append(PatternSource::new(audio_pattern, beep)),
+            None => sink.append(BeepSource::new(beep)),
+        }
+
+        Ok(Self {
+            _stream: stream,
+            sink,
+            muted,
+        })
+    }
+}
+
+#[cfg(feature = "audio")]
+impl Buzzer for RodioBuzzer {
+    fn set_playing(&mut self, playing: bool) {
+        let playing = playing && !self.muted.load(Ordering::Relaxed);
+        if !playing && !self.sink.is_paused() {
+            self.sink.pause();
+        }
+        if playing && self.sink.is_paused() {
+            self.sink.play();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn playback_rate_doubles_every_48_pitch_steps() {
+        let rate = |pitch| {
+            AudioPattern {
+                bits: [0; 16],
+                pitch,
+            }
+            .playback_rate()
+        };
+        assert_eq!(rate(64), 4000f32);
+        assert_eq!(rate(112), 8000f32);
+        assert_eq!(rate(16), 2000f32);
+    }
+
+    #[test]
+    fn plays_beep_until_pattern_is_loaded() {
+        let pattern = Arc::new(Mutex::new(None));
+        let mut source = PatternSource::new(pattern.clone(), Beep::default());
+        let beep = BeepSource::new(Beep::default());
+        let refresh = PATTERN_REFRESH_SAMPLES as usize;
+        assert!(source.by_ref().take(refresh).eq(beep.take(refresh)));
+
+        let mut bits = [0; AUDIO_PATTERN_SIZE];
+        bits[0] = 0xF0;
+        *pattern.lock().unwrap() = Some(AudioPattern { bits, pitch: 64 });
+        let samples = source.take(200).collect::<Vec<_>>();
+        // at 4000 bits per second, each bit lasts for about 11 samples
+        assert!(samples[..44].iter().all(|sample| *sample == AMPLITUDE));
+        assert!(samples[46..].iter().all(|sample| *sample == -AMPLITUDE));
+    }
+
+    #[test]
+    fn keeps_playing_while_cpu_holds_pattern() {
+        let mut bits = [0; AUDIO_PATTERN_SIZE];
+        bits[0] = 0xF0;
+        let pattern = Arc::new(Mutex::new(Some(AudioPattern { bits, pitch: 64 })));
+        let mut source = PatternSource::new(pattern.clone(), Beep::default());
+        let before = source.by_ref().take(44).collect::<Vec<_>>();
+
+        let _held = pattern.lock().unwrap();
+        let refresh = PATTERN_REFRESH_SAMPLES as usize;
+        let samples = source.take(2 * refresh).collect::<Vec<_>>();
+        assert!(before.iter().all(|sample| *sample == AMPLITUDE));
+        // the copy goes on past the refresh that cannot take the lock, from the fifth bit on
+        assert!(samples[2..].iter().all(|sample| *sample == -AMPLITUDE));
+    }
+}
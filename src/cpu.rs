@@ -1,375 +1,2945 @@
-use std::{
+use alloc::{
+    boxed::Box,
+    collections::{BTreeSet, VecDeque},
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
     cmp::Ordering,
-    sync::{
-        mpsc::{Receiver, Sender},
-        Arc, Mutex,
-    },
+    ops::Range,
+    sync::atomic::{self, AtomicBool, AtomicU64},
+};
+#[cfg(feature = "std")]
+use std::{
+    io,
+    path::PathBuf,
     time::{Duration, Instant},
 };
 
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    debugger::{Inspection, Watchpoint},
+    disasm::{disassemble, mnemonic},
+    frontend::{KeySource, LatestState, PollError, ScreenSink, StatusSink},
+    opcode::Opcode,
+    quirks::Quirks,
+    sync::Mutex,
+};
+#[cfg(feature = "std")]
+use crate::{
+    savestate::{program_hash, write_state_file},
+    trace::Tracer,
+};
 
 const MEMORY_SIZE: usize = 4096;
-const PROGRAM_INIT_LOAD_POS: usize = 0x200;
+pub const PROGRAM_INIT_LOAD_POS: usize = 0x200;
 const MAX_ALLOWED_PROGRAM_SIZE: usize = MEMORY_SIZE - PROGRAM_INIT_LOAD_POS;
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+// SUPER-CHIP saves up to 8 registers to the HP48 "RPL user flags", XO-CHIP up to all 16
+const RPL_FLAG_COUNT: usize = 8;
+const XOCHIP_RPL_FLAG_COUNT: usize = 16;
 const FONT_START_POS: usize = 0x50;
 const FONT_END_POS: usize = 0x9F;
+// 5 bytes for each of the 16 hex digits
+pub const FONT_SIZE: usize = FONT_END_POS - FONT_START_POS + 1;
+
+// font taken from https://tobiasvl.github.io/blog/write-a-chip-8-emulator/
+const FONT: [u8; FONT_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// the 8x10 digits that FX30 points at, right after the small font. SUPER-CHIP only had 0 to 9, the
+// rest follow Octo.
+const BIG_FONT_START_POS: usize = FONT_END_POS + 1;
+const BIG_FONT_END_POS: usize = BIG_FONT_START_POS + BIG_FONT.len() - 1;
+const BIG_FONT: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+// the characters of text drawn by the emulator itself that are not hex digits, in the style of
+// FONT. Lowercase letters are drawn as uppercase, anything else missing as ?.
+const TEXT_GLYPHS: [(char, [u8; 5]); 32] = [
+    ('G', [0xF0, 0x80, 0xB0, 0x90, 0xF0]),
+    ('H', [0x90, 0x90, 0xF0, 0x90, 0x90]),
+    ('I', [0xE0, 0x40, 0x40, 0x40, 0xE0]),
+    ('J', [0x70, 0x20, 0x20, 0xA0, 0xE0]),
+    ('K', [0x90, 0xA0, 0xC0, 0xA0, 0x90]),
+    ('L', [0x80, 0x80, 0x80, 0x80, 0xF0]),
+    ('M', [0x90, 0xF0, 0xF0, 0x90, 0x90]),
+    ('N', [0x90, 0xD0, 0xB0, 0x90, 0x90]),
+    // narrower than 0
+    ('O', [0x60, 0x90, 0x90, 0x90, 0x60]),
+    ('P', [0xF0, 0x90, 0xF0, 0x80, 0x80]),
+    ('Q', [0x60, 0x90, 0x90, 0xB0, 0x70]),
+    ('R', [0xE0, 0x90, 0xE0, 0xA0, 0x90]),
+    ('S', [0x70, 0x80, 0x60, 0x10, 0xE0]),
+    ('T', [0xE0, 0x40, 0x40, 0x40, 0x40]),
+    ('U', [0x90, 0x90, 0x90, 0x90, 0xF0]),
+    ('V', [0x90, 0x90, 0x90, 0xA0, 0x40]),
+    ('W', [0x90, 0x90, 0xF0, 0xF0, 0x90]),
+    ('X', [0x90, 0x90, 0x60, 0x90, 0x90]),
+    ('Y', [0xA0, 0xA0, 0x40, 0x40, 0x40]),
+    ('Z', [0xF0, 0x10, 0x60, 0x80, 0xF0]),
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('-', [0x00, 0x00, 0xF0, 0x00, 0x00]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0xF0]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x40]),
+    (',', [0x00, 0x00, 0x00, 0x40, 0x80]),
+    ('\'', [0x40, 0x40, 0x00, 0x00, 0x00]),
+    ('!', [0x40, 0x40, 0x40, 0x00, 0x40]),
+    ('?', [0xE0, 0x10, 0x60, 0x00, 0x40]),
+    ('/', [0x10, 0x20, 0x40, 0x40, 0x80]),
+    ('(', [0x20, 0x40, 0x40, 0x40, 0x20]),
+    (')', [0x40, 0x20, 0x20, 0x20, 0x40]),
+    ('>', [0x80, 0x40, 0x20, 0x40, 0x80]),
+];
+// text is drawn with a pixel between the characters
+pub const TEXT_CHAR_WIDTH: usize = 5;
+pub const TEXT_CHAR_HEIGHT: usize = 5;
+const SPLASH_TEXT: &str = "CHIP-8";
 
 const INSTRUCTIONS_PER_SECOND: usize = 700;
+// how many of the last executed instructions crash dumps list
+const RECENT_INSTRUCTION_COUNT: usize = 16;
+pub const TICKS_PER_SECOND: usize = 60;
+
+pub const LORES_SCREEN_WIDTH: usize = 64;
+pub const LORES_SCREEN_HEIGHT: usize = 32;
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+// XO-CHIP has two bit planes, everything else only ever uses the first one
+pub const PLANE_COUNT: usize = 2;
+
+// Each row is stored with the leftmost pixel in the most significant bit. In lores mode, only
+// the upper LORES_SCREEN_WIDTH bits of the first LORES_SCREEN_HEIGHT rows are used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuScreenMem {
+    pub hires: bool,
+    pub planes: [[u128; HIRES_SCREEN_HEIGHT]; PLANE_COUNT],
+}
+
+impl CpuScreenMem {
+    pub fn new(hires: bool) -> Self {
+        Self {
+            hires,
+            planes: [[0; HIRES_SCREEN_HEIGHT]; PLANE_COUNT],
+        }
+    }
+
+    // shown until a program draws its first screen, with the name in the middle of the lores screen
+    pub fn splash() -> Self {
+        let mut screen = Self::new(false);
+        let left = (LORES_SCREEN_WIDTH - (SPLASH_TEXT.len() * TEXT_CHAR_WIDTH - 1)) / 2;
+        let top = (LORES_SCREEN_HEIGHT - TEXT_CHAR_HEIGHT) / 2;
+        screen.draw_text(left, top, SPLASH_TEXT);
+        screen
+    }
+
+    // Draws the text to the first plane, with the top left corner of its first character at x, y.
+    // The characters that do not fully fit on the screen are left out.
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str) {
+        if y + TEXT_CHAR_HEIGHT > self.height() {
+            return;
+        }
+        let width = self.width();
+        text.chars()
+            .enumerate()
+            .map(|(i, c)| (x + i * TEXT_CHAR_WIDTH, c))
+            .take_while(|(left, _)| left + TEXT_CHAR_WIDTH - 1 <= width)
+            .for_each(|(left, c)| {
+                text_glyph(c).iter().enumerate().for_each(|(row, bits)| {
+                    // the glyph is in the upper half of its byte
+                    self.planes[0][y + row] |= ((*bits as u128) << (u128::BITS - 8)) >> left;
+                });
+            });
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_WIDTH
+        } else {
+            LORES_SCREEN_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_HEIGHT
+        } else {
+            LORES_SCREEN_HEIGHT
+        }
+    }
+
+    // mask of the bits in a row that are visible in the current mode
+    pub fn row_mask(&self) -> u128 {
+        !0u128 << (u128::BITS as usize - self.width())
+    }
+
+    // color of the pixel, with bit N set if the pixel is lit in plane N
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        let bit = 1u128 << (u128::BITS as usize - 1 - x);
+        (0..PLANE_COUNT)
+            .filter(|plane| self.planes[*plane][y] & bit != 0)
+            .fold(0, |color, plane| color | (1 << plane))
+    }
+
+    // the planes whose bit is set in plane_mask
+    fn selected_planes(
+        &mut self,
+        plane_mask: u8,
+    ) -> impl Iterator<Item = &mut [u128; HIRES_SCREEN_HEIGHT]> {
+        self.planes
+            .iter_mut()
+            .enumerate()
+            .filter(move |(plane, _)| plane_mask & (1 << plane) != 0)
+            .map(|(_, rows)| rows)
+    }
+
+    // writes the RGBA colors of the rows into out, 4 bytes per pixel, for each combination of lit
+    // planes that pixel() can return
+    pub fn write_rgba(
+        &self,
+        rows: Range<usize>,
+        plane_colors: &[[u8; 4]; 1 << PLANE_COUNT],
+        out: &mut [u8],
+    ) {
+        let width = self.width();
+        out.chunks_exact_mut(width * 4)
+            .zip(rows)
+            .for_each(|(line, y)| {
+                let plane_rows = self.planes.map(|rows| rows[y]);
+                line.chunks_exact_mut(4).enumerate().for_each(|(x, pixel)| {
+                    let shift = u128::BITS as usize - 1 - x;
+                    let color = plane_rows
+                        .iter()
+                        .enumerate()
+                        .fold(0, |color, (plane, row)| {
+                            color | (((row >> shift) & 1) as usize) << plane
+                        });
+                    pixel.copy_from_slice(&plane_colors[color]);
+                });
+            });
+    }
+
+    pub fn clear(&mut self, plane_mask: u8) {
+        self.selected_planes(plane_mask)
+            .for_each(|rows| *rows = [0; HIRES_SCREEN_HEIGHT]);
+    }
+
+    // one character per pixel, '.' when unlit and '#', '+' or '@' depending on the lit planes
+    pub fn ascii_art(&self) -> String {
+        const CHARS: [char; 1 << PLANE_COUNT] = ['.', '#', '+', '@'];
+
+        (0..self.height())
+            .map(|y| {
+                let mut line = (0..self.width())
+                    .map(|x| CHARS[self.pixel(x, y) as usize])
+                    .collect::<String>();
+                line.push('\n');
+                line
+            })
+            .collect()
+    }
+
+    // plain PBM bitmap, where a pixel is black if it is lit in any plane
+    pub fn pbm(&self) -> String {
+        // the format asks for lines of at most 70 characters
+        const PIXELS_PER_LINE: usize = 64;
+
+        let mut pbm = format!("P1\n{} {}\n", self.width(), self.height());
+        (0..self.height()).for_each(|y| {
+            (0..self.width()).for_each(|x| {
+                pbm.push(if self.pixel(x, y) != 0 { '1' } else { '0' });
+                if (x + 1) % PIXELS_PER_LINE == 0 {
+                    pbm.push('\n');
+                }
+            });
+        });
+        pbm
+    }
+
+    pub fn scroll_down(&mut self, plane_mask: u8, amount: usize) {
+        let height = self.height();
+        self.selected_planes(plane_mask).for_each(|rows| {
+            (0..height).rev().for_each(|y| {
+                rows[y] = if y >= amount { rows[y - amount] } else { 0 };
+            });
+        });
+    }
+
+    pub fn scroll_up(&mut self, plane_mask: u8, amount: usize) {
+        let height = self.height();
+        self.selected_planes(plane_mask).for_each(|rows| {
+            (0..height).for_each(|y| {
+                rows[y] = if y + amount < height {
+                    rows[y + amount]
+                } else {
+                    0
+                };
+            });
+        });
+    }
+
+    pub fn scroll_right(&mut self, plane_mask: u8, amount: usize) {
+        let row_mask = self.row_mask();
+        let height = self.height();
+        self.selected_planes(plane_mask).for_each(|rows| {
+            rows[0..height]
+                .iter_mut()
+                .for_each(|row| *row = (*row >> amount) & row_mask)
+        });
+    }
 
-pub type CpuScreenMem = [u64; 32];
+    pub fn scroll_left(&mut self, plane_mask: u8, amount: usize) {
+        let row_mask = self.row_mask();
+        let height = self.height();
+        self.selected_planes(plane_mask).for_each(|rows| {
+            rows[0..height]
+                .iter_mut()
+                .for_each(|row| *row = (*row << amount) & row_mask)
+        });
+    }
+}
 
 pub enum CpuIoEvents {
     KeyPressed(u8),
     KeyReleased(u8),
+    TogglePause,
+    Step,
+    AdvanceFrame,
+    #[cfg(feature = "std")]
+    SaveState(PathBuf),
+    LoadState(Box<CpuState>),
+    Reset,
+    Rewind(bool),
+    DumpState,
+    // prints what the debugger asked for to stdout
+    Inspect(Inspection),
+    AddWatchpoint(Watchpoint),
+    RemoveWatchpoint(Watchpoint),
+    AddBreakpoint(usize),
+    RemoveBreakpoint(usize),
+    // scales the instructions executed per tick, the timers keep running at 60Hz
+    SetSpeedMultiplier(f32),
+    // stops the cpu, such as when the window is closing
+    Shutdown,
+}
+
+pub enum CpuStatusEvents {
+    Exited,
+    Crashed(ExecError),
+    // the cpu paused on its own, such as for a watchpoint, for the given reason
+    Paused(String),
+    // text for the user, such as the answers to debugger commands and the instructions executed
+    // while stepping, without a trailing newline
+    Output(String),
+}
+
+// XO-CHIP plays the sound as a 1-bit pattern, looped at a rate derived from the pitch register
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioPattern {
+    pub bits: [u8; AUDIO_PATTERN_SIZE],
+    pub pitch: u8,
+}
+
+impl Default for AudioPattern {
+    // a 250Hz square wave, for programs that set the pitch before loading a pattern
+    fn default() -> Self {
+        Self {
+            bits: [
+                0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+                0xFF, 0x00,
+            ],
+            pitch: 64,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CpuState {
+    pub memory: Vec<u8>,
+    pub screen_hires: bool,
+    pub screen_planes: Vec<Vec<u128>>,
+    pub selected_planes: u8,
+    pub keypad_state: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+
+    pub program_counter: u16,
+    pub index_register: u16,
+    pub stack: Vec<u16>,
+    pub variable_registers: [u8; 16],
+    pub waiting_for_key: Option<u8>,
+    pub audio_pattern: Option<AudioPattern>,
+    pub rpl_flags: [u8; XOCHIP_RPL_FLAG_COUNT],
+}
+
+// the rows of the character in the style of FONT, with its pixels in the upper half of each byte
+pub fn text_glyph(c: char) -> [u8; TEXT_CHAR_HEIGHT] {
+    match c.to_digit(16) {
+        Some(digit) => {
+            let start = digit as usize * TEXT_CHAR_HEIGHT;
+            FONT[start..start + TEXT_CHAR_HEIGHT].try_into().unwrap()
+        }
+        None => TEXT_GLYPHS
+            .iter()
+            .find(|(letter, _)| *letter == c.to_ascii_uppercase())
+            .or_else(|| TEXT_GLYPHS.iter().find(|(letter, _)| *letter == '?'))
+            .map_or([0; TEXT_CHAR_HEIGHT], |(_, rows)| *rows),
+    }
 }
 
 fn get_keypad_state_mask(key: u8) -> u16 {
     1 << key
 }
 
+// the 16-byte aligned rows of memory surrounding the address
+const HEX_DUMP_BYTES_PER_ROW: usize = 16;
+
+// the rows around the address
+fn hex_dump_around(memory: &[u8], address: usize) -> String {
+    let start = (address / HEX_DUMP_BYTES_PER_ROW).saturating_sub(1) * HEX_DUMP_BYTES_PER_ROW;
+    let end = ((address / HEX_DUMP_BYTES_PER_ROW + 2) * HEX_DUMP_BYTES_PER_ROW).min(memory.len());
+    hex_dump(memory, start..end)
+}
+
+fn hex_dump(memory: &[u8], range: Range<usize>) -> String {
+    let end = range.end;
+    range
+        .step_by(HEX_DUMP_BYTES_PER_ROW)
+        .map(|row_start| {
+            let bytes = memory[row_start..(row_start + HEX_DUMP_BYTES_PER_ROW).min(end)]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("  {:03X}: {}\n", row_start, bytes)
+        })
+        .collect()
+}
+
 pub struct Cpu {
+    program: Vec<u8>,
+    quirks: Quirks,
+    xochip: bool,
     memory: [u8; MEMORY_SIZE],
     screen_pixels: CpuScreenMem,
-    screen_update_sender: Sender<CpuScreenMem>,
-    cpu_io_receiver: Receiver<CpuIoEvents>,
+    selected_planes: u8,
+    screen_sink: Box<dyn ScreenSink>,
+    key_source: Box<dyn KeySource>,
+    status_sink: Box<dyn StatusSink>,
     keypad_state: u16,
     delay_timer_arc: Arc<Mutex<u8>>,
     sound_timer_arc: Arc<Mutex<u8>>,
+    audio_pattern_arc: Arc<Mutex<Option<AudioPattern>>>,
+    paused_arc: Arc<AtomicBool>,
+    run_state: RunState,
+    screen_updated: bool,
+    exited: bool,
+    rewinding: bool,
+    speed_multiplier: f32,
+    // the fraction of an instruction that is carried over to the next tick
+    instruction_budget: f64,
+    instructions_executed: u64,
+    // lets other threads see how fast the cpu runs, updated after every tick
+    instructions_executed_arc: Arc<AtomicU64>,
+    rewind_capacity: usize,
+    rewind_buffer: VecDeque<CpuState>,
+    #[cfg(feature = "std")]
+    tracer: Option<Tracer>,
+    // where the flags are kept between runs, if they are
+    #[cfg(feature = "std")]
+    rpl_flags_path: Option<PathBuf>,
+    font: [u8; FONT_SIZE],
+    // where to write the crash dump, if anywhere
+    #[cfg(feature = "std")]
+    crash_dump_path: Option<PathBuf>,
+    // published every tick for whoever shows the state live, such as the debug overlay and the HUD
+    latest_states: Vec<LatestState>,
+    // the address and the instruction of the last few executed instructions, for crash dumps
+    recent_instructions: VecDeque<(usize, u16)>,
+    // along with the value that each one last saw
+    watchpoints: Vec<(Watchpoint, u8)>,
+    breakpoints: BTreeSet<usize>,
+    // the breakpoint that the cpu paused at, which does not pause it again when resuming from it
+    paused_breakpoint: Option<usize>,
 
     program_counter: usize,
     index_register: u16,
     stack: Vec<u16>,
+    max_stack_depth: usize,
+    strict_jumps: bool,
     variable_registers: [u8; 16],
+    waiting_for_key: Option<usize>,
+    rpl_flags: [u8; XOCHIP_RPL_FLAG_COUNT],
+    // for CXNN, seeded again on every reset
+    rng: StdRng,
+    rng_seed: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CpuConfig {
+    pub quirks: Quirks,
+    pub xochip: bool,
+    pub max_stack_depth: usize,
+    // whether jumping into the interpreter area (below PROGRAM_INIT_LOAD_POS) is an error
+    pub strict_jumps: bool,
+    pub rewind_capacity: usize,
+    pub start_paused: bool,
+    // for CXNN, which draws different numbers on every run without one
+    pub rng_seed: Option<u64>,
+}
+
+impl Default for CpuConfig {
+    fn default() -> Self {
+        Self {
+            quirks: Quirks::default(),
+            xochip: false,
+            // the original interpreter had room for 16 return addresses
+            max_stack_depth: 16,
+            strict_jumps: false,
+            rewind_capacity: 0,
+            start_paused: false,
+            rng_seed: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RunState {
+    Running,
+    Paused,
+    // execute a single instruction, then pause
+    Stepping,
+    // execute until the next screen update, then pause
+    AdvancingFrame,
 }
 
 #[derive(Debug)]
 pub enum InitCpuError {
+    EmptyProgram,
     ProgramTooBig { actual: usize, allowed: usize },
 }
 
+// errors caused by the program, which stop the cpu
+#[derive(Debug)]
+pub enum ExecError {
+    StackOverflow {
+        program_counter: usize,
+        max_depth: usize,
+    },
+    StackUnderflow {
+        program_counter: usize,
+    },
+    // the whole instruction has to fit in memory
+    ProgramCounterOutOfBounds {
+        program_counter: usize,
+    },
+    JumpBelowProgram {
+        program_counter: usize,
+        target: usize,
+    },
+    // the bytes that an instruction reads or writes at I run past the end of memory
+    MemoryOutOfBounds {
+        program_counter: usize,
+        address: usize,
+        length: usize,
+    },
+    // including 0NNN, the machine code routines of the original interpreter
+    InvalidInstruction {
+        program_counter: usize,
+        instruction: u16,
+    },
+}
+
+#[derive(Debug)]
+pub enum RunHeadlessError {
+    // there is nobody to press a key
+    WaitingForKey { program_counter: usize },
+    Exec(ExecError),
+}
+
+#[derive(Debug)]
+pub enum LoadStateError {
+    InvalidMemorySize { actual: usize, expected: usize },
+    InvalidScreenSize { actual: usize, expected: usize },
+    InvalidPlaneCount { actual: usize, expected: usize },
+}
+
+// the checks that Cpu::new makes, for frontends that want to fail before setting anything up
+pub fn validate_program(program: &[u8]) -> Result<(), InitCpuError> {
+    if program.is_empty() {
+        return Err(InitCpuError::EmptyProgram);
+    }
+    if program.len() > MAX_ALLOWED_PROGRAM_SIZE {
+        return Err(InitCpuError::ProgramTooBig {
+            actual: program.len(),
+            allowed: MAX_ALLOWED_PROGRAM_SIZE,
+        });
+    }
+    Ok(())
+}
+
+// StdRng produces the same numbers for a seed on every platform, unlike SmallRng
+fn create_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        #[cfg(feature = "std")]
+        None => StdRng::from_entropy(),
+        // there is nothing to seed from without std, so every run gets the same numbers
+        #[cfg(not(feature = "std"))]
+        None => StdRng::seed_from_u64(0),
+    }
+}
+
 impl Cpu {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         program: Vec<u8>,
-        screen_update_sender: Sender<CpuScreenMem>,
-        cpu_io_receiver: Receiver<CpuIoEvents>,
+        config: CpuConfig,
+        screen_sink: impl ScreenSink + 'static,
+        key_source: impl KeySource + 'static,
+        status_sink: impl StatusSink + 'static,
         delay_timer_arc: Arc<Mutex<u8>>,
         sound_timer_arc: Arc<Mutex<u8>>,
+        paused_arc: Arc<AtomicBool>,
     ) -> Result<Self, InitCpuError> {
-        let mut memory = [0; MEMORY_SIZE];
+        validate_program(&program)?;
+        if program.len() % 2 != 0 {
+            log::warn!(
+                "Program has an odd size of {} bytes, its last byte can only be data",
+                program.len()
+            );
+        }
 
-        // insert program to memory
-        if program.len() > MAX_ALLOWED_PROGRAM_SIZE {
-            return Err(InitCpuError::ProgramTooBig {
-                actual: program.len(),
-                allowed: MAX_ALLOWED_PROGRAM_SIZE,
-            });
+        let mut cpu = Self {
+            program,
+            quirks: config.quirks,
+            xochip: config.xochip,
+            memory: [0; MEMORY_SIZE],
+            screen_pixels: CpuScreenMem::new(false),
+            selected_planes: 1,
+            screen_sink: Box::new(screen_sink),
+            key_source: Box::new(key_source),
+            status_sink: Box::new(status_sink),
+            keypad_state: 0,
+            delay_timer_arc,
+            sound_timer_arc,
+            audio_pattern_arc: Arc::new(Mutex::new(None)),
+            paused_arc,
+            run_state: RunState::Running,
+            screen_updated: false,
+            exited: false,
+            rewinding: false,
+            speed_multiplier: 1f32,
+            instruction_budget: 0f64,
+            instructions_executed: 0,
+            instructions_executed_arc: Arc::new(AtomicU64::new(0)),
+            rewind_capacity: config.rewind_capacity,
+            rewind_buffer: VecDeque::with_capacity(config.rewind_capacity),
+            #[cfg(feature = "std")]
+            tracer: None,
+            #[cfg(feature = "std")]
+            rpl_flags_path: None,
+            font: FONT,
+            #[cfg(feature = "std")]
+            crash_dump_path: None,
+            latest_states: Vec::new(),
+            recent_instructions: VecDeque::with_capacity(RECENT_INSTRUCTION_COUNT),
+            watchpoints: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            paused_breakpoint: None,
+            program_counter: PROGRAM_INIT_LOAD_POS,
+            index_register: 0,
+            stack: Vec::with_capacity(config.max_stack_depth),
+            max_stack_depth: config.max_stack_depth,
+            strict_jumps: config.strict_jumps,
+            variable_registers: [0; 16],
+            waiting_for_key: None,
+            rpl_flags: [0; XOCHIP_RPL_FLAG_COUNT],
+            rng: create_rng(config.rng_seed),
+            rng_seed: config.rng_seed,
+        };
+        cpu.initialize();
+        if config.start_paused {
+            cpu.set_run_state(RunState::Paused);
         }
 
-        memory[PROGRAM_INIT_LOAD_POS..(PROGRAM_INIT_LOAD_POS + program.len())]
-            .copy_from_slice(&program);
+        Ok(cpu)
+    }
+
+    fn initialize(&mut self) {
+        self.memory = [0; MEMORY_SIZE];
+
+        // insert program to memory
+        self.memory[PROGRAM_INIT_LOAD_POS..(PROGRAM_INIT_LOAD_POS + self.program.len())]
+            .copy_from_slice(&self.program);
 
         // insert font to memory
-        // font taken from https://tobiasvl.github.io/blog/write-a-chip-8-emulator/
-        memory[FONT_START_POS..(FONT_END_POS + 1)].copy_from_slice(&[
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ]);
+        self.memory[FONT_START_POS..(FONT_END_POS + 1)].copy_from_slice(&self.font);
+        self.memory[BIG_FONT_START_POS..(BIG_FONT_END_POS + 1)].copy_from_slice(&BIG_FONT);
 
-        let screen_pixels = [0; 32];
-        let keypad_state = 0;
+        self.screen_pixels = CpuScreenMem::new(false);
+        self.selected_planes = 1;
+        self.keypad_state = 0;
 
-        let program_counter = PROGRAM_INIT_LOAD_POS;
-        let index_register = 0;
-        let stack = Vec::with_capacity(16);
-        let variable_registers = [0; 16];
+        self.program_counter = PROGRAM_INIT_LOAD_POS;
+        self.recent_instructions.clear();
+        self.index_register = 0;
+        self.stack.clear();
+        self.variable_registers = [0; 16];
+        self.waiting_for_key = None;
+        self.rng = create_rng(self.rng_seed);
+        *self.audio_pattern_arc.lock().unwrap() = None;
+        self.update_watched_values();
+    }
 
-        Ok(Self {
-            memory,
-            screen_pixels,
-            screen_update_sender,
-            cpu_io_receiver,
-            keypad_state,
-            delay_timer_arc,
-            sound_timer_arc,
-            program_counter,
-            index_register,
-            stack,
-            variable_registers,
-        })
+    pub fn reset(&mut self) {
+        self.initialize();
+        *self.delay_timer_arc.lock().unwrap() = 0;
+        *self.sound_timer_arc.lock().unwrap() = 0;
+        self.send_screen_update();
     }
 
-    fn send_screen_update(&self) {
-        self.screen_update_sender
-            .send(self.screen_pixels)
-            .expect("Update screen failed!");
+    fn set_run_state(&mut self, run_state: RunState) {
+        self.run_state = run_state;
+        // the timers read the same flag, so they freeze along with the cpu
+        self.paused_arc
+            .store(run_state != RunState::Running, atomic::Ordering::Relaxed);
     }
 
-    fn process_cpu_io_event(&mut self, event: &CpuIoEvents) {
-        match event {
-            CpuIoEvents::KeyPressed(key) => {
-                self.keypad_state |= get_keypad_state_mask(*key);
-            }
-            CpuIoEvents::KeyReleased(key) => {
-                self.keypad_state &= !get_keypad_state_mask(*key);
-            }
-        }
+    fn send_screen_update(&mut self) {
+        self.screen_updated = true;
+        self.screen_sink.update(&self.screen_pixels);
     }
 
-    pub fn run(&mut self) {
-        let mut rng = rand::thread_rng();
-        let duration_per_instruction: Duration =
-            Duration::from_secs_f32(1f32 / INSTRUCTIONS_PER_SECOND as f32);
+    pub fn screen(&self) -> &CpuScreenMem {
+        &self.screen_pixels
+    }
 
-        loop {
-            let start_time = Instant::now();
+    // Vx, for x up to F
+    pub fn variable_register(&self, x: usize) -> u8 {
+        self.variable_registers[x]
+    }
+
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    pub fn get_audio_pattern_arc(&self) -> Arc<Mutex<Option<AudioPattern>>> {
+        self.audio_pattern_arc.clone()
+    }
+
+    pub fn get_instructions_executed_arc(&self) -> Arc<AtomicU64> {
+        self.instructions_executed_arc.clone()
+    }
+
+    // lets a new cpu play through the sound timer thread of the previous one
+    pub fn set_audio_pattern_arc(&mut self, audio_pattern_arc: Arc<Mutex<Option<AudioPattern>>>) {
+        *audio_pattern_arc.lock().unwrap() = *self.audio_pattern_arc.lock().unwrap();
+        self.audio_pattern_arc = audio_pattern_arc;
+    }
+
+    // replaces the built-in font, which FX29 points at
+    pub fn set_font(&mut self, font: [u8; FONT_SIZE]) {
+        self.font = font;
+        self.memory[FONT_START_POS..(FONT_END_POS + 1)].copy_from_slice(&self.font);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn add_latest_state(&mut self, latest_state: LatestState) {
+        self.latest_states.push(latest_state);
+    }
 
-            while let Ok(event) = self.cpu_io_receiver.try_recv() {
-                self.process_cpu_io_event(&event);
+    // the flags outlive resets, and with a file also the emulator itself. A missing file just
+    // means that nothing was saved yet.
+    #[cfg(feature = "std")]
+    pub fn set_rpl_flags_path(&mut self, path: PathBuf) {
+        match std::fs::read(&path) {
+            Ok(flags) => {
+                let count = flags.len().min(XOCHIP_RPL_FLAG_COUNT);
+                self.rpl_flags[..count].copy_from_slice(&flags[..count]);
             }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => log::warn!("Cannot read flags from {:?}: {:?}", path, err),
+        }
+        self.rpl_flags_path = Some(path);
+    }
 
-            let instruction = ((self.memory[self.program_counter] as u16) << 8)
-                + self.memory[self.program_counter + 1] as u16;
-            self.program_counter += 2;
+    fn rpl_flag_count(&self) -> usize {
+        if self.xochip {
+            XOCHIP_RPL_FLAG_COUNT
+        } else {
+            RPL_FLAG_COUNT
+        }
+    }
 
-            let op = ((instruction & 0xF000) >> 12) as u8;
-            let x = ((instruction & 0x0F00) >> 8) as usize;
-            let y = ((instruction & 0x00F0) >> 4) as usize;
-            let n = (instruction & 0x000F) as u8;
-            let nn = (instruction & 0x00FF) as u8;
-            let nnn = instruction & 0x0FFF;
+    #[cfg(feature = "std")]
+    fn write_rpl_flags(&self) {
+        if let Some(path) = &self.rpl_flags_path {
+            if let Err(err) = std::fs::write(path, &self.rpl_flags[..self.rpl_flag_count()]) {
+                log::warn!("Cannot write flags to {:?}: {:?}", path, err);
+            }
+        }
+    }
 
-            let mut skip = false;
+    fn process_cpu_io_event(&mut self, event: &CpuIoEvents) {
+        #[cfg(feature = "std")]
+        if let Some(tracer) = &mut self.tracer {
+            match event {
+                CpuIoEvents::KeyPressed(key) => tracer.event(&format!("key {:X} pressed", key)),
+                CpuIoEvents::KeyReleased(key) => tracer.event(&format!("key {:X} released", key)),
+                _ => {}
+            }
+        }
 
-            match op {
-                0x0 => {
-                    if nnn == 0xE0 {
-                        self.screen_pixels = [0; 32];
-                        self.send_screen_update();
-                    } else if nnn == 0xEE {
-                        self.program_counter = self
-                            .stack
-                            .pop()
-                            .expect("Should not call 0x00EE on an empty stack.")
-                            as usize;
-                    } else {
-                        panic!("{:#06x} might be a call to a machine assembly routine, but this emulator does not support that.", instruction);
-                    }
-                }
-                0x1 => {
-                    self.program_counter = nnn as usize;
-                }
-                0x2 => {
-                    self.stack.push(self.program_counter as u16);
-                    self.program_counter = nnn as usize;
-                }
-                0x3 => {
-                    if self.variable_registers[x] == nn {
-                        skip = true;
-                    }
-                }
-                0x4 => {
-                    if self.variable_registers[x] != nn {
-                        skip = true;
-                    }
-                }
-                0x5 => {
-                    if self.variable_registers[x] == self.variable_registers[y] {
-                        skip = true;
-                    }
-                }
-                0x6 => {
-                    self.variable_registers[x] = nn;
+        match event {
+            CpuIoEvents::KeyPressed(key) => {
+                self.keypad_state |= get_keypad_state_mask(*key);
+                if let Some(x) = self.waiting_for_key.take() {
+                    self.variable_registers[x] = *key;
                 }
-                0x7 => {
-                    self.variable_registers[x] = self.variable_registers[x].wrapping_add(nn);
+            }
+            CpuIoEvents::KeyReleased(key) => {
+                self.keypad_state &= !get_keypad_state_mask(*key);
+            }
+            #[cfg(feature = "std")]
+            CpuIoEvents::SaveState(path) => {
+                match write_state_file(path, program_hash(&self.program), &self.snapshot()) {
+                    Ok(_) => log::info!("Saved state to {:?}", path),
+                    Err(err) => log::error!("Cannot save state to {:?}: {:?}", path, err),
                 }
-                0x8 => match n {
-                    0x0 => {
-                        self.variable_registers[x] = self.variable_registers[y];
-                    }
-                    0x1 => {
-                        self.variable_registers[x] |= self.variable_registers[y];
-                    }
-                    0x2 => {
-                        self.variable_registers[x] &= self.variable_registers[y];
-                    }
-                    0x3 => {
-                        self.variable_registers[x] ^= self.variable_registers[y];
-                    }
-                    0x4 => {
-                        let (final_value, overflowed) =
-                            self.variable_registers[x].overflowing_add(self.variable_registers[y]);
-
-                        self.variable_registers[0xF] = overflowed.into();
-                        self.variable_registers[x] = final_value;
-                    }
-                    0x5 => {
-                        self.variable_registers[0xF] =
-                            (self.variable_registers[x] > self.variable_registers[y]).into();
-                        self.variable_registers[x] =
-                            self.variable_registers[x].wrapping_sub(self.variable_registers[y]);
-                    }
-                    0x6 => {
-                        // TODO: Ambiguous instruction - provide configuration
-                        self.variable_registers[0xF] = self.variable_registers[x] & 1;
-                        self.variable_registers[x] >>= 1;
-                    }
-                    0x7 => {
-                        self.variable_registers[0xF] =
-                            (self.variable_registers[y] > self.variable_registers[x]).into();
-                        self.variable_registers[x] =
-                            self.variable_registers[y].wrapping_sub(self.variable_registers[x]);
-                    }
-                    0xE => {
-                        // TODO: Ambiguous instruction - provide configuration
-                        self.variable_registers[0xF] = (self.variable_registers[x] & 0x80) >> 7;
-                        self.variable_registers[x] <<= 1;
-                    }
-                    _ => {
-                        panic!("{:#06x} is not a valid 0x8 instruction.", instruction);
-                    }
-                },
-                0x9 => {
-                    if self.variable_registers[x] != self.variable_registers[y] {
-                        skip = true;
-                    }
+            }
+            CpuIoEvents::LoadState(state) => {
+                if let Err(err) = self.load_state(state.as_ref().clone()) {
+                    log::error!("Cannot load state: {:?}", err);
                 }
-                0xA => {
-                    self.index_register = nnn;
+            }
+            CpuIoEvents::Reset => {
+                self.reset();
+            }
+            CpuIoEvents::DumpState => {
+                self.output(self.dump());
+            }
+            CpuIoEvents::Inspect(inspection) => {
+                self.output(self.inspect(inspection));
+            }
+            CpuIoEvents::AddWatchpoint(watchpoint) => {
+                if self.add_watchpoint(*watchpoint) {
+                    self.output(format!("Watching {}", watchpoint));
+                } else {
+                    self.output(format!("{} cannot be watched", watchpoint));
                 }
-                0xB => {
-                    // TODO: Ambiguous instruction - provide configuration
-                    self.program_counter = nnn as usize + self.variable_registers[0x0] as usize;
-                }
-                0xC => {
-                    self.variable_registers[x] = rng.gen::<u8>() & nn;
-                }
-                0xD => {
-                    let x_start = (self.variable_registers[x] % 64) as u16;
-                    let y_start = (self.variable_registers[y] % 32) as u16;
-                    self.variable_registers[0xF] = 0;
-
-                    let total_len = self.screen_pixels.len();
-                    (y_start..(y_start + n as u16))
-                        .into_iter()
-                        .filter(|y| (*y as usize) < total_len)
-                        .for_each(|y| {
-                            let nth_byte =
-                                self.memory[(self.index_register + y - y_start) as usize] as u64;
-                            let mask = match x_start.cmp(&56) {
-                                Ordering::Equal => nth_byte,
-                                Ordering::Less => nth_byte << (56 - x_start),
-                                Ordering::Greater => nth_byte >> (x_start - 56),
-                            };
-                            let is_updated = (mask & self.screen_pixels[y as usize]) != 0;
-                            if is_updated {
-                                self.variable_registers[0xF] = 1;
-                            }
-                            self.screen_pixels[y as usize] ^= mask;
-                        });
-                    self.send_screen_update();
+            }
+            CpuIoEvents::RemoveWatchpoint(watchpoint) => {
+                if self.remove_watchpoint(*watchpoint) {
+                    self.output(format!("Stopped watching {}", watchpoint));
+                } else {
+                    self.output(format!("{} was not watched", watchpoint));
                 }
-                0xE => {
-                    if nn == 0x9E {
-                        skip = self.keypad_state
-                            & get_keypad_state_mask(self.variable_registers[x])
-                            != 0;
-                    } else if nn == 0xA1 {
-                        skip = self.keypad_state
-                            & get_keypad_state_mask(self.variable_registers[x])
-                            == 0;
-                    } else {
-                        panic!("{:#06x} is not a valid 0xE instruction.", instruction);
-                    }
+            }
+            CpuIoEvents::AddBreakpoint(address) => {
+                self.add_breakpoint(*address);
+                self.output(format!("Breakpoint set at {:#05x}", address));
+            }
+            CpuIoEvents::RemoveBreakpoint(address) => {
+                if self.remove_breakpoint(*address) {
+                    self.output(format!("Breakpoint at {:#05x} removed", address));
+                } else {
+                    self.output(format!("There is no breakpoint at {:#05x}", address));
                 }
-                0xF => {
-                    match nn {
-                        0x07 => {
-                            let timer_value = *self.delay_timer_arc.lock().unwrap();
-                            self.variable_registers[x] = timer_value;
-                        }
-                        0x15 => {
-                            *self.delay_timer_arc.lock().unwrap() = self.variable_registers[x];
-                        }
-                        0x18 => {
-                            *self.sound_timer_arc.lock().unwrap() = self.variable_registers[x];
-                        }
-                        0x1E => {
-                            // TODO: Ambiguous instruction - provide configuration
-                            if self.index_register == 0xFFF && self.variable_registers[x] > 0 {
-                                self.variable_registers[0xF] = 1;
-                            }
-                            self.index_register += self.variable_registers[x] as u16;
-                        }
-                        0x0A => {
-                            let key_value = loop {
-                                let event =
-                                    self.cpu_io_receiver.recv().expect("CPU IO receive error");
-                                self.process_cpu_io_event(&event);
-
-                                if let CpuIoEvents::KeyPressed(value) = event {
-                                    break value;
-                                }
-                            };
-                            self.variable_registers[x] = key_value;
-                        }
-                        0x29 => {
-                            self.index_register = FONT_START_POS as u16
-                                + (5 * (self.variable_registers[x] as u16 & 0xF));
-                        }
-                        0x33 => {
-                            let value = self.variable_registers[x];
-                            self.memory[self.index_register as usize] = (value / 100) % 10;
-                            self.memory[self.index_register as usize + 1] = (value / 10) % 10;
-                            self.memory[self.index_register as usize + 2] = value % 10;
-                        }
-                        0x55 => {
-                            // TODO: Ambiguous instruction - provide configuration
-                            (0..=x).into_iter().for_each(|r| {
-                                self.memory[self.index_register as usize + r] =
-                                    self.variable_registers[r];
-                            })
-                        }
-                        0x65 => {
-                            // TODO: Ambiguous instruction - provide configuration
-                            (0..=x).into_iter().for_each(|r| {
-                                self.variable_registers[r] =
-                                    self.memory[self.index_register as usize + r];
-                            })
-                        }
-                        _ => {
-                            panic!("{:#06x} is not a valid 0xF instruction.", instruction);
-                        }
-                    }
+            }
+            CpuIoEvents::Rewind(rewinding) => {
+                self.rewinding = *rewinding;
+            }
+            CpuIoEvents::SetSpeedMultiplier(speed_multiplier) => {
+                self.speed_multiplier = speed_multiplier.max(0f32);
+            }
+            CpuIoEvents::TogglePause => {
+                self.set_run_state(match self.run_state {
+                    RunState::Running => RunState::Paused,
+                    _ => RunState::Running,
+                });
+            }
+            CpuIoEvents::Step => {
+                if self.run_state == RunState::Paused {
+                    self.set_run_state(RunState::Stepping);
                 }
-                _ => {
-                    unreachable!()
+            }
+            CpuIoEvents::AdvanceFrame => {
+                if self.run_state == RunState::Paused {
+                    self.screen_updated = false;
+                    self.set_run_state(RunState::AdvancingFrame);
                 }
             }
+            // handled by run_tick, which stops right away
+            CpuIoEvents::Shutdown => {}
+        }
+    }
 
-            if skip {
-                self.program_counter += 2
-            }
+    fn output(&mut self, text: String) {
+        self.status_sink
+            .report(CpuStatusEvents::Output(text.trim_end().to_string()));
+    }
+
+    // the cpu pauses before executing the instruction at the address
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    // returns whether the breakpoint was set
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    // pauses when the next instruction has a breakpoint, returning whether it did
+    fn check_breakpoint(&mut self) -> bool {
+        let program_counter = self.program_counter;
+        if self.breakpoints.is_empty()
+            || !self.breakpoints.contains(&program_counter)
+            || self.paused_breakpoint == Some(program_counter)
+            || self.waiting_for_key.is_some()
+            || self.exited
+        {
+            return false;
+        }
+
+        let message = format!("breakpoint hit at PC={:#05x}", program_counter);
+        log::info!("{}", message);
+        self.paused_breakpoint = Some(program_counter);
+        self.set_run_state(RunState::Paused);
+        self.status_sink.report(CpuStatusEvents::Paused(message));
+        true
+    }
 
-            let elapsed = start_time.elapsed();
-            if elapsed < duration_per_instruction {
-                std::thread::sleep(duration_per_instruction - elapsed);
+    // returns false for memory outside of the address space
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        match self.watched_value(watchpoint) {
+            Some(value) => {
+                if !self
+                    .watchpoints
+                    .iter()
+                    .any(|(watched, _)| *watched == watchpoint)
+                {
+                    self.watchpoints.push((watchpoint, value));
+                }
+                true
             }
+            None => false,
         }
     }
+
+    // returns whether the watchpoint was set
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints
+            .retain(|(watched, _)| *watched != watchpoint);
+        self.watchpoints.len() != count
+    }
+
+    fn watched_value(&self, watchpoint: Watchpoint) -> Option<u8> {
+        match watchpoint {
+            Watchpoint::Memory(address) => self.memory.get(address).copied(),
+            Watchpoint::Register(x) => self.variable_registers.get(x).copied(),
+        }
+    }
+
+    // after the whole state changed, which no instruction is to blame for
+    fn update_watched_values(&mut self) {
+        for i in 0..self.watchpoints.len() {
+            let (watchpoint, last_value) = self.watchpoints[i];
+            self.watchpoints[i].1 = self.watched_value(watchpoint).unwrap_or(last_value);
+        }
+    }
+
+    // pauses when the instruction at program_counter changed any watched value
+    fn check_watchpoints(&mut self, program_counter: usize) {
+        let mut hits = Vec::new();
+        for i in 0..self.watchpoints.len() {
+            let (watchpoint, last_value) = self.watchpoints[i];
+            let value = self.watched_value(watchpoint).unwrap_or(last_value);
+            if value != last_value {
+                hits.push(format!(
+                    "watch hit: {} {:#04x} -> {:#04x} at PC={:#05x}",
+                    watchpoint, last_value, value, program_counter
+                ));
+                self.watchpoints[i].1 = value;
+            }
+        }
+
+        if !hits.is_empty() {
+            let message = hits.join("\n");
+            log::info!("{}", message);
+            self.set_run_state(RunState::Paused);
+            self.status_sink.report(CpuStatusEvents::Paused(message));
+        }
+    }
+
+    pub fn inspect(&self, inspection: &Inspection) -> String {
+        match inspection {
+            Inspection::Registers => self.dump(),
+            Inspection::Memory(range) => {
+                let start = range.start.min(MEMORY_SIZE);
+                hex_dump(&self.memory, start..range.end.clamp(start, MEMORY_SIZE))
+            }
+            Inspection::Disassembly { address, count } => {
+                let start = address.unwrap_or(self.program_counter).min(MEMORY_SIZE);
+                let end = start
+                    .saturating_add(count.saturating_mul(2))
+                    .min(MEMORY_SIZE);
+                disassemble(&self.memory[start..end], start as u16)
+                    .iter()
+                    .map(|line| {
+                        let marker = if line.address as usize == self.program_counter {
+                            "->"
+                        } else {
+                            "  "
+                        };
+                        let bytes = line
+                            .bytes
+                            .iter()
+                            .map(|byte| format!("{:02X}", byte))
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        format!(
+                            "{} {:03X}: {:<11}  {}\n",
+                            marker, line.address, bytes, line.text
+                        )
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    pub fn dump(&self) -> String {
+        let registers = self
+            .variable_registers
+            .chunks(8)
+            .enumerate()
+            .map(|(chunk, registers)| {
+                registers
+                    .iter()
+                    .enumerate()
+                    .map(|(r, value)| format!("V{:X}: {:#04x}", chunk * 8 + r, value))
+                    .collect::<Vec<_>>()
+                    .join("  ")
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let stack = self
+            .stack
+            .iter()
+            .map(|address| format!("{:#05x}", address))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        format!(
+            "PC: {:#05x}  I: {:#05x}  SP: {}\n\
+             {}\n\
+             Stack: [{}]\n\
+             DT: {:#04x}  ST: {:#04x}\n\
+             Keypad: {:016b}\n\
+             Memory around PC:\n{}\
+             Memory around I:\n{}",
+            self.program_counter,
+            self.index_register,
+            self.stack.len(),
+            registers,
+            stack,
+            *self.delay_timer_arc.lock().unwrap(),
+            *self.sound_timer_arc.lock().unwrap(),
+            self.keypad_state,
+            hex_dump_around(&self.memory, self.program_counter),
+            hex_dump_around(&self.memory, self.index_register as usize),
+        )
+    }
+
+    // everything in dump, along with the error, the last executed instructions and all of memory
+    pub fn crash_dump(&self, error: &ExecError) -> String {
+        let recent_instructions = self
+            .recent_instructions
+            .iter()
+            .map(|(address, instruction)| {
+                format!(
+                    "  {:03X}: {:04X}  {}\n",
+                    address,
+                    instruction,
+                    mnemonic(&Opcode::decode(*instruction)).unwrap_or_else(|| "???".to_string())
+                )
+            })
+            .collect::<String>();
+
+        format!(
+            "Error: {:?}\n\
+             {}\
+             Last executed instructions:\n{}\
+             Memory:\n{}",
+            error,
+            self.dump(),
+            recent_instructions,
+            hex_dump(&self.memory, 0..MEMORY_SIZE),
+        )
+    }
+
+    #[cfg(feature = "std")]
+    pub fn set_crash_dump_path(&mut self, path: PathBuf) {
+        self.crash_dump_path = Some(path);
+    }
+
+    #[cfg(feature = "std")]
+    fn write_crash_dump(&self, error: &ExecError) {
+        if let Some(path) = &self.crash_dump_path {
+            match std::fs::write(path, self.crash_dump(error)) {
+                Ok(_) => log::error!("Wrote crash dump to {:?}", path),
+                Err(err) => log::error!("Cannot write crash dump to {:?}: {:?}", path, err),
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> CpuState {
+        CpuState {
+            memory: self.memory.to_vec(),
+            screen_hires: self.screen_pixels.hires,
+            screen_planes: self
+                .screen_pixels
+                .planes
+                .iter()
+                .map(|rows| rows.to_vec())
+                .collect(),
+            selected_planes: self.selected_planes,
+            keypad_state: self.keypad_state,
+            delay_timer: *self.delay_timer_arc.lock().unwrap(),
+            sound_timer: *self.sound_timer_arc.lock().unwrap(),
+            program_counter: self.program_counter as u16,
+            index_register: self.index_register,
+            stack: self.stack.clone(),
+            variable_registers: self.variable_registers,
+            waiting_for_key: self.waiting_for_key.map(|x| x as u8),
+            audio_pattern: *self.audio_pattern_arc.lock().unwrap(),
+            rpl_flags: self.rpl_flags,
+        }
+    }
+
+    pub fn load_state(&mut self, state: CpuState) -> Result<(), LoadStateError> {
+        if state.memory.len() != self.memory.len() {
+            return Err(LoadStateError::InvalidMemorySize {
+                actual: state.memory.len(),
+                expected: self.memory.len(),
+            });
+        }
+        if state.screen_planes.len() != PLANE_COUNT {
+            return Err(LoadStateError::InvalidPlaneCount {
+                actual: state.screen_planes.len(),
+                expected: PLANE_COUNT,
+            });
+        }
+        if let Some(rows) = state
+            .screen_planes
+            .iter()
+            .find(|rows| rows.len() != HIRES_SCREEN_HEIGHT)
+        {
+            return Err(LoadStateError::InvalidScreenSize {
+                actual: rows.len(),
+                expected: HIRES_SCREEN_HEIGHT,
+            });
+        }
+
+        self.memory.copy_from_slice(&state.memory);
+        self.screen_pixels.hires = state.screen_hires;
+        self.screen_pixels
+            .planes
+            .iter_mut()
+            .zip(&state.screen_planes)
+            .for_each(|(rows, state_rows)| rows.copy_from_slice(state_rows));
+        self.selected_planes = state.selected_planes;
+        self.keypad_state = state.keypad_state;
+        // the timer threads hold on to the same arcs, so write through them instead of
+        // replacing them
+        *self.delay_timer_arc.lock().unwrap() = state.delay_timer;
+        *self.sound_timer_arc.lock().unwrap() = state.sound_timer;
+
+        self.program_counter = state.program_counter as usize;
+        self.index_register = state.index_register;
+        self.stack = state.stack;
+        self.variable_registers = state.variable_registers;
+        self.waiting_for_key = state.waiting_for_key.map(|x| x as usize & 0xF);
+        *self.audio_pattern_arc.lock().unwrap() = state.audio_pattern;
+        self.rpl_flags = state.rpl_flags;
+        self.update_watched_values();
+
+        self.send_screen_update();
+        Ok(())
+    }
+
+    // snapshots are only taken once per tick, so that rewinding does not slow down emulation
+    fn push_rewind_snapshot(&mut self) {
+        if self.rewind_capacity == 0 {
+            return;
+        }
+        if self.rewind_buffer.len() == self.rewind_capacity {
+            self.rewind_buffer.pop_front();
+        }
+        self.rewind_buffer.push_back(self.snapshot());
+    }
+
+    // the oldest snapshot is never popped, so rewinding past the beginning stays there
+    fn rewind(&mut self) {
+        let state = if self.rewind_buffer.len() > 1 {
+            self.rewind_buffer.pop_back()
+        } else {
+            self.rewind_buffer.front().cloned()
+        };
+
+        if let Some(state) = state {
+            self.load_state(state)
+                .expect("Rewind snapshots should always be valid");
+        }
+    }
+
+    // keeps the cpu running at its normal speed until it stops, sleeping between ticks
+    #[cfg(feature = "std")]
+    pub fn run(&mut self) {
+        let tick_duration = Duration::from_secs_f64(1f64 / TICKS_PER_SECOND as f64);
+
+        let mut next_tick = Instant::now();
+
+        let mut report_start = Instant::now();
+        let mut report_instructions_executed = 0;
+
+        self.push_rewind_snapshot();
+        if let Some(state) = self.rewind_buffer.front() {
+            let snapshot_size = std::mem::size_of::<CpuState>()
+                + state.memory.len()
+                + state.screen_planes.len() * HIRES_SCREEN_HEIGHT * std::mem::size_of::<u128>()
+                + state.stack.capacity() * std::mem::size_of::<u16>();
+            log::info!(
+                "Rewind buffer holds up to {} snapshots of ~{} bytes each (~{} KiB in total)",
+                self.rewind_capacity,
+                snapshot_size,
+                self.rewind_capacity * snapshot_size / 1024
+            );
+        }
+
+        while self.run_tick() {
+            self.latest_states
+                .iter()
+                .for_each(|latest_state| latest_state.publish(|| self.snapshot()));
+            if self.run_state == RunState::Running && !self.rewinding {
+                let report_elapsed = report_start.elapsed();
+                if report_elapsed >= Duration::from_secs(1) {
+                    log::debug!(
+                        "Achieved {:.1} instructions per second (configured: {})",
+                        (self.instructions_executed - report_instructions_executed) as f64
+                            / report_elapsed.as_secs_f64(),
+                        INSTRUCTIONS_PER_SECOND as f32 * self.speed_multiplier
+                    );
+                    report_start = Instant::now();
+                    report_instructions_executed = self.instructions_executed;
+                }
+            } else {
+                report_start = Instant::now();
+                report_instructions_executed = self.instructions_executed;
+            }
+
+            // sleep until an absolute tick boundary so that oversleeping does not accumulate
+            next_tick += tick_duration;
+            let now = Instant::now();
+            if next_tick > now {
+                std::thread::sleep(next_tick - now);
+            } else if now - next_tick > tick_duration {
+                // we fell far behind (e.g. waiting for a key press), don't try to catch up
+                next_tick = now;
+            }
+        }
+    }
+
+    // handles the pending events, then executes the instructions due in a single tick, for
+    // frontends that cannot give the cpu a thread of its own. The timers are left to the caller.
+    // Returns false once the cpu stopped.
+    pub fn run_tick(&mut self) -> bool {
+        loop {
+            match self.key_source.poll() {
+                Ok(CpuIoEvents::Shutdown) => return false,
+                Ok(event) => self.process_cpu_io_event(&event),
+                Err(PollError::Empty) => break,
+                // the application moved on to another program
+                Err(PollError::Disconnected) => return false,
+            }
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(tracer) = &mut self.tracer {
+            tracer.timers(
+                *self.delay_timer_arc.lock().unwrap(),
+                *self.sound_timer_arc.lock().unwrap(),
+            );
+        }
+
+        if self.run_state == RunState::Running && !self.rewinding {
+            // INSTRUCTIONS_PER_SECOND is not always divisible by TICKS_PER_SECOND, so the
+            // fraction of an instruction left over is carried to the next tick
+            self.instruction_budget += INSTRUCTIONS_PER_SECOND as f64 / TICKS_PER_SECOND as f64
+                * self.speed_multiplier as f64;
+            while self.instruction_budget >= 1f64 {
+                if self.check_breakpoint() {
+                    self.instruction_budget = 0f64;
+                    break;
+                }
+                if let Err(err) = self.step() {
+                    self.report_exec_error(err);
+                    return false;
+                }
+                self.instruction_budget -= 1f64;
+                // such as for a watchpoint
+                if self.run_state != RunState::Running {
+                    self.instruction_budget = 0f64;
+                    break;
+                }
+            }
+            self.instructions_executed_arc
+                .store(self.instructions_executed, atomic::Ordering::Relaxed);
+            self.push_rewind_snapshot();
+        } else {
+            match self.run_state {
+                RunState::Running => self.rewind(),
+                RunState::Paused => {}
+                RunState::Stepping => {
+                    if let Some(x) = self.waiting_for_key {
+                        self.output(format!("Waiting for a key press to store in V{}", x));
+                    } else {
+                        let program_counter = self.program_counter;
+                        match self.execute_traced_instruction() {
+                            Ok(line) => {
+                                #[cfg(feature = "std")]
+                                if let Some(tracer) = &mut self.tracer {
+                                    tracer.instruction(&line);
+                                }
+                                self.output(line);
+                                self.check_watchpoints(program_counter);
+                            }
+                            Err(err) => {
+                                self.report_exec_error(err);
+                                return false;
+                            }
+                        }
+                    }
+                    self.set_run_state(RunState::Paused);
+                }
+                RunState::AdvancingFrame => {
+                    for _ in 0..(INSTRUCTIONS_PER_SECOND / TICKS_PER_SECOND) {
+                        if self.check_breakpoint() {
+                            break;
+                        }
+                        if let Err(err) = self.execute_next_instruction() {
+                            self.report_exec_error(err);
+                            return false;
+                        }
+                        if self.screen_updated {
+                            self.set_run_state(RunState::Paused);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(tracer) = &mut self.tracer {
+            tracer.flush();
+        }
+
+        if self.exited {
+            self.status_sink.report(CpuStatusEvents::Exited);
+            return false;
+        }
+
+        true
+    }
+
+    // runs the given number of instructions as fast as possible, counting the timers down as if
+    // INSTRUCTIONS_PER_SECOND were executed each second
+    pub fn run_headless(&mut self, cycles: u64) -> Result<(), RunHeadlessError> {
+        self.run_headless_with(cycles, |_, _| {})
+    }
+
+    // like run_headless, also handing the screen to on_tick after every tick, along with the
+    // number of ticks so far
+    pub fn run_headless_with(
+        &mut self,
+        cycles: u64,
+        mut on_tick: impl FnMut(u64, &CpuScreenMem),
+    ) -> Result<(), RunHeadlessError> {
+        for cycle in 1..=cycles {
+            if let Err(err) = self.step() {
+                #[cfg(feature = "std")]
+                self.write_crash_dump(&err);
+                return Err(RunHeadlessError::Exec(err));
+            }
+            if self.exited {
+                break;
+            }
+            if self.waiting_for_key.is_some() {
+                return Err(RunHeadlessError::WaitingForKey {
+                    program_counter: self.program_counter - 2,
+                });
+            }
+
+            let ticks = cycle * TICKS_PER_SECOND as u64 / INSTRUCTIONS_PER_SECOND as u64;
+            let previous_ticks =
+                (cycle - 1) * TICKS_PER_SECOND as u64 / INSTRUCTIONS_PER_SECOND as u64;
+            if ticks != previous_ticks {
+                [&self.delay_timer_arc, &self.sound_timer_arc]
+                    .iter()
+                    .for_each(|timer| {
+                        let mut value = timer.lock().unwrap();
+                        *value = value.saturating_sub(1);
+                    });
+                on_tick(ticks, &self.screen_pixels);
+            }
+        }
+
+        Ok(())
+    }
+
+    // executes a single instruction right away, without waiting for its tick or handling any
+    // events. Nothing happens while the program waits for a key or after it exited. The timers
+    // are left to the caller.
+    pub fn step(&mut self) -> Result<(), ExecError> {
+        let program_counter = self.program_counter;
+        self.execute_next_instruction()?;
+        self.instructions_executed += 1;
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(program_counter);
+        }
+        Ok(())
+    }
+
+    fn report_exec_error(&mut self, error: ExecError) {
+        log::error!("Cpu stopped: {:?}", error);
+        #[cfg(feature = "std")]
+        self.write_crash_dump(&error);
+        self.status_sink.report(CpuStatusEvents::Crashed(error));
+    }
+
+    fn execute_next_instruction(&mut self) -> Result<(), ExecError> {
+        // formatting the trace line is skipped unless someone reads it
+        #[cfg(feature = "std")]
+        let traced = self.tracer.is_some() || log::log_enabled!(log::Level::Trace);
+        #[cfg(not(feature = "std"))]
+        let traced = log::log_enabled!(log::Level::Trace);
+        // nothing gets executed while halted, so there is nothing to trace either
+        if traced && self.waiting_for_key.is_none() && !self.exited {
+            let line = self.execute_traced_instruction()?;
+            log::trace!("{}", line);
+            #[cfg(feature = "std")]
+            if let Some(tracer) = &mut self.tracer {
+                tracer.instruction(&line);
+            }
+            Ok(())
+        } else {
+            self.execute_instruction()
+        }
+    }
+
+    fn execute_traced_instruction(&mut self) -> Result<String, ExecError> {
+        let program_counter = self.program_counter;
+        let opcode = Opcode::decode(self.fetch()?);
+        let variable_registers = self.variable_registers;
+        let index_register = self.index_register;
+
+        self.execute_instruction()?;
+
+        let mut deltas = (0..variable_registers.len())
+            .filter(|r| variable_registers[*r] != self.variable_registers[*r])
+            .map(|r| {
+                format!(
+                    "V{}: {:#04x} -> {:#04x}",
+                    r, variable_registers[r], self.variable_registers[r]
+                )
+            })
+            .collect::<Vec<_>>();
+        if index_register != self.index_register {
+            deltas.push(format!(
+                "I: {:#05x} -> {:#05x}",
+                index_register, self.index_register
+            ));
+        }
+
+        Ok(format!(
+            "{:03X}: {:04X}  {:<16} {}",
+            program_counter,
+            opcode.raw,
+            mnemonic(&opcode).unwrap_or_else(|| "???".to_string()),
+            deltas.join(", ")
+        )
+        .trim_end()
+        .to_string())
+    }
+
+    fn fetch(&self) -> Result<u16, ExecError> {
+        let program_counter = self.program_counter;
+        if program_counter + 1 >= MEMORY_SIZE {
+            return Err(ExecError::ProgramCounterOutOfBounds { program_counter });
+        }
+
+        Ok(((self.memory[program_counter] as u16) << 8) + self.memory[program_counter + 1] as u16)
+    }
+
+    // F000 NNNN is the only instruction with four bytes
+    fn instruction_length(&self, address: usize) -> usize {
+        if self.xochip && self.memory.get(address..(address + 2)) == Some(&[0xF0, 0x00]) {
+            4
+        } else {
+            2
+        }
+    }
+
+    fn jump_target(&self, program_counter: usize, target: usize) -> Result<usize, ExecError> {
+        if self.strict_jumps && target < PROGRAM_INIT_LOAD_POS {
+            return Err(ExecError::JumpBelowProgram {
+                program_counter,
+                target,
+            });
+        }
+
+        Ok(target)
+    }
+
+    fn memory_range(
+        program_counter: usize,
+        address: usize,
+        length: usize,
+    ) -> Result<Range<usize>, ExecError> {
+        if address + length > MEMORY_SIZE {
+            return Err(ExecError::MemoryOutOfBounds {
+                program_counter,
+                address,
+                length,
+            });
+        }
+
+        Ok(address..(address + length))
+    }
+
+    fn scroll_amount(&self, amount: usize) -> usize {
+        if self.quirks.lores_half_scroll && !self.screen_pixels.hires {
+            amount / 2
+        } else {
+            amount
+        }
+    }
+
+    fn execute_instruction(&mut self) -> Result<(), ExecError> {
+        if self.waiting_for_key.is_some() || self.exited {
+            return Ok(());
+        }
+
+        let program_counter = self.program_counter;
+        let instruction = self.fetch()?;
+        self.program_counter += 2;
+        // left behind, so it pauses again the next time that it is reached
+        self.paused_breakpoint = None;
+
+        if self.recent_instructions.len() == RECENT_INSTRUCTION_COUNT {
+            self.recent_instructions.pop_front();
+        }
+        self.recent_instructions
+            .push_back((program_counter, instruction));
+
+        let Opcode {
+            op,
+            x,
+            y,
+            n,
+            nn,
+            nnn,
+            ..
+        } = Opcode::decode(instruction);
+
+        let mut skip = false;
+
+        match op {
+            0x0 => {
+                if nnn == 0xE0 {
+                    self.screen_pixels.clear(self.selected_planes);
+                    self.send_screen_update();
+                } else if nnn & 0xFF0 == 0x0C0 {
+                    let amount = self.scroll_amount(n as usize);
+                    self.screen_pixels.scroll_down(self.selected_planes, amount);
+                    self.send_screen_update();
+                } else if self.xochip && nnn & 0xFF0 == 0x0D0 {
+                    let amount = self.scroll_amount(n as usize);
+                    self.screen_pixels.scroll_up(self.selected_planes, amount);
+                    self.send_screen_update();
+                } else if nnn == 0xFB {
+                    let amount = self.scroll_amount(4);
+                    self.screen_pixels
+                        .scroll_right(self.selected_planes, amount);
+                    self.send_screen_update();
+                } else if nnn == 0xFC {
+                    let amount = self.scroll_amount(4);
+                    self.screen_pixels.scroll_left(self.selected_planes, amount);
+                    self.send_screen_update();
+                } else if nnn == 0xFD {
+                    self.exited = true;
+                } else if nnn == 0xFE || nnn == 0xFF {
+                    // switching the resolution clears every plane, like SUPER-CHIP does
+                    self.screen_pixels = CpuScreenMem::new(nnn == 0xFF);
+                    self.send_screen_update();
+                } else if nnn == 0xEE {
+                    self.program_counter = self
+                        .stack
+                        .pop()
+                        .ok_or(ExecError::StackUnderflow { program_counter })?
+                        as usize;
+                } else {
+                    return Err(ExecError::InvalidInstruction {
+                        program_counter,
+                        instruction,
+                    });
+                }
+            }
+            0x1 => {
+                self.program_counter = self.jump_target(program_counter, nnn as usize)?;
+            }
+            0x2 => {
+                if self.stack.len() >= self.max_stack_depth {
+                    return Err(ExecError::StackOverflow {
+                        program_counter,
+                        max_depth: self.max_stack_depth,
+                    });
+                }
+                let target = self.jump_target(program_counter, nnn as usize)?;
+                self.stack.push(self.program_counter as u16);
+                self.program_counter = target;
+            }
+            0x3 => {
+                if self.variable_registers[x] == nn {
+                    skip = true;
+                }
+            }
+            0x4 => {
+                if self.variable_registers[x] != nn {
+                    skip = true;
+                }
+            }
+            0x5 => {
+                if self.variable_registers[x] == self.variable_registers[y] {
+                    skip = true;
+                }
+            }
+            0x6 => {
+                self.variable_registers[x] = nn;
+            }
+            0x7 => {
+                self.variable_registers[x] = self.variable_registers[x].wrapping_add(nn);
+            }
+            0x8 => match n {
+                0x0 => {
+                    self.variable_registers[x] = self.variable_registers[y];
+                }
+                0x1 => {
+                    self.variable_registers[x] |= self.variable_registers[y];
+                }
+                0x2 => {
+                    self.variable_registers[x] &= self.variable_registers[y];
+                }
+                0x3 => {
+                    self.variable_registers[x] ^= self.variable_registers[y];
+                }
+                // The flag is written after the result, so that it wins when X is F. Subtraction
+                // sets it when there is no borrow, which includes equal values.
+                0x4 => {
+                    let (final_value, overflowed) =
+                        self.variable_registers[x].overflowing_add(self.variable_registers[y]);
+                    self.variable_registers[x] = final_value;
+                    self.variable_registers[0xF] = overflowed.into();
+                }
+                0x5 => {
+                    let (vx, vy) = (self.variable_registers[x], self.variable_registers[y]);
+                    self.variable_registers[x] = vx.wrapping_sub(vy);
+                    self.variable_registers[0xF] = (vx >= vy).into();
+                }
+                0x6 => {
+                    // TODO: Ambiguous instruction - provide configuration
+                    let vx = self.variable_registers[x];
+                    self.variable_registers[x] = vx >> 1;
+                    self.variable_registers[0xF] = vx & 1;
+                }
+                0x7 => {
+                    let (vx, vy) = (self.variable_registers[x], self.variable_registers[y]);
+                    self.variable_registers[x] = vy.wrapping_sub(vx);
+                    self.variable_registers[0xF] = (vy >= vx).into();
+                }
+                0xE => {
+                    // TODO: Ambiguous instruction - provide configuration
+                    let vx = self.variable_registers[x];
+                    self.variable_registers[x] = vx << 1;
+                    self.variable_registers[0xF] = vx >> 7;
+                }
+                _ => {
+                    return Err(ExecError::InvalidInstruction {
+                        program_counter,
+                        instruction,
+                    });
+                }
+            },
+            0x9 => {
+                if self.variable_registers[x] != self.variable_registers[y] {
+                    skip = true;
+                }
+            }
+            0xA => {
+                self.index_register = nnn;
+            }
+            0xB => {
+                // TODO: Ambiguous instruction - provide configuration
+                self.program_counter = self.jump_target(
+                    program_counter,
+                    nnn as usize + self.variable_registers[0x0] as usize,
+                )?;
+            }
+            0xC => {
+                self.variable_registers[x] = self.rng.gen::<u8>() & nn;
+            }
+            0xD => {
+                let width = self.screen_pixels.width();
+                let height = self.screen_pixels.height();
+                let row_mask = self.screen_pixels.row_mask();
+
+                let x_start = self.variable_registers[x] as usize % width;
+                let y_start = self.variable_registers[y] as usize % height;
+                let clip_sprites = self.quirks.clip_sprites;
+
+                // DXY0 draws a 16x16 sprite, two bytes per row
+                let (sprite_width, sprite_height) = if n == 0 { (16, 16) } else { (8, n as usize) };
+                let bytes_per_row = sprite_width / 8;
+
+                // position of a sprite row that starts at the leftmost pixel
+                let leftmost_shift = u128::BITS as usize - sprite_width;
+
+                // with several planes selected, the sprite for each plane follows the previous one
+                let sprite_size = sprite_height * bytes_per_row;
+                let selected_plane_count = self.selected_planes.count_ones() as usize;
+                let mut sprite_address = Self::memory_range(
+                    program_counter,
+                    self.index_register as usize,
+                    selected_plane_count * sprite_size,
+                )?
+                .start;
+                let mut collided_rows = 0u16;
+                (0..PLANE_COUNT)
+                    .filter(|plane| self.selected_planes & (1 << plane) != 0)
+                    .for_each(|plane| {
+                        (0..sprite_height)
+                            .filter(|row| !clip_sprites || y_start + row < height)
+                            .for_each(|row| {
+                                let y = (y_start + row) % height;
+                                let row_address = sprite_address + row * bytes_per_row;
+                                let sprite_row = self.memory
+                                    [row_address..(row_address + bytes_per_row)]
+                                    .iter()
+                                    .fold(0u128, |bits, byte| (bits << 8) | *byte as u128);
+                                let mask = if clip_sprites {
+                                    match x_start.cmp(&leftmost_shift) {
+                                        Ordering::Equal => sprite_row,
+                                        Ordering::Less => sprite_row << (leftmost_shift - x_start),
+                                        Ordering::Greater => {
+                                            sprite_row >> (x_start - leftmost_shift)
+                                        }
+                                    }
+                                } else {
+                                    // rotates the sprite within the visible part of the row, so
+                                    // that what goes past the right edge comes back on the left
+                                    let leftmost_row = sprite_row << leftmost_shift;
+                                    (leftmost_row >> x_start)
+                                        | leftmost_row
+                                            .checked_shl((width - x_start) as u32)
+                                            .unwrap_or(0)
+                                } & row_mask;
+                                if (mask & self.screen_pixels.planes[plane][y]) != 0 {
+                                    collided_rows |= 1 << row;
+                                }
+                                self.screen_pixels.planes[plane][y] ^= mask;
+                            });
+                        sprite_address += sprite_size;
+                    });
+
+                self.variable_registers[0xF] =
+                    if self.quirks.hires_collision_rows && self.screen_pixels.hires {
+                        collided_rows.count_ones() as u8
+                    } else {
+                        (collided_rows != 0).into()
+                    };
+                self.send_screen_update();
+            }
+            0xE => {
+                if nn == 0x9E {
+                    skip =
+                        self.keypad_state & get_keypad_state_mask(self.variable_registers[x]) != 0;
+                } else if nn == 0xA1 {
+                    skip =
+                        self.keypad_state & get_keypad_state_mask(self.variable_registers[x]) == 0;
+                } else {
+                    return Err(ExecError::InvalidInstruction {
+                        program_counter,
+                        instruction,
+                    });
+                }
+            }
+            0xF => {
+                match nn {
+                    0x07 => {
+                        let timer_value = *self.delay_timer_arc.lock().unwrap();
+                        self.variable_registers[x] = timer_value;
+                    }
+                    0x15 => {
+                        *self.delay_timer_arc.lock().unwrap() = self.variable_registers[x];
+                    }
+                    0x18 => {
+                        *self.sound_timer_arc.lock().unwrap() = self.variable_registers[x];
+                    }
+                    0x1E => {
+                        // TODO: Ambiguous instruction - provide configuration
+                        if self.index_register == 0xFFF && self.variable_registers[x] > 0 {
+                            self.variable_registers[0xF] = 1;
+                        }
+                        // F000 NNNN can leave I anywhere in 16 bits
+                        self.index_register = self
+                            .index_register
+                            .wrapping_add(self.variable_registers[x] as u16);
+                    }
+                    0x00 if self.xochip && x == 0 => {
+                        // F000 NNNN, which can address more memory than there is here
+                        let address = self.program_counter;
+                        if address + 1 >= MEMORY_SIZE {
+                            return Err(ExecError::ProgramCounterOutOfBounds { program_counter });
+                        }
+                        self.index_register =
+                            ((self.memory[address] as u16) << 8) + self.memory[address + 1] as u16;
+                        self.program_counter += 2;
+                    }
+                    0x01 if self.xochip => {
+                        // FN01, N being the mask of the planes to draw to
+                        self.selected_planes = x as u8 & ((1 << PLANE_COUNT) - 1);
+                    }
+                    0x02 if self.xochip && x == 0 => {
+                        let range = Self::memory_range(
+                            program_counter,
+                            self.index_register as usize,
+                            AUDIO_PATTERN_SIZE,
+                        )?;
+                        self.audio_pattern_arc
+                            .lock()
+                            .unwrap()
+                            .get_or_insert_with(AudioPattern::default)
+                            .bits
+                            .copy_from_slice(&self.memory[range]);
+                    }
+                    0x3A if self.xochip => {
+                        self.audio_pattern_arc
+                            .lock()
+                            .unwrap()
+                            .get_or_insert_with(AudioPattern::default)
+                            .pitch = self.variable_registers[x];
+                    }
+                    0x0A => {
+                        // the register is filled in by the next key press event
+                        self.waiting_for_key = Some(x);
+                    }
+                    0x29 => {
+                        self.index_register =
+                            FONT_START_POS as u16 + (5 * (self.variable_registers[x] as u16 & 0xF));
+                    }
+                    0x30 => {
+                        self.index_register = BIG_FONT_START_POS as u16
+                            + (10 * (self.variable_registers[x] as u16 & 0xF));
+                    }
+                    0x33 => {
+                        let value = self.variable_registers[x];
+                        let range =
+                            Self::memory_range(program_counter, self.index_register as usize, 3)?;
+                        self.memory[range].copy_from_slice(&[
+                            (value / 100) % 10,
+                            (value / 10) % 10,
+                            value % 10,
+                        ]);
+                    }
+                    0x55 => {
+                        // TODO: Ambiguous instruction - provide configuration
+                        let range = Self::memory_range(
+                            program_counter,
+                            self.index_register as usize,
+                            x + 1,
+                        )?;
+                        self.memory[range].copy_from_slice(&self.variable_registers[..=x]);
+                    }
+                    0x65 => {
+                        // TODO: Ambiguous instruction - provide configuration
+                        let range = Self::memory_range(
+                            program_counter,
+                            self.index_register as usize,
+                            x + 1,
+                        )?;
+                        self.variable_registers[..=x].copy_from_slice(&self.memory[range]);
+                    }
+                    0x75 => {
+                        // SUPER-CHIP has no flags for V8 and above, so they are left out
+                        let count = (x + 1).min(self.rpl_flag_count());
+                        if self.rpl_flags[..count] != self.variable_registers[..count] {
+                            self.rpl_flags[..count]
+                                .copy_from_slice(&self.variable_registers[..count]);
+                            #[cfg(feature = "std")]
+                            self.write_rpl_flags();
+                        }
+                    }
+                    0x85 => {
+                        let count = (x + 1).min(self.rpl_flag_count());
+                        self.variable_registers[..count].copy_from_slice(&self.rpl_flags[..count]);
+                    }
+                    _ => {
+                        return Err(ExecError::InvalidInstruction {
+                            program_counter,
+                            instruction,
+                        });
+                    }
+                }
+            }
+            _ => {
+                unreachable!()
+            }
+        }
+
+        if skip {
+            self.program_counter += self.instruction_length(self.program_counter);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::NoFrontend;
+
+    // counts up in V0 and draws its digit one pixel further down and right each time
+    const COUNTER: [u8; 10] = [
+        0x70, 0x01, // 200: V0 += 1
+        0xF0, 0x29, // 202: I = digit V0
+        0xD1, 0x15, // 204: draw 8x5 at V1, V1
+        0x71, 0x01, // 206: V1 += 1
+        0x12, 0x00, // 208: jump to 200
+    ];
+
+    fn cpu_with(program: &[u8], config: CpuConfig) -> Cpu {
+        Cpu::new(
+            program.to_vec(),
+            config,
+            NoFrontend,
+            NoFrontend,
+            NoFrontend,
+            Arc::new(Mutex::new(0)),
+            Arc::new(Mutex::new(0)),
+            Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap()
+    }
+
+    fn cpu(program: &[u8]) -> Cpu {
+        cpu_with(program, CpuConfig::default())
+    }
+
+    fn steps(cpu: &mut Cpu, count: usize) {
+        (0..count).for_each(|_| cpu.step().unwrap());
+    }
+
+    fn lit_pixels(screen: &CpuScreenMem) -> Vec<(usize, usize)> {
+        (0..screen.height())
+            .flat_map(|y| (0..screen.width()).map(move |x| (x, y)))
+            .filter(|(x, y)| screen.pixel(*x, *y) != 0)
+            .collect()
+    }
+
+    #[test]
+    fn load_state_restores_snapshot() {
+        let mut cpu = cpu(&COUNTER);
+        steps(&mut cpu, 50);
+        let state = cpu.snapshot();
+
+        steps(&mut cpu, 50);
+        assert_ne!(cpu.snapshot(), state);
+        cpu.load_state(state.clone()).unwrap();
+        assert_eq!(cpu.snapshot(), state);
+
+        // and it carries on from there like the first time
+        steps(&mut cpu, 50);
+        let mut other = self::cpu(&COUNTER);
+        steps(&mut other, 100);
+        assert_eq!(cpu.snapshot(), other.snapshot());
+    }
+
+    #[test]
+    fn load_state_rejects_wrong_memory_size() {
+        let mut cpu = cpu(&COUNTER);
+        let mut state = cpu.snapshot();
+        state.memory.pop();
+        assert!(matches!(
+            cpu.load_state(state),
+            Err(LoadStateError::InvalidMemorySize { .. })
+        ));
+    }
+
+    #[test]
+    fn rewind_restores_snapshots_newest_first() {
+        let mut cpu = cpu_with(
+            &COUNTER,
+            CpuConfig {
+                rewind_capacity: 4,
+                ..CpuConfig::default()
+            },
+        );
+        let mut states = Vec::new();
+        (0..3).for_each(|_| {
+            steps(&mut cpu, 5);
+            cpu.push_rewind_snapshot();
+            states.push(cpu.snapshot());
+        });
+        steps(&mut cpu, 5);
+
+        cpu.rewind();
+        assert_eq!(cpu.snapshot(), states[2]);
+        cpu.rewind();
+        assert_eq!(cpu.snapshot(), states[1]);
+        // two times around the loop
+        assert_eq!(cpu.variable_register(0), 2);
+        assert_eq!(cpu.program_counter(), 0x200);
+    }
+
+    #[test]
+    fn reset_matches_fresh_cpu() {
+        let mut cpu = cpu(&COUNTER);
+        steps(&mut cpu, 300);
+        *cpu.delay_timer_arc.lock().unwrap() = 10;
+        cpu.reset();
+        assert_eq!(cpu.snapshot(), self::cpu(&COUNTER).snapshot());
+    }
+
+    #[test]
+    fn rewind_stays_at_oldest_snapshot_once_full() {
+        let mut cpu = cpu_with(
+            &COUNTER,
+            CpuConfig {
+                rewind_capacity: 3,
+                ..CpuConfig::default()
+            },
+        );
+        let mut states = Vec::new();
+        (0..5).for_each(|_| {
+            steps(&mut cpu, 5);
+            cpu.push_rewind_snapshot();
+            states.push(cpu.snapshot());
+        });
+        assert_eq!(cpu.rewind_buffer.len(), 3);
+
+        // the first two were dropped to make room
+        (0..5).for_each(|_| cpu.rewind());
+        assert_eq!(cpu.snapshot(), states[2]);
+        assert_eq!(cpu.rewind_buffer.len(), 1);
+    }
+
+    #[test]
+    fn scrolls_down_right_and_left() {
+        let mut cpu = cpu(&[
+            0x00, 0xFF, // 200: hires
+            0xA2, 0x0C, // 202: I = 20C
+            0xD0, 0x01, // 204: draw 8x1 at V0, V0
+            0x00, 0xC3, // 206: scroll down 3
+            0x00, 0xFB, // 208: scroll right 4
+            0x00, 0xFC, // 20A: scroll left 4
+            0x80, 0x00, // 20C: a single pixel
+        ]);
+        steps(&mut cpu, 3);
+        assert_eq!(lit_pixels(cpu.screen()), [(0, 0)]);
+        steps(&mut cpu, 1);
+        assert_eq!(lit_pixels(cpu.screen()), [(0, 3)]);
+        steps(&mut cpu, 1);
+        assert_eq!(lit_pixels(cpu.screen()), [(4, 3)]);
+        steps(&mut cpu, 1);
+        assert_eq!(lit_pixels(cpu.screen()), [(0, 3)]);
+    }
+
+    #[test]
+    fn scrolling_drops_pixels_past_the_edge() {
+        let mut cpu = cpu(&[
+            0x00, 0xFF, // 200: hires
+            0x60, 0x7E, // 202: V0 = 126
+            0x61, 0x3E, // 204: V1 = 62
+            0xA2, 0x10, // 206: I = 210
+            0xD0, 0x12, // 208: draw 8x2 at V0, V1
+            0x00, 0xC1, // 20A: scroll down 1
+            0x00, 0xFB, // 20C: scroll right 4
+            0x12, 0x0E, // 20E: jump to 20E
+            0xC0, 0xC0, // 210: a 2x2 square
+        ]);
+        steps(&mut cpu, 5);
+        assert_eq!(
+            lit_pixels(cpu.screen()),
+            [(126, 62), (127, 62), (126, 63), (127, 63)]
+        );
+        steps(&mut cpu, 1);
+        assert_eq!(lit_pixels(cpu.screen()), [(126, 63), (127, 63)]);
+        steps(&mut cpu, 1);
+        assert!(lit_pixels(cpu.screen()).is_empty());
+    }
+
+    // a filled 16x16 sprite, drawn again 8 rows further down
+    fn big_sprite_program() -> Vec<u8> {
+        let mut program = vec![
+            0x00, 0xFF, // 200: hires
+            0xA2, 0x10, // 202: I = 210
+            0xD0, 0x00, // 204: draw 16x16 at V0, V0
+            0x61, 0x08, // 206: V1 = 8
+            0xD0, 0x10, // 208: draw 16x16 at V0, V1
+            0x12, 0x0A, // 20A: jump to 20A
+            0x00, 0x00, 0x00, 0x00, // 20C: padding
+        ];
+        program.extend([0xFF; 32]); // 210: the sprite
+        program
+    }
+
+    #[test]
+    fn draws_16x16_sprite_and_counts_collided_rows_in_hires() {
+        let mut cpu = cpu_with(
+            &big_sprite_program(),
+            CpuConfig {
+                quirks: Quirks {
+                    hires_collision_rows: true,
+                    ..Quirks::default()
+                },
+                ..CpuConfig::default()
+            },
+        );
+        steps(&mut cpu, 3);
+        assert_eq!(lit_pixels(cpu.screen()).len(), 16 * 16);
+        assert_eq!(cpu.variable_register(0xF), 0);
+
+        steps(&mut cpu, 2);
+        // the first 8 rows of the second sprite erased the last 8 of the first one
+        let lit = lit_pixels(cpu.screen());
+        assert_eq!(lit.len(), 16 * 16);
+        assert!(lit
+            .iter()
+            .all(|(x, y)| *x < 16 && (*y < 8 || (16..24).contains(y))));
+        assert_eq!(cpu.variable_register(0xF), 8);
+    }
+
+    #[test]
+    fn sprite_past_end_of_memory_is_an_error() {
+        let mut cpu = cpu(&[
+            0xAF, 0xF8, // 200: I = FF8
+            0xD0, 0x08, // 202: draw 8x8 at V0, V0, the last 8 bytes
+            0xD0, 0x09, // 204: draw 8x9 at V0, V0, one byte too many
+        ]);
+        steps(&mut cpu, 2);
+        assert!(matches!(
+            cpu.step(),
+            Err(ExecError::MemoryOutOfBounds {
+                program_counter: 0x204,
+                address: 0xFF8,
+                length: 9,
+            })
+        ));
+    }
+
+    #[test]
+    fn loads_audio_pattern_up_to_end_of_memory() {
+        let xochip = CpuConfig {
+            xochip: true,
+            ..CpuConfig::default()
+        };
+        let mut cpu = cpu_with(
+            &[
+                0xAF, 0xF0, // 200: I = FF0
+                0xF0, 0x02, // 202: load audio pattern
+            ],
+            xochip,
+        );
+        // the last 16 bytes of memory
+        cpu.memory[0xFF0..].copy_from_slice(&[0xAA; AUDIO_PATTERN_SIZE]);
+        steps(&mut cpu, 2);
+        assert_eq!(
+            cpu.get_audio_pattern_arc().lock().unwrap().unwrap().bits,
+            [0xAA; AUDIO_PATTERN_SIZE]
+        );
+
+        let mut cpu = cpu_with(
+            &[
+                0xAF, 0xF1, // 200: I = FF1
+                0xF0, 0x02, // 202: load audio pattern, one byte past the end
+            ],
+            xochip,
+        );
+        steps(&mut cpu, 1);
+        assert!(matches!(
+            cpu.step(),
+            Err(ExecError::MemoryOutOfBounds {
+                program_counter: 0x202,
+                address: 0xFF1,
+                length: AUDIO_PATTERN_SIZE,
+            })
+        ));
+    }
+
+    #[test]
+    fn dump_shows_registers_stack_timers_and_memory() {
+        let mut cpu = cpu(&[
+            0x6A, 0x2A, // 200: VA = 2A
+            0xA3, 0x00, // 202: I = 300
+            0x22, 0x08, // 204: call 208
+            0x00, 0x00, // 206: unused
+            0x12, 0x08, // 208: jump to 208
+        ]);
+        steps(&mut cpu, 3);
+        *cpu.delay_timer_arc.lock().unwrap() = 0x3C;
+        cpu.process_cpu_io_event(&CpuIoEvents::KeyPressed(0x3));
+
+        assert_eq!(
+            cpu.dump(),
+            "PC: 0x208  I: 0x300  SP: 1\n\
+             V0: 0x00  V1: 0x00  V2: 0x00  V3: 0x00  V4: 0x00  V5: 0x00  V6: 0x00  V7: 0x00\n\
+             V8: 0x00  V9: 0x00  VA: 0x2a  VB: 0x00  VC: 0x00  VD: 0x00  VE: 0x00  VF: 0x00\n\
+             Stack: [0x206]\n\
+             DT: 0x3c  ST: 0x00\n\
+             Keypad: 0000000000001000\n\
+             Memory around PC:\n\
+             \x20 1F0: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n\
+             \x20 200: 6A 2A A3 00 22 08 00 00 12 08 00 00 00 00 00 00\n\
+             \x20 210: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n\
+             Memory around I:\n\
+             \x20 2F0: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n\
+             \x20 300: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n\
+             \x20 310: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n"
+        );
+    }
+
+    #[test]
+    fn dump_stops_at_end_of_memory() {
+        let mut cpu = cpu(&[
+            0xAF, 0xFF, // 200: I = FFF
+        ]);
+        steps(&mut cpu, 1);
+        let dump = cpu.dump();
+        let memory_around_i = dump.split("Memory around I:\n").nth(1).unwrap();
+        assert_eq!(
+            memory_around_i
+                .lines()
+                .map(|line| &line[..6])
+                .collect::<Vec<_>>(),
+            ["  FE0:", "  FF0:"]
+        );
+    }
+
+    #[test]
+    fn calling_too_deep_overflows_the_stack() {
+        let mut cpu = cpu(&[
+            0x22, 0x00, // 200: call 200
+        ]);
+        steps(&mut cpu, 16);
+        assert!(matches!(
+            cpu.step(),
+            Err(ExecError::StackOverflow {
+                program_counter: 0x200,
+                max_depth: 16,
+            })
+        ));
+    }
+
+    #[test]
+    fn returning_without_a_call_underflows_the_stack() {
+        let mut cpu = cpu(&[
+            0x00, 0xEE, // 200: return
+        ]);
+        assert!(matches!(
+            cpu.step(),
+            Err(ExecError::StackUnderflow {
+                program_counter: 0x200
+            })
+        ));
+    }
+
+    #[test]
+    fn executes_last_instruction_of_memory() {
+        let mut cpu = cpu(&[
+            0x1F, 0xFE, // 200: jump to FFE
+        ]);
+        cpu.memory[0xFFE..].copy_from_slice(&[0x60, 0x2A]); // FFE: V0 = 2A
+        steps(&mut cpu, 2);
+        assert_eq!(cpu.variable_register(0), 0x2A);
+
+        // and falls off the end of memory after it
+        assert!(matches!(
+            cpu.step(),
+            Err(ExecError::ProgramCounterOutOfBounds {
+                program_counter: 0x1000
+            })
+        ));
+    }
+
+    #[test]
+    fn fetching_half_an_instruction_is_an_error() {
+        let mut cpu = cpu(&[
+            0x60, 0x01, // 200: V0 = 1
+            0xBF, 0xFE, // 202: jump to FFE + V0
+        ]);
+        steps(&mut cpu, 2);
+        assert_eq!(cpu.program_counter(), 0xFFF);
+        assert!(matches!(
+            cpu.step(),
+            Err(ExecError::ProgramCounterOutOfBounds {
+                program_counter: 0xFFF
+            })
+        ));
+    }
+
+    #[test]
+    fn strict_jumps_stop_below_program() {
+        // into the font, as if it were code
+        let program = [
+            0x20, 0x50, // 200: call 050
+        ];
+        let mut cpu = cpu(&program);
+        steps(&mut cpu, 1);
+        assert_eq!(cpu.program_counter(), 0x050);
+
+        let mut cpu = cpu_with(
+            &program,
+            CpuConfig {
+                strict_jumps: true,
+                ..CpuConfig::default()
+            },
+        );
+        assert!(matches!(
+            cpu.step(),
+            Err(ExecError::JumpBelowProgram {
+                program_counter: 0x200,
+                target: 0x050,
+            })
+        ));
+        assert!(cpu.stack.is_empty());
+    }
+
+    // a single pixel in lores, scrolled down 4, right 4 and left 4
+    const LORES_SCROLL: [u8; 12] = [
+        0xA2, 0x0A, // 200: I = 20A
+        0xD0, 0x01, // 202: draw 8x1 at V0, V0
+        0x00, 0xC4, // 204: scroll down 4
+        0x00, 0xFB, // 206: scroll right 4
+        0x00, 0xFC, // 208: scroll left 4
+        0x80, 0x00, // 20A: a single pixel
+    ];
+
+    #[test]
+    fn scrolls_lores_by_the_full_amount() {
+        let mut cpu = cpu(&LORES_SCROLL);
+        steps(&mut cpu, 3);
+        assert_eq!(lit_pixels(cpu.screen()), [(0, 4)]);
+        steps(&mut cpu, 1);
+        assert_eq!(lit_pixels(cpu.screen()), [(4, 4)]);
+        steps(&mut cpu, 1);
+        assert_eq!(lit_pixels(cpu.screen()), [(0, 4)]);
+    }
+
+    #[test]
+    fn scrolls_lores_by_half_with_quirk() {
+        let mut cpu = cpu_with(
+            &LORES_SCROLL,
+            CpuConfig {
+                quirks: Quirks {
+                    lores_half_scroll: true,
+                    ..Quirks::default()
+                },
+                ..CpuConfig::default()
+            },
+        );
+        steps(&mut cpu, 3);
+        assert_eq!(lit_pixels(cpu.screen()), [(0, 2)]);
+        steps(&mut cpu, 1);
+        assert_eq!(lit_pixels(cpu.screen()), [(2, 2)]);
+        steps(&mut cpu, 1);
+        assert_eq!(lit_pixels(cpu.screen()), [(0, 2)]);
+    }
+
+    #[test]
+    fn scrolls_hires_by_the_full_amount_with_quirk() {
+        let program = [
+            0x00, 0xFF, // 200: hires
+            0xA2, 0x0C, // 202: I = 20C
+            0xD0, 0x01, // 204: draw 8x1 at V0, V0
+            0x00, 0xC4, // 206: scroll down 4
+            0x00, 0xFB, // 208: scroll right 4
+            0x00, 0xFC, // 20A: scroll left 4
+            0x80, 0x00, // 20C: a single pixel
+        ];
+        let mut cpu = cpu_with(
+            &program,
+            CpuConfig {
+                quirks: Quirks {
+                    lores_half_scroll: true,
+                    ..Quirks::default()
+                },
+                ..CpuConfig::default()
+            },
+        );
+        steps(&mut cpu, 4);
+        assert_eq!(lit_pixels(cpu.screen()), [(0, 4)]);
+        steps(&mut cpu, 1);
+        assert_eq!(lit_pixels(cpu.screen()), [(4, 4)]);
+    }
+
+    // a 16x16 checkerboard in the bottom right corner of the hires screen, drawn twice
+    fn corner_checkerboard_program() -> Vec<u8> {
+        let mut program = vec![
+            0x00, 0xFF, // 200: hires
+            0x60, 0x78, // 202: V0 = 120
+            0x61, 0x38, // 204: V1 = 56
+            0xA2, 0x10, // 206: I = 210
+            0xD0, 0x10, // 208: draw 16x16 at V0, V1
+            0xD0, 0x10, // 20A: draw 16x16 at V0, V1
+            0x12, 0x0C, // 20C: jump to 20C
+            0x00, 0x00, // 20E: padding
+        ];
+        (0..8).for_each(|_| program.extend([0xAA, 0xAA, 0x55, 0x55])); // 210: the sprite
+        program
+    }
+
+    fn corner_checkerboard_cpu(quirks: Quirks) -> Cpu {
+        cpu_with(
+            &corner_checkerboard_program(),
+            CpuConfig {
+                quirks,
+                ..CpuConfig::default()
+            },
+        )
+    }
+
+    #[test]
+    fn clips_sprite_at_the_edges() {
+        let mut cpu = corner_checkerboard_cpu(Quirks::default());
+        steps(&mut cpu, 5);
+        let lit = lit_pixels(cpu.screen());
+        assert_eq!(lit.len(), 8 * 8 / 2);
+        assert!(lit
+            .iter()
+            .all(|(x, y)| *x >= 120 && *y >= 56 && (x + y) % 2 == 0));
+        assert_eq!(cpu.variable_register(0xF), 0);
+
+        steps(&mut cpu, 1);
+        assert!(lit_pixels(cpu.screen()).is_empty());
+        assert_eq!(cpu.variable_register(0xF), 1);
+    }
+
+    #[test]
+    fn counts_collided_rows_of_clipped_sprite_with_quirk() {
+        let mut cpu = corner_checkerboard_cpu(Quirks {
+            hires_collision_rows: true,
+            ..Quirks::default()
+        });
+        steps(&mut cpu, 6);
+        // only the rows that are on the screen
+        assert_eq!(cpu.variable_register(0xF), 8);
+    }
+
+    #[test]
+    fn wraps_sprite_around_the_edges() {
+        let mut cpu = corner_checkerboard_cpu(Quirks {
+            hires_collision_rows: true,
+            clip_sprites: false,
+            ..Quirks::default()
+        });
+        steps(&mut cpu, 5);
+        let lit = lit_pixels(cpu.screen());
+        assert_eq!(lit.len(), 16 * 16 / 2);
+        assert!(lit
+            .iter()
+            .all(|(x, y)| (*x >= 120 || *x < 8) && (*y >= 56 || *y < 8)));
+        assert!(lit.contains(&(0, 0)) && lit.contains(&(127, 63)));
+
+        steps(&mut cpu, 1);
+        assert!(lit_pixels(cpu.screen()).is_empty());
+        assert_eq!(cpu.variable_register(0xF), 16);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn rpl_flags_round_trip_through_file() {
+        let path = std::env::temp_dir().join(format!("chip8-{}-rpl-flags.bin", std::process::id()));
+        let mut cpu = cpu(&[
+            0x60, 0x01, // 200: V0 = 1
+            0x61, 0x02, // 202: V1 = 2
+            0x62, 0x03, // 204: V2 = 3
+            0xF2, 0x75, // 206: save V0 to V2 to the flags
+        ]);
+        cpu.set_rpl_flags_path(path.clone());
+        steps(&mut cpu, 4);
+        let written = std::fs::read(&path).unwrap();
+
+        let mut cpu = self::cpu(&[
+            0xF2, 0x85, // 200: load V0 to V2 from the flags
+        ]);
+        cpu.set_rpl_flags_path(path.clone());
+        steps(&mut cpu, 1);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(written, [1, 2, 3, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            (0..4).map(|x| cpu.variable_register(x)).collect::<Vec<_>>(),
+            [1, 2, 3, 0]
+        );
+    }
+
+    fn xochip_cpu(program: &[u8]) -> Cpu {
+        cpu_with(
+            program,
+            CpuConfig {
+                xochip: true,
+                ..CpuConfig::default()
+            },
+        )
+    }
+
+    #[test]
+    fn skips_over_long_index_load() {
+        let mut cpu = xochip_cpu(&[
+            0x30, 0x00, // 200: skip if V0 == 0
+            0xF0, 0x00, 0x12, 0x34, // 202: I = 1234
+            0x61, 0x01, // 206: V1 = 1
+        ]);
+        steps(&mut cpu, 1);
+        assert_eq!(cpu.program_counter(), 0x206);
+        steps(&mut cpu, 1);
+        assert_eq!(cpu.variable_register(1), 1);
+        assert_eq!(cpu.index_register(), 0);
+    }
+
+    #[test]
+    fn long_index_load_takes_all_16_bits() {
+        let mut cpu = xochip_cpu(&[
+            0xF0, 0x00, 0xFF, 0xFE, // 200: I = FFFE
+            0x60, 0x03, // 204: V0 = 3
+            0xF0, 0x1E, // 206: I += V0
+        ]);
+        steps(&mut cpu, 1);
+        assert_eq!(cpu.index_register(), 0xFFFE);
+        assert_eq!(cpu.program_counter(), 0x204);
+        steps(&mut cpu, 2);
+        assert_eq!(cpu.index_register(), 0x0001);
+    }
+
+    #[test]
+    fn memory_at_index_past_end_is_an_error() {
+        [
+            [0xF0, 0x33], // store BCD of V0
+            [0xF0, 0x55], // store V0
+            [0xF0, 0x65], // load V0
+            [0xD0, 0x01], // draw 8x1
+            [0xF0, 0x02], // load audio pattern
+        ]
+        .iter()
+        .for_each(|instruction| {
+            let mut program = vec![
+                0xF0, 0x00, 0x10, 0x00, // 200: I = 1000, just past the end of memory
+            ];
+            program.extend(instruction); // 204
+            let mut cpu = xochip_cpu(&program);
+            steps(&mut cpu, 1);
+            assert!(
+                matches!(
+                    cpu.step(),
+                    Err(ExecError::MemoryOutOfBounds {
+                        program_counter: 0x204,
+                        address: 0x1000,
+                        ..
+                    })
+                ),
+                "{:02X?}",
+                instruction
+            );
+        });
+    }
+
+    #[test]
+    fn stores_registers_up_to_end_of_memory() {
+        let mut cpu = cpu(&[
+            0x60, 0x01, // 200: V0 = 1
+            0x61, 0x02, // 202: V1 = 2
+            0xAF, 0xFE, // 204: I = FFE
+            0xF1, 0x55, // 206: store V0 to V1
+            0xF2, 0x55, // 208: store V0 to V2, one byte too many
+        ]);
+        steps(&mut cpu, 4);
+        assert_eq!(cpu.memory[0xFFE..], [1, 2]);
+        assert!(matches!(
+            cpu.step(),
+            Err(ExecError::MemoryOutOfBounds {
+                program_counter: 0x208,
+                address: 0xFFE,
+                length: 3,
+            })
+        ));
+    }
+
+    // the 0 of the font, starting 2 rows above the bottom edge of the lores screen
+    const BOTTOM_EDGE_SPRITE: [u8; 8] = [
+        0x60, 0x0A, // 200: V0 = 10
+        0x61, 0x1E, // 202: V1 = 30
+        0xF2, 0x29, // 204: I = digit V2
+        0xD0, 0x15, // 206: draw 8x5 at V0, V1
+    ];
+
+    fn lit_rows(screen: &CpuScreenMem) -> Vec<usize> {
+        let mut rows = lit_pixels(screen)
+            .iter()
+            .map(|(_, y)| *y)
+            .collect::<Vec<_>>();
+        rows.dedup();
+        rows
+    }
+
+    #[test]
+    fn clips_sprite_at_bottom_edge() {
+        let mut cpu = cpu(&BOTTOM_EDGE_SPRITE);
+        steps(&mut cpu, 4);
+        assert_eq!(lit_rows(cpu.screen()), [30, 31]);
+    }
+
+    #[test]
+    fn wraps_sprite_at_bottom_edge_with_quirk() {
+        let mut cpu = cpu_with(
+            &BOTTOM_EDGE_SPRITE,
+            CpuConfig {
+                quirks: Quirks {
+                    clip_sprites: false,
+                    ..Quirks::default()
+                },
+                ..CpuConfig::default()
+            },
+        );
+        steps(&mut cpu, 4);
+        assert_eq!(lit_rows(cpu.screen()), [0, 1, 2, 30, 31]);
+        // the bottom row of the 0 ends up in the third row from the top
+        assert_eq!(
+            lit_pixels(cpu.screen())
+                .iter()
+                .filter(|(_, y)| *y == 2)
+                .count(),
+            4
+        );
+    }
+
+    #[test]
+    fn draws_big_digit() {
+        let mut cpu = cpu(&[
+            0x00, 0xFF, // 200: hires
+            0x60, 0x17, // 202: V0 = 17, of which only the 7 counts
+            0xF0, 0x30, // 204: I = big digit V0
+            0xD1, 0x1A, // 206: draw 8x10 at V1, V1
+        ]);
+        steps(&mut cpu, 3);
+        assert_eq!(cpu.index_register() as usize, BIG_FONT_START_POS + 7 * 10);
+        steps(&mut cpu, 1);
+
+        let rows = cpu
+            .screen()
+            .ascii_art()
+            .lines()
+            .take(11)
+            .map(|line| line[..9].to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            [
+                "########.",
+                "########.",
+                "......##.",
+                "......##.",
+                ".....##..",
+                "....##...",
+                "...##....",
+                "...##....",
+                "...##....",
+                "...##....",
+                ".........",
+            ]
+        );
+    }
+
+    #[test]
+    fn step_executes_one_instruction_at_a_time() {
+        let mut cpu = cpu(&[
+            0x60, 0x05, // 200: V0 = 5
+            0x70, 0x03, // 202: V0 += 3
+            0x81, 0x00, // 204: V1 = V0
+            0xA3, 0x00, // 206: I = 300
+            0xF1, 0x55, // 208: store V0 to V1
+            0xF2, 0x0A, // 20A: wait for a key into V2
+            0x12, 0x0C, // 20C: jump to 20C
+        ]);
+        cpu.step().unwrap();
+        assert_eq!(
+            (cpu.program_counter(), cpu.variable_register(0)),
+            (0x202, 5)
+        );
+        cpu.step().unwrap();
+        assert_eq!(
+            (cpu.program_counter(), cpu.variable_register(0)),
+            (0x204, 8)
+        );
+        cpu.step().unwrap();
+        assert_eq!(cpu.variable_register(1), 8);
+        cpu.step().unwrap();
+        assert_eq!(cpu.index_register(), 0x300);
+        cpu.step().unwrap();
+        assert_eq!(cpu.memory[0x300..0x303], [8, 8, 0]);
+        assert_eq!(cpu.instructions_executed, 5);
+
+        // nothing happens until a key is pressed
+        steps(&mut cpu, 3);
+        assert_eq!(cpu.program_counter(), 0x20C);
+        assert!(cpu.waiting_for_key.is_some());
+        cpu.process_cpu_io_event(&CpuIoEvents::KeyPressed(0xB));
+        cpu.step().unwrap();
+        assert_eq!(cpu.variable_register(2), 0xB);
+        assert_eq!(cpu.program_counter(), 0x20C);
+    }
+
+    #[test]
+    fn stack_depth_limit_is_configurable() {
+        // calls itself once more with every call
+        let program = [
+            0x22, 0x00, // 200: call 200
+        ];
+        let mut cpu = cpu_with(
+            &program,
+            CpuConfig {
+                max_stack_depth: 4,
+                ..CpuConfig::default()
+            },
+        );
+        steps(&mut cpu, 4);
+        assert_eq!(cpu.stack, [0x202; 4]);
+        assert!(matches!(
+            cpu.step(),
+            Err(ExecError::StackOverflow {
+                program_counter: 0x200,
+                max_depth: 4,
+            })
+        ));
+
+        let mut cpu = cpu_with(
+            &program,
+            CpuConfig {
+                max_stack_depth: 64,
+                ..CpuConfig::default()
+            },
+        );
+        steps(&mut cpu, 64);
+        assert!(matches!(
+            cpu.step(),
+            Err(ExecError::StackOverflow { max_depth: 64, .. })
+        ));
+    }
+
+    #[test]
+    fn jump_to_last_byte_of_memory_stops_headless_run() {
+        let mut cpu = cpu(&[
+            0x1F, 0xFF, // 200: jump to FFF
+        ]);
+        assert!(matches!(
+            cpu.run_headless(10),
+            Err(RunHeadlessError::Exec(
+                ExecError::ProgramCounterOutOfBounds {
+                    program_counter: 0xFFF
+                }
+            ))
+        ));
+        assert_eq!(cpu.instructions_executed, 1);
+    }
+
+    // runs 8XYN with VX and V1 set to the given values, returning VX and VF afterwards
+    fn arithmetic(x: u8, vx: u8, vy: u8, n: u8) -> (u8, u8) {
+        // VX = vx, V1 = vy, then VX = VX op V1
+        let mut cpu = cpu(&[0x60 | x, vx, 0x61, vy, 0x80 | x, 0x10 | n]);
+        steps(&mut cpu, 3);
+        (
+            cpu.variable_register(x as usize),
+            cpu.variable_register(0xF),
+        )
+    }
+
+    #[test]
+    fn add_sets_carry() {
+        assert_eq!(arithmetic(0x0, 0x01, 0x02, 0x4), (0x03, 0));
+        assert_eq!(arithmetic(0x0, 0xFF, 0x02, 0x4), (0x01, 1));
+    }
+
+    #[test]
+    fn subtract_sets_no_borrow_including_equal_values() {
+        assert_eq!(arithmetic(0x0, 0x05, 0x03, 0x5), (0x02, 1));
+        assert_eq!(arithmetic(0x0, 0x03, 0x05, 0x5), (0xFE, 0));
+        assert_eq!(arithmetic(0x0, 0x04, 0x04, 0x5), (0x00, 1));
+        // VY - VX
+        assert_eq!(arithmetic(0x0, 0x03, 0x05, 0x7), (0x02, 1));
+        assert_eq!(arithmetic(0x0, 0x05, 0x03, 0x7), (0xFE, 0));
+        assert_eq!(arithmetic(0x0, 0x04, 0x04, 0x7), (0x00, 1));
+    }
+
+    #[test]
+    fn shift_sets_shifted_out_bit() {
+        assert_eq!(arithmetic(0x0, 0x05, 0x00, 0x6), (0x02, 1));
+        assert_eq!(arithmetic(0x0, 0x04, 0x00, 0x6), (0x02, 0));
+        assert_eq!(arithmetic(0x0, 0x81, 0x00, 0xE), (0x02, 1));
+        assert_eq!(arithmetic(0x0, 0x41, 0x00, 0xE), (0x82, 0));
+    }
+
+    #[test]
+    fn flag_wins_over_result_in_vf() {
+        assert_eq!(arithmetic(0xF, 0xFF, 0x02, 0x4).1, 1);
+        assert_eq!(arithmetic(0xF, 0x01, 0x02, 0x4).1, 0);
+        assert_eq!(arithmetic(0xF, 0x03, 0x05, 0x5).1, 0);
+        assert_eq!(arithmetic(0xF, 0x05, 0x05, 0x5).1, 1);
+        assert_eq!(arithmetic(0xF, 0x04, 0x00, 0x6).1, 0);
+        assert_eq!(arithmetic(0xF, 0x05, 0x03, 0x7).1, 0);
+        assert_eq!(arithmetic(0xF, 0x40, 0x00, 0xE).1, 0);
+        assert_eq!(arithmetic(0xF, 0x80, 0x00, 0xE).1, 1);
+    }
+
+    // keeps the text of every Output status
+    #[derive(Clone, Default)]
+    struct RecordedOutput(Arc<Mutex<Vec<String>>>);
+
+    impl StatusSink for RecordedOutput {
+        fn report(&mut self, status: CpuStatusEvents) {
+            if let CpuStatusEvents::Output(text) = status {
+                self.0.lock().unwrap().push(text);
+            }
+        }
+    }
+
+    #[test]
+    fn debugger_answers_go_to_status_sink() {
+        let output = RecordedOutput::default();
+        let mut cpu = Cpu::new(
+            vec![
+                0x60, 0x2A, // 200: V0 = 2A
+            ],
+            CpuConfig {
+                start_paused: true,
+                ..CpuConfig::default()
+            },
+            NoFrontend,
+            NoFrontend,
+            output.clone(),
+            Arc::new(Mutex::new(0)),
+            Arc::new(Mutex::new(0)),
+            Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        cpu.process_cpu_io_event(&CpuIoEvents::AddBreakpoint(0x200));
+        cpu.process_cpu_io_event(&CpuIoEvents::Step);
+        cpu.run_tick();
+        assert_eq!(
+            *output.0.lock().unwrap(),
+            [
+                "Breakpoint set at 0x200",
+                "200: 602A  LD V0, 0x2a      V0: 0x00 -> 0x2a",
+            ]
+        );
+    }
+
+    fn random_registers(seed: u64) -> Vec<u8> {
+        let mut cpu = cpu_with(
+            &[
+                0xC0, 0xFF, // 200: V0 = random
+                0xC1, 0xFF, // 202: V1 = random
+                0xC2, 0x0F, // 204: V2 = random & 0F
+                0xC3, 0xFF, // 206: V3 = random
+            ],
+            CpuConfig {
+                rng_seed: Some(seed),
+                ..CpuConfig::default()
+            },
+        );
+        steps(&mut cpu, 4);
+        (0..4).map(|x| cpu.variable_register(x)).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_random_numbers() {
+        assert_eq!(random_registers(1234), random_registers(1234));
+        assert_ne!(random_registers(1234), random_registers(4321));
+        assert!(random_registers(1234)[2] <= 0x0F);
+    }
+
+    #[test]
+    fn reset_draws_same_random_numbers_again() {
+        let mut cpu = cpu_with(
+            &[
+                0xC0, 0xFF, // 200: V0 = random
+                0xC1, 0xFF, // 202: V1 = random
+            ],
+            CpuConfig {
+                rng_seed: Some(7),
+                ..CpuConfig::default()
+            },
+        );
+        steps(&mut cpu, 2);
+        let first = (cpu.variable_register(0), cpu.variable_register(1));
+        cpu.reset();
+        steps(&mut cpu, 2);
+        assert_eq!((cpu.variable_register(0), cpu.variable_register(1)), first);
+    }
 }
@@ -0,0 +1,137 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt, ops::Range};
+
+// how much `mem` and `disasm` show when not told
+const DEFAULT_MEMORY_LENGTH: usize = 64;
+const DEFAULT_DISASSEMBLY_COUNT: usize = 10;
+
+pub const HELP: &str = "\
+Commands:
+  step, s                  execute a single instruction while paused
+  continue, c              resume the program
+  pause, p                 pause the program
+  regs, r                  print the registers, stack and timers
+  mem, m <addr> [len]      print len bytes of memory from addr (64 by default)
+  disasm, d [addr] [count] disassemble count instructions from addr (PC and 10 by default)
+  break, b <addr>          pause before executing the instruction at addr
+  unbreak <addr>           remove the breakpoint at addr
+  watch, w <addr|Vx>       pause when the memory at addr or the register Vx changes
+  unwatch <addr|Vx>        stop watching addr or Vx
+  help, h                  print this help
+Addresses and lengths are hex, with or without 0x";
+
+// what can be typed at the debugger prompt
+pub enum DebugCommand {
+    Step,
+    Continue,
+    Pause,
+    Help,
+    Break(usize),
+    Unbreak(usize),
+    Watch(Watchpoint),
+    Unwatch(Watchpoint),
+    // answered by the cpu, which has the state
+    Inspect(Inspection),
+}
+
+// pauses the program when the watched value changes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Watchpoint {
+    Memory(usize),
+    Register(usize),
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watchpoint::Memory(address) => write!(f, "mem[{:#05x}]", address),
+            Watchpoint::Register(x) => write!(f, "V{:X}", x),
+        }
+    }
+}
+
+pub enum Inspection {
+    Registers,
+    Memory(Range<usize>),
+    // None to start at the program counter
+    Disassembly {
+        address: Option<usize>,
+        count: usize,
+    },
+}
+
+pub fn parse_command(line: &str) -> Result<DebugCommand, String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+
+    if let ("watch" | "w" | "unwatch", Some(target), None) = (command, words.next(), words.next()) {
+        let watchpoint = parse_watchpoint(target)?;
+        return Ok(match command {
+            "unwatch" => DebugCommand::Unwatch(watchpoint),
+            _ => DebugCommand::Watch(watchpoint),
+        });
+    }
+
+    let numbers = words.map(parse_number).collect::<Result<Vec<_>, _>>()?;
+
+    let command = match (command, numbers.as_slice()) {
+        ("step" | "s", []) => DebugCommand::Step,
+        ("continue" | "c", []) => DebugCommand::Continue,
+        ("pause" | "p", []) => DebugCommand::Pause,
+        ("help" | "h", []) => DebugCommand::Help,
+        ("break" | "b", [address]) => DebugCommand::Break(*address),
+        ("unbreak", [address]) => DebugCommand::Unbreak(*address),
+        ("regs" | "r", []) => DebugCommand::Inspect(Inspection::Registers),
+        ("mem" | "m", [address]) => DebugCommand::Inspect(Inspection::Memory(
+            *address..address.saturating_add(DEFAULT_MEMORY_LENGTH),
+        )),
+        ("mem" | "m", [address, length]) => DebugCommand::Inspect(Inspection::Memory(
+            *address..address.saturating_add(*length),
+        )),
+        ("disasm" | "d", []) => DebugCommand::Inspect(Inspection::Disassembly {
+            address: None,
+            count: DEFAULT_DISASSEMBLY_COUNT,
+        }),
+        ("disasm" | "d", [address]) => DebugCommand::Inspect(Inspection::Disassembly {
+            address: Some(*address),
+            count: DEFAULT_DISASSEMBLY_COUNT,
+        }),
+        ("disasm" | "d", [address, count]) => DebugCommand::Inspect(Inspection::Disassembly {
+            address: Some(*address),
+            count: *count,
+        }),
+        ("", _) => return Err("Type help for the list of commands".to_string()),
+        _ => {
+            return Err(format!(
+                "Cannot understand {:?}, type help for help",
+                line.trim()
+            ))
+        }
+    };
+    Ok(command)
+}
+
+fn parse_watchpoint(word: &str) -> Result<Watchpoint, String> {
+    match word.strip_prefix(['V', 'v']) {
+        Some(x) => match u8::from_str_radix(x, 16) {
+            Ok(x) if x < 16 => Ok(Watchpoint::Register(x as usize)),
+            _ => Err(format!(
+                "{:?} is not a register, they go from V0 to VF",
+                word
+            )),
+        },
+        None => parse_number(word).map(Watchpoint::Memory),
+    }
+}
+
+fn parse_number(word: &str) -> Result<usize, String> {
+    let digits = word
+        .strip_prefix("0x")
+        .or_else(|| word.strip_prefix("0X"))
+        .unwrap_or(word);
+    usize::from_str_radix(digits, 16).map_err(|_| format!("{:?} is not a hex number", word))
+}
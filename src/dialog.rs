@@ -0,0 +1,40 @@
+use std::{
+    path::PathBuf,
+    sync::mpsc::{self, Receiver},
+};
+
+use rfd::{AsyncFileDialog, FileDialog};
+use winit::window::Window;
+
+const TITLE: &str = "Open CHIP-8 program";
+const PROGRAM_EXTENSIONS: [&str; 2] = ["ch8", "rom"];
+
+// blocks until the dialog is closed, which is only fine before the window is created. None when
+// it was cancelled.
+pub fn pick_program() -> Option<PathBuf> {
+    FileDialog::new()
+        .set_title(TITLE)
+        .add_filter("CHIP-8 programs", &PROGRAM_EXTENSIONS)
+        .add_filter("All files", &["*"])
+        .pick_file()
+}
+
+// The dialog waits for the user on its own thread, so that the event loop keeps drawing the
+// window meanwhile. The picked program arrives on the receiver once the dialog is closed, None
+// when it was cancelled.
+pub fn pick_program_in_background(window: &Window) -> Receiver<Option<PathBuf>> {
+    // created here, as some platforms only show dialogs created on the main thread
+    let dialog = AsyncFileDialog::new()
+        .set_title(TITLE)
+        .add_filter("CHIP-8 programs", &PROGRAM_EXTENSIONS)
+        .add_filter("All files", &["*"])
+        .set_parent(window)
+        .pick_file();
+
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let path = pollster::block_on(dialog).map(|file| file.path().to_path_buf());
+        let _ = sender.send(path);
+    });
+    receiver
+}
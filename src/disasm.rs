@@ -0,0 +1,145 @@
+use alloc::{
+    collections::BTreeSet,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::opcode::Opcode;
+
+// returns None for words that the cpu would refuse to execute
+pub fn mnemonic(opcode: &Opcode) -> Option<String> {
+    let Opcode {
+        op,
+        x,
+        y,
+        n,
+        nn,
+        nnn,
+        ..
+    } = *opcode;
+
+    let text = match op {
+        0x0 => match nnn {
+            0x0C0..=0x0CF => format!("SCD {}", n),
+            0x0D0..=0x0DF => format!("SCU {}", n),
+            0xE0 => "CLS".to_string(),
+            0xEE => "RET".to_string(),
+            0xFB => "SCR".to_string(),
+            0xFC => "SCL".to_string(),
+            0xFD => "EXIT".to_string(),
+            0xFE => "LOW".to_string(),
+            0xFF => "HIGH".to_string(),
+            _ => return None,
+        },
+        0x1 => format!("JP {:#05x}", nnn),
+        0x2 => format!("CALL {:#05x}", nnn),
+        0x3 => format!("SE V{}, {:#04x}", x, nn),
+        0x4 => format!("SNE V{}, {:#04x}", x, nn),
+        0x5 => format!("SE V{}, V{}", x, y),
+        0x6 => format!("LD V{}, {:#04x}", x, nn),
+        0x7 => format!("ADD V{}, {:#04x}", x, nn),
+        0x8 => match n {
+            0x0 => format!("LD V{}, V{}", x, y),
+            0x1 => format!("OR V{}, V{}", x, y),
+            0x2 => format!("AND V{}, V{}", x, y),
+            0x3 => format!("XOR V{}, V{}", x, y),
+            0x4 => format!("ADD V{}, V{}", x, y),
+            0x5 => format!("SUB V{}, V{}", x, y),
+            0x6 => format!("SHR V{}", x),
+            0x7 => format!("SUBN V{}, V{}", x, y),
+            0xE => format!("SHL V{}", x),
+            _ => return None,
+        },
+        0x9 => format!("SNE V{}, V{}", x, y),
+        0xA => format!("LD I, {:#05x}", nnn),
+        0xB => format!("JP V0, {:#05x}", nnn),
+        0xC => format!("RND V{}, {:#04x}", x, nn),
+        0xD => format!("DRW V{}, V{}, {}", x, y, n),
+        0xE => match nn {
+            0x9E => format!("SKP V{}", x),
+            0xA1 => format!("SKNP V{}", x),
+            _ => return None,
+        },
+        0xF => match nn {
+            // the address follows in the next two bytes
+            0x00 if x == 0 => "LD I, LONG".to_string(),
+            0x01 => format!("PLANE {}", x),
+            0x02 if x == 0 => "AUDIO".to_string(),
+            0x07 => format!("LD V{}, DT", x),
+            0x0A => format!("LD V{}, K", x),
+            0x15 => format!("LD DT, V{}", x),
+            0x18 => format!("LD ST, V{}", x),
+            0x1E => format!("ADD I, V{}", x),
+            0x29 => format!("LD F, V{}", x),
+            0x30 => format!("LD HF, V{}", x),
+            0x33 => format!("LD B, V{}", x),
+            0x3A => format!("PITCH V{}", x),
+            0x55 => format!("LD [I], V{}", x),
+            0x65 => format!("LD V{}, [I]", x),
+            0x75 => format!("LD R, V{}", x),
+            0x85 => format!("LD V{}, R", x),
+            _ => return None,
+        },
+        _ => unreachable!(),
+    };
+
+    Some(text)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisasmLine {
+    pub address: u16,
+    // two bytes for an instruction, four for F000 NNNN, a single one for a trailing odd byte
+    pub bytes: Vec<u8>,
+    pub text: String,
+    // whether a jump or call elsewhere in the rom lands here
+    pub is_jump_target: bool,
+}
+
+pub fn disassemble(rom: &[u8], base: u16) -> Vec<DisasmLine> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let chunk = &rom[offset..(offset + 2).min(rom.len())];
+        let (text, length) = match *chunk {
+            [0xF0, 0x00] if offset + 4 <= rom.len() => (
+                format!(
+                    "LD I, LONG {:#06x}",
+                    ((rom[offset + 2] as u16) << 8) + rom[offset + 3] as u16
+                ),
+                4,
+            ),
+            [high, low] => {
+                let word = ((high as u16) << 8) + low as u16;
+                match mnemonic(&Opcode::decode(word)) {
+                    Some(text) => (text, 2),
+                    None => (format!(".byte {:#04x}, {:#04x}", high, low), 2),
+                }
+            }
+            // odd-sized rom, the last byte cannot be an instruction
+            _ => (format!(".byte {:#04x}", chunk[0]), 1),
+        };
+
+        lines.push(DisasmLine {
+            address: base + offset as u16,
+            bytes: rom[offset..(offset + length)].to_vec(),
+            text,
+            is_jump_target: false,
+        });
+        offset += length;
+    }
+
+    let jump_targets = lines
+        .iter()
+        .filter(|line| line.bytes.len() == 2)
+        .map(|line| Opcode::decode(((line.bytes[0] as u16) << 8) + line.bytes[1] as u16))
+        .filter(|opcode| opcode.op == 0x1 || opcode.op == 0x2)
+        .map(|opcode| opcode.nnn)
+        .collect::<BTreeSet<_>>();
+    lines
+        .iter_mut()
+        .for_each(|line| line.is_jump_target = jump_targets.contains(&line.address));
+
+    lines
+}
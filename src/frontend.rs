@@ -0,0 +1,173 @@
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+
+use crate::{
+    cpu::{CpuIoEvents, CpuScreenMem, CpuState, CpuStatusEvents},
+    sync::Mutex,
+};
+
+// what the cpu talks to, so that it can run without a window or any channels
+
+pub trait ScreenSink: Send {
+    fn update(&mut self, screen: &CpuScreenMem);
+}
+
+// the keypad, along with the other events that control the cpu
+pub trait KeySource: Send {
+    // Err(PollError::Disconnected) stops the cpu
+    fn poll(&mut self) -> Result<CpuIoEvents, PollError>;
+}
+
+#[derive(Debug)]
+pub enum PollError {
+    // nothing happened since the last poll
+    Empty,
+    // nobody is left to send events, such as after the window was closed
+    Disconnected,
+}
+
+pub trait StatusSink: Send {
+    fn report(&mut self, status: CpuStatusEvents);
+}
+
+// played while the sound timer is running
+pub trait Buzzer {
+    fn set_playing(&mut self, playing: bool);
+}
+
+// the receivers may already be gone, such as when the window was closed, and there is nobody left
+// to tell
+#[cfg(feature = "std")]
+impl ScreenSink for Sender<CpuScreenMem> {
+    fn update(&mut self, screen: &CpuScreenMem) {
+        let _ = self.send(*screen);
+    }
+}
+
+#[cfg(feature = "std")]
+impl KeySource for Receiver<CpuIoEvents> {
+    fn poll(&mut self) -> Result<CpuIoEvents, PollError> {
+        self.try_recv().map_err(|err| match err {
+            TryRecvError::Empty => PollError::Empty,
+            TryRecvError::Disconnected => PollError::Disconnected,
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl StatusSink for Sender<CpuStatusEvents> {
+    fn report(&mut self, status: CpuStatusEvents) {
+        let _ = self.send(status);
+    }
+}
+
+// Only the latest screen is kept, as a frontend cannot show more than one per frame anyway. A
+// channel would queue up every screen drawn between two frames instead.
+#[derive(Clone, Default)]
+pub struct LatestScreen {
+    screen: Arc<Mutex<Option<CpuScreenMem>>>,
+}
+
+impl LatestScreen {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the screen drawn since the last call, if any
+    pub fn take(&self) -> Option<CpuScreenMem> {
+        self.screen.lock().unwrap().take()
+    }
+}
+
+impl ScreenSink for LatestScreen {
+    fn update(&mut self, screen: &CpuScreenMem) {
+        *self.screen.lock().unwrap() = Some(*screen);
+    }
+}
+
+// The state of the cpu, for frontends that show it live, such as the debug overlay. The cpu only
+// takes a new snapshot once the last one was taken, so that it costs next to nothing while nobody
+// looks at it.
+#[derive(Clone, Default)]
+pub struct LatestState {
+    state: Arc<Mutex<Option<CpuState>>>,
+}
+
+impl LatestState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn take(&self) -> Option<CpuState> {
+        self.state.lock().unwrap().take()
+    }
+
+    pub fn publish(&self, snapshot: impl FnOnce() -> CpuState) {
+        let mut state = self.state.lock().unwrap();
+        if state.is_none() {
+            *state = Some(snapshot());
+        }
+    }
+}
+
+// for running without a frontend, such as headless runs, which read the screen at the end
+pub struct NoFrontend;
+
+impl ScreenSink for NoFrontend {
+    fn update(&mut self, _: &CpuScreenMem) {}
+}
+
+impl KeySource for NoFrontend {
+    fn poll(&mut self) -> Result<CpuIoEvents, PollError> {
+        Err(PollError::Empty)
+    }
+}
+
+impl StatusSink for NoFrontend {
+    fn report(&mut self, _: CpuStatusEvents) {}
+}
+
+impl Buzzer for NoFrontend {
+    fn set_playing(&mut self, _: bool) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::AtomicBool;
+
+    use super::*;
+    use crate::cpu::{Cpu, CpuConfig};
+
+    // draws a sprite and clears the screen again, over and over
+    const FLICKER: [u8; 6] = [
+        0xD0, 0x05, // 200: draw 8x5 at V0, V0
+        0x00, 0xE0, // 202: clear the screen
+        0x12, 0x00, // 204: jump to 200
+    ];
+
+    #[test]
+    fn latest_screen_keeps_only_last_of_many_screens() {
+        let latest_screen = LatestScreen::new();
+        let mut cpu = Cpu::new(
+            FLICKER.to_vec(),
+            CpuConfig::default(),
+            latest_screen.clone(),
+            NoFrontend,
+            NoFrontend,
+            Arc::new(Mutex::new(0)),
+            Arc::new(Mutex::new(0)),
+            Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        // two thirds of which draw, without anybody taking the screens
+        (0..300_000).for_each(|_| cpu.step().unwrap());
+        // a single screen, however many were drawn
+        assert_eq!(latest_screen.take().as_ref(), Some(cpu.screen()));
+        assert_eq!(latest_screen.take(), None);
+
+        cpu.step().unwrap();
+        assert_eq!(latest_screen.take().as_ref(), Some(cpu.screen()));
+    }
+}
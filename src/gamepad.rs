@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+
+use crate::cpu::CpuIoEvents;
+
+pub const DEFAULT_STICK_DEADZONE: f32 = 0.5;
+
+// Every connected gamepad presses the same CHIP-8 keys. Gamepads can be plugged in and out at any
+// time, as gilrs keeps looking for them.
+pub struct Gamepads {
+    gilrs: Gilrs,
+    // how far a stick has to be pushed before it presses a key, from 0.0 to 1.0
+    stick_deadzone: f32,
+    // the key that each button holds down, so that a gamepad that is unplugged only releases its
+    // own keys
+    button_keys: HashMap<(GamepadId, Button), u8>,
+    // the key that each stick axis holds down, along the lines of the d-pad
+    stick_keys: HashMap<(GamepadId, Axis), u8>,
+}
+
+impl Gamepads {
+    // None when gamepads are not supported on this system
+    pub fn new(stick_deadzone: f32) -> Option<Self> {
+        match Gilrs::new() {
+            Ok(gilrs) => Some(Self {
+                gilrs,
+                stick_deadzone,
+                button_keys: HashMap::new(),
+                stick_keys: HashMap::new(),
+            }),
+            Err(err) => {
+                log::warn!("Gamepads are not available: {:?}", err);
+                None
+            }
+        }
+    }
+
+    // the key events since the last call, with the buttons mapped through the keymap
+    pub fn poll(&mut self, buttons: &HashMap<Button, u8>) -> Vec<CpuIoEvents> {
+        let mut events = Vec::new();
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(value) = buttons.get(&button) {
+                        events.push(CpuIoEvents::KeyPressed(*value));
+                        self.button_keys.insert((event.id, button), *value);
+                    }
+                }
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(value) = self.button_keys.remove(&(event.id, button)) {
+                        events.push(CpuIoEvents::KeyReleased(value));
+                    }
+                }
+                EventType::AxisChanged(axis, position, _) => {
+                    self.move_stick(event.id, axis, position, &mut events);
+                }
+                EventType::Connected => {
+                    log::info!("Gamepad {} connected", self.gilrs.gamepad(event.id).name());
+                }
+                EventType::Disconnected => {
+                    log::info!(
+                        "Gamepad {} disconnected",
+                        self.gilrs.gamepad(event.id).name()
+                    );
+                    // Its buttons never get a release event, so none of the keys that it holds
+                    // stay held down. The keys held on the keyboard and other gamepads are kept.
+                    release_keys(&mut self.button_keys, event.id, &mut events);
+                    release_keys(&mut self.stick_keys, event.id, &mut events);
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    // Pressing a stick past the deadzone presses the key of its direction, 2/4/6/8 like the
+    // d-pad, and letting it back releases the key. Up is positive on the Y axes.
+    fn move_stick(
+        &mut self,
+        id: GamepadId,
+        axis: Axis,
+        position: f32,
+        events: &mut Vec<CpuIoEvents>,
+    ) {
+        let (negative_key, positive_key) = match axis {
+            Axis::LeftStickX | Axis::RightStickX => (0x4, 0x6),
+            Axis::LeftStickY | Axis::RightStickY => (0x8, 0x2),
+            _ => return,
+        };
+        let key = if position > self.stick_deadzone {
+            Some(positive_key)
+        } else if position < -self.stick_deadzone {
+            Some(negative_key)
+        } else {
+            None
+        };
+
+        let held_key = self.stick_keys.get(&(id, axis)).copied();
+        if key == held_key {
+            return;
+        }
+        if let Some(held_key) = held_key {
+            events.push(CpuIoEvents::KeyReleased(held_key));
+            self.stick_keys.remove(&(id, axis));
+        }
+        if let Some(key) = key {
+            events.push(CpuIoEvents::KeyPressed(key));
+            self.stick_keys.insert((id, axis), key);
+        }
+    }
+}
+
+// forgets the keys that the gamepad holds down, releasing them
+fn release_keys<T>(
+    held_keys: &mut HashMap<(GamepadId, T), u8>,
+    id: GamepadId,
+    events: &mut Vec<CpuIoEvents>,
+) {
+    held_keys.retain(|(held_by, _), value| {
+        if *held_by == id {
+            events.push(CpuIoEvents::KeyReleased(*value));
+        }
+        *held_by != id
+    });
+}
@@ -1,8 +1,10 @@
-use std::{cmp::Ordering, sync::mpsc::Receiver};
+use std::{cmp::Ordering, ops::Range, path::Path};
+
+use image::{ImageResult, Rgba, RgbaImage};
 
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
-    AddressMode, Backends, BindGroup, BindGroupDescriptor, BindGroupEntry,
+    AddressMode, Backends, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
     BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BlendState,
     Buffer, BufferAddress, BufferBindingType, BufferUsages, ColorTargetState, ColorWrites,
     CommandEncoderDescriptor, CompositeAlphaMode, Device, DeviceDescriptor, Extent3d, Face,
@@ -10,36 +12,154 @@ use wgpu::{
     Instance, Limits, LoadOp, MultisampleState, Operations, Origin3d, PipelineLayoutDescriptor,
     PolygonMode, PowerPreference, PresentMode, PrimitiveState, PrimitiveTopology, Queue,
     RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor,
-    RequestAdapterOptions, SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor,
-    ShaderSource, ShaderStages, Surface, SurfaceConfiguration, SurfaceError, Texture,
-    TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType,
-    TextureUsages, TextureViewDescriptor, TextureViewDimension, VertexAttribute,
+    RequestAdapterOptions, RequestDeviceError, Sampler, SamplerBindingType, SamplerDescriptor,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, Surface, SurfaceConfiguration,
+    SurfaceError, Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension, VertexAttribute,
     VertexBufferLayout, VertexFormat, VertexState, VertexStepMode,
 };
 use winit::{dpi::PhysicalSize, window::Window};
 
-use crate::cpu::CpuScreenMem;
+#[cfg(feature = "egui")]
+use crate::overlay::OverlayFrame;
+#[cfg(feature = "softbuffer")]
+use crate::software_renderer::SoftbufferRenderer;
+use crate::{
+    cpu::{CpuScreenMem, PLANE_COUNT},
+    frontend::LatestScreen,
+    hud::{draw_hud, hud_scale, HudImage},
+    palette::Palette,
+};
+
+pub(crate) const SCREEN_PX_WIDTH: usize = 64;
+pub(crate) const SCREEN_PX_HEIGHT: usize = 32;
+pub(crate) const SCREEN_PX_STRIDE: usize = 4;
+// how much of the way to its color an unlit pixel fades each frame, unless chosen otherwise
+pub const DEFAULT_FADE_RATE: f32 = 0.3;
+
+#[derive(Debug)]
+pub enum GraphicsInitError {
+    // not even a software one
+    NoAdapter,
+    NoDevice(RequestDeviceError),
+    ZeroSizedWindow,
+    #[cfg(feature = "softbuffer")]
+    Softbuffer(softbuffer::SoftBufferError),
+}
+
+// which renderer draws the window
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RendererChoice {
+    // wgpu, or softbuffer when there is no graphics adapter and it was built in
+    Auto,
+    Wgpu,
+    #[cfg(feature = "softbuffer")]
+    Softbuffer,
+}
+
+// how many frames in a row can fail to render before giving up
+const MAX_FAILED_FRAMES: u32 = 60;
+
+// what to do when a frame cannot be rendered
+#[derive(Debug, PartialEq, Eq)]
+pub enum RenderErrorAction {
+    // configure the surface again, such as after the window changed size, and try again
+    Reconfigure,
+    // wait for the next frame
+    Skip,
+    Exit,
+}
+
+pub fn render_error_action(error: &SurfaceError, failed_frames: u32) -> RenderErrorAction {
+    match error {
+        _ if failed_frames >= MAX_FAILED_FRAMES => RenderErrorAction::Exit,
+        SurfaceError::OutOfMemory => RenderErrorAction::Exit,
+        SurfaceError::Lost | SurfaceError::Outdated => RenderErrorAction::Reconfigure,
+        SurfaceError::Timeout => RenderErrorAction::Skip,
+    }
+}
 
-const SCREEN_PX_WIDTH: usize = 64;
-const SCREEN_PX_HEIGHT: usize = 32;
-const SCREEN_PX_STRIDE: usize = 4;
+// draws the screen pixels into the window, either with the graphics card or without one
+pub trait Renderer {
+    // the rows written so far are lost
+    fn set_screen_size(&mut self, width: u32, height: u32);
+    // pixels holds the RGBA pixels of the rows, one row after the other
+    fn write_screen_rows(&mut self, rows: Range<usize>, pixels: &[u8]);
+    fn resize(&mut self, size: PhysicalSize<u32>);
+    fn set_grid(&mut self, grid: bool);
+    // draws the screen, with the bars around it in the background color
+    fn present(&mut self, background: [u8; SCREEN_PX_STRIDE]) -> Result<(), SurfaceError>;
+    fn present_mode(&self) -> PresentMode;
+    // draws the HUD over the top left corner of the window from the next frame on, or stops
+    // drawing it
+    fn set_hud(&mut self, hud: Option<&HudImage>);
+    // draws the overlay over the screen in the next frame, or stops drawing it
+    #[cfg(feature = "egui")]
+    fn set_overlay(&mut self, _overlay: Option<OverlayFrame>) {}
+}
 
 pub struct Graphics {
+    renderer: Box<dyn Renderer>,
+    latest_screen: LatestScreen,
+    // the last screen received from the cpu
+    screen: CpuScreenMem,
+    // the screen that the renderer currently shows, None when its pixels were just reset
+    uploaded_screen: Option<CpuScreenMem>,
+    pixel_buffer: Vec<u8>,
+    // how much of the way to its color an unlit pixel fades each frame, None to not fade
+    fade: Option<f32>,
+    // the color that each pixel currently shows, while fading
+    faded_pixels: Vec<[f32; 3]>,
+    plane_colors: [[u8; SCREEN_PX_STRIDE]; 4],
+    // whether lines are drawn between the pixels, only in the window and not in screenshots
+    grid: bool,
+    // the text of the HUD, None while it is hidden
+    hud_lines: Option<Vec<String>>,
+    // for sizing the HUD
+    window_size: PhysicalSize<u32>,
+    // set when the window contents are stale even though the screen did not change, such as after
+    // a resize, so that render does not skip the next frame
+    needs_redraw: bool,
+    frames_presented: u64,
+    // frames in a row that could not be presented
+    failed_frames: u32,
+}
+
+pub struct WgpuRenderer {
     surface: Surface,
     device: Device,
     queue: Queue,
     config: SurfaceConfiguration,
     window_size: PhysicalSize<u32>,
     render_pipeline: RenderPipeline,
+    // the same quad as the screen, blended over it at the size of the HUD
+    hud_pipeline: RenderPipeline,
+    hud_transform_buffer: Buffer,
+    hud_transform_bind_group: BindGroup,
+    // created again only when the HUD changes size, and kept while it is hidden
+    hud_texture: Option<(Extent3d, Texture, BindGroup)>,
+    hud_visible: bool,
     vertex_buffer: Buffer,
     index_buffer: Buffer,
     num_indices: u32,
     ratio_buffer: Buffer,
+    grid_buffer: Buffer,
     ratio_bind_group: BindGroup,
     screen_texture_size: Extent3d,
     screen_texture: Texture,
     screen_texture_bind_group: BindGroup,
-    screen_update_receiver: Receiver<CpuScreenMem>,
+    screen_texture_bind_group_layout: BindGroupLayout,
+    screen_texture_sampler: Sampler,
+    integer_scale: bool,
+    // created once the overlay is first shown
+    #[cfg(feature = "egui")]
+    egui_renderer: Option<egui_wgpu::Renderer>,
+    #[cfg(feature = "egui")]
+    overlay: Option<OverlayFrame>,
+    // the texture changes of every overlay frame since the last one drawn, as egui only sends
+    // each change once
+    #[cfg(feature = "egui")]
+    overlay_textures: egui::TexturesDelta,
 }
 
 fn calculate_screen_ratio(size: &PhysicalSize<u32>) -> [f32; 2] {
@@ -50,6 +170,133 @@ fn calculate_screen_ratio(size: &PhysicalSize<u32>) -> [f32; 2] {
     }
 }
 
+// scale of the screen quad, followed by its offset, in clip space
+fn calculate_screen_transform(
+    size: &PhysicalSize<u32>,
+    screen_width: u32,
+    screen_height: u32,
+    integer_scale: bool,
+) -> [f32; 4] {
+    let scale = (size.width / screen_width).min(size.height / screen_height);
+    if !integer_scale || scale == 0 {
+        let [x, y] = calculate_screen_ratio(size);
+        return [x, y, 0.0, 0.0];
+    }
+
+    let (quad_width, quad_height) = (screen_width * scale, screen_height * scale);
+    // when the margins cannot be split evenly, move the quad by half a window pixel so that the
+    // edges of the screen pixels stay on window pixel edges
+    let half_pixel_offset = |window_length: u32, quad_length: u32| {
+        if (window_length - quad_length) % 2 == 1 {
+            1.0 / window_length as f32
+        } else {
+            0.0
+        }
+    };
+    [
+        quad_width as f32 / size.width as f32,
+        quad_height as f32 / size.height as f32,
+        half_pixel_offset(size.width, quad_width),
+        half_pixel_offset(size.height, quad_height),
+    ]
+}
+
+// moves the quad into the top left corner of the window, at the size of the HUD in window pixels
+fn calculate_hud_transform(
+    window_size: &PhysicalSize<u32>,
+    hud_size: &PhysicalSize<u32>,
+) -> [f32; 4] {
+    let scale_x = hud_size.width as f32 / window_size.width as f32;
+    let scale_y = hud_size.height as f32 / window_size.height as f32;
+    [scale_x, scale_y, scale_x - 1.0, 1.0 - scale_y]
+}
+
+// the window pixels that the screen covers, as x, y, width and height, for drawing without the
+// shader
+#[cfg(feature = "softbuffer")]
+pub(crate) fn calculate_screen_rect(
+    size: &PhysicalSize<u32>,
+    screen_width: u32,
+    screen_height: u32,
+    integer_scale: bool,
+) -> [u32; 4] {
+    let [scale_x, scale_y, _, _] =
+        calculate_screen_transform(size, screen_width, screen_height, integer_scale);
+    let width = ((size.width as f32 * scale_x).round() as u32).min(size.width);
+    let height = ((size.height as f32 * scale_y).round() as u32).min(size.height);
+    [
+        (size.width - width) / 2,
+        (size.height - height) / 2,
+        width,
+        height,
+    ]
+}
+
+// writes the screen as an image in the format of the file extension, with each pixel drawn as a
+// scale x scale block
+pub fn save_screen_image(
+    screen: &CpuScreenMem,
+    plane_colors: &[[u8; SCREEN_PX_STRIDE]; 4],
+    path: &Path,
+    scale: u32,
+) -> ImageResult<()> {
+    let (width, height) = (screen.width() as u32, screen.height() as u32);
+    RgbaImage::from_fn(width * scale, height * scale, |x, y| {
+        Rgba(plane_colors[screen.pixel((x / scale) as usize, (y / scale) as usize) as usize])
+    })
+    .save(path)
+}
+
+// padded to a vec4, like the other uniform
+fn grid_uniform(grid: bool) -> [f32; 4] {
+    [if grid { 1.0 } else { 0.0 }, 0.0, 0.0, 0.0]
+}
+
+fn create_screen_texture(
+    device: &Device,
+    bind_group_layout: &BindGroupLayout,
+    sampler: &Sampler,
+    width: u32,
+    height: u32,
+) -> (Extent3d, Texture, BindGroup) {
+    let screen_texture_size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let screen_texture = device.create_texture(&TextureDescriptor {
+        label: Some("Screen Texture"),
+        size: screen_texture_size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba8Unorm,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+    });
+
+    let screen_texture_view = screen_texture.create_view(&TextureViewDescriptor::default());
+    let screen_texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("screen_texture_bind_group"),
+        layout: bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&screen_texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(sampler),
+            },
+        ],
+    });
+
+    (
+        screen_texture_size,
+        screen_texture,
+        screen_texture_bind_group,
+    )
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct Vertex {
@@ -77,25 +324,345 @@ const SCREEN_VERTICES: [Vertex; 4] = [
 ];
 const SCREEN_INDICES: [u16; 6] = [0, 1, 3, 3, 1, 2];
 
+// The runs of rows that differ between the screen that the renderer shows and the update, at
+// either resolution. Every row when nothing was uploaded yet or it was at the other resolution.
+fn changed_row_ranges(uploaded: Option<&CpuScreenMem>, update: &CpuScreenMem) -> Vec<Range<usize>> {
+    let row_mask = update.row_mask();
+    let changed_rows = (0..update.height()).filter(|y| match uploaded {
+        Some(uploaded) if uploaded.hires == update.hires => (0..PLANE_COUNT)
+            .any(|plane| (uploaded.planes[plane][*y] ^ update.planes[plane][*y]) & row_mask != 0),
+        _ => true,
+    });
+    let mut row_ranges: Vec<Range<usize>> = Vec::new();
+    changed_rows.for_each(|y| match row_ranges.last_mut() {
+        Some(range) if range.end == y => range.end += 1,
+        _ => row_ranges.push(y..y + 1),
+    });
+    row_ranges
+}
+
 // must only be created and maintained by the main thread
 impl Graphics {
-    pub async fn new(window: &Window, screen_update_receiver: Receiver<CpuScreenMem>) -> Self {
+    pub async fn new(
+        window: &Window,
+        palette: Palette,
+        integer_scale: bool,
+        fade: Option<f32>,
+        grid: bool,
+        present_mode: PresentMode,
+        renderer: RendererChoice,
+    ) -> Result<Self, GraphicsInitError> {
         let window_size = window.inner_size();
+        if window_size.width == 0 || window_size.height == 0 {
+            return Err(GraphicsInitError::ZeroSizedWindow);
+        }
+
+        let wgpu_renderer = || WgpuRenderer::new(window, integer_scale, grid, present_mode);
+        let renderer: Box<dyn Renderer> = match renderer {
+            RendererChoice::Wgpu => Box::new(wgpu_renderer().await?),
+            #[cfg(feature = "softbuffer")]
+            RendererChoice::Softbuffer => Box::new(
+                SoftbufferRenderer::new(window, integer_scale, grid)
+                    .map_err(GraphicsInitError::Softbuffer)?,
+            ),
+            RendererChoice::Auto => match wgpu_renderer().await {
+                Ok(renderer) => Box::new(renderer),
+                #[cfg(feature = "softbuffer")]
+                Err(err @ (GraphicsInitError::NoAdapter | GraphicsInitError::NoDevice(_))) => {
+                    log::warn!(
+                        "Cannot render with wgpu ({:?}), using softbuffer instead",
+                        err
+                    );
+                    Box::new(
+                        SoftbufferRenderer::new(window, integer_scale, grid)
+                            .map_err(GraphicsInitError::Softbuffer)?,
+                    )
+                }
+                Err(err) => return Err(err),
+            },
+        };
+
+        Ok(Self::with_renderer(
+            renderer,
+            palette,
+            fade,
+            grid,
+            window_size,
+        ))
+    }
+
+    fn with_renderer(
+        renderer: Box<dyn Renderer>,
+        palette: Palette,
+        fade: Option<f32>,
+        grid: bool,
+        window_size: PhysicalSize<u32>,
+    ) -> Self {
+        let mut graphics = Self {
+            renderer,
+            latest_screen: LatestScreen::new(),
+            screen: CpuScreenMem::splash(),
+            uploaded_screen: None,
+            pixel_buffer: Vec::new(),
+            fade,
+            faded_pixels: Vec::new(),
+            plane_colors: palette.plane_colors(),
+            grid,
+            hud_lines: None,
+            window_size,
+            needs_redraw: true,
+            frames_presented: 0,
+            failed_frames: 0,
+        };
+        // the renderer would otherwise show black until the cpu draws its first screen
+        graphics.upload_changed_rows(&CpuScreenMem::splash());
+        graphics
+    }
+
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        if new_size.width == 0 || new_size.height == 0 {
+            log::warn!(
+                "window inner size cannot be 0! Current size = {} x {}",
+                new_size.width,
+                new_size.height
+            );
+            return;
+        }
+
+        self.renderer.resize(new_size);
+        self.window_size = new_size;
+        // the text grows and shrinks with the window
+        if self.hud_lines.is_some() {
+            self.upload_hud();
+        }
+        self.needs_redraw = true;
+    }
+
+    // drawn over the top left corner of the window from the next frame on, None to hide it
+    pub fn set_hud(&mut self, lines: Option<Vec<String>>) {
+        if lines != self.hud_lines {
+            self.hud_lines = lines;
+            self.upload_hud();
+        }
+    }
+
+    fn upload_hud(&mut self) {
+        let hud = self.hud_lines.as_ref().map(|lines| {
+            let scale = hud_scale(lines, self.window_size.width, self.window_size.height);
+            draw_hud(lines, scale)
+        });
+        self.renderer.set_hud(hud.as_ref());
+        self.needs_redraw = true;
+    }
+
+    // drawn over the screen in the next frame
+    #[cfg(feature = "egui")]
+    pub fn set_overlay(&mut self, overlay: Option<OverlayFrame>) {
+        self.renderer.set_overlay(overlay);
+        self.needs_redraw = true;
+    }
+
+    pub fn toggle_grid(&mut self) {
+        self.grid = !self.grid;
+        self.renderer.set_grid(self.grid);
+        self.needs_redraw = true;
+    }
+
+    // for the cpu to draw to
+    pub fn latest_screen(&self) -> LatestScreen {
+        self.latest_screen.clone()
+    }
+
+    pub fn screen(&self) -> &CpuScreenMem {
+        &self.screen
+    }
+
+    // frames where nothing changed are not presented, so they are not counted either
+    pub fn frames_presented(&self) -> u64 {
+        self.frames_presented
+    }
+
+    pub fn failed_frames(&self) -> u32 {
+        self.failed_frames
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        self.renderer.present_mode()
+    }
+
+    pub fn plane_colors(&self) -> &[[u8; SCREEN_PX_STRIDE]; 4] {
+        &self.plane_colors
+    }
+
+    // the screen is drawn again right away, even if the program does not draw anything
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.plane_colors = palette.plane_colors();
+        self.uploaded_screen = None;
+        // nothing to fade from either
+        self.faded_pixels.clear();
+        if self.fade.is_none() {
+            let screen = self.screen;
+            self.upload_changed_rows(&screen);
+        }
+        self.needs_redraw = true;
+    }
+
+    // writes the screen as an image, with each pixel drawn as a scale x scale block
+    pub fn save_screenshot(&self, path: &Path, scale: u32) -> ImageResult<()> {
+        save_screen_image(&self.screen, &self.plane_colors, path, scale)
+    }
+
+    // returns whether the texture changed
+    fn handle_screen_updates(&mut self) -> bool {
+        let mut changed = false;
+        if let Some(update) = self.latest_screen.take() {
+            if (update.width(), update.height()) != (self.screen.width(), self.screen.height()) {
+                self.renderer
+                    .set_screen_size(update.width() as u32, update.height() as u32);
+                self.uploaded_screen = None;
+            }
+            self.screen = update;
+
+            if self.fade.is_none() {
+                changed = self.upload_changed_rows(&update);
+            }
+        }
+
+        if let Some(rate) = self.fade {
+            changed = self.upload_faded_screen(rate);
+        }
+
+        changed
+    }
+
+    // a sprite usually only touches a few rows, so only those are uploaded again. Returns whether
+    // any row changed.
+    fn upload_changed_rows(&mut self, update: &CpuScreenMem) -> bool {
+        let row_ranges = changed_row_ranges(self.uploaded_screen.as_ref(), update);
+        let changed = !row_ranges.is_empty();
+
+        // reused between uploads rather than allocated for each one
+        let mut pixels = std::mem::take(&mut self.pixel_buffer);
+        row_ranges.into_iter().for_each(|rows| {
+            pixels.resize(SCREEN_PX_STRIDE * update.width() * rows.len(), 0);
+            update.write_rgba(rows.clone(), &self.plane_colors, &mut pixels);
+            self.renderer.write_screen_rows(rows, &pixels);
+        });
+        self.pixel_buffer = pixels;
+        self.uploaded_screen = Some(*update);
+
+        changed
+    }
+
+    // Lit pixels show their color right away, while unlit ones fade out over a few frames, which
+    // hides the flicker of sprites that are erased and drawn again. Called every frame, returns
+    // whether any pixel changed.
+    fn upload_faded_screen(&mut self, rate: f32) -> bool {
+        let (width, height) = (self.screen.width(), self.screen.height());
+        let target = |x: usize, y: usize| {
+            let color = self.plane_colors[self.screen.pixel(x, y) as usize];
+            [color[0] as f32, color[1] as f32, color[2] as f32]
+        };
+
+        let mut changed = false;
+        if self.faded_pixels.len() != width * height {
+            // nothing to fade from after the resolution changed
+            self.faded_pixels = (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| target(x, y))
+                .collect();
+            changed = true;
+        }
 
+        (0..height).for_each(|y| {
+            (0..width).for_each(|x| {
+                let target = target(x, y);
+                let displayed = &mut self.faded_pixels[y * width + x];
+                let faded = if self.screen.pixel(x, y) != 0
+                    || (0..3).all(|c| (target[c] - displayed[c]).abs() < 1f32)
+                {
+                    target
+                } else {
+                    [0, 1, 2].map(|c| displayed[c] + (target[c] - displayed[c]) * rate)
+                };
+                if faded != *displayed {
+                    *displayed = faded;
+                    changed = true;
+                }
+            });
+        });
+
+        if changed {
+            let mut pixels = std::mem::take(&mut self.pixel_buffer);
+            pixels.resize(SCREEN_PX_STRIDE * width * height, 0);
+            pixels
+                .chunks_exact_mut(SCREEN_PX_STRIDE)
+                .zip(&self.faded_pixels)
+                .for_each(|(pixel, color)| {
+                    pixel.copy_from_slice(&[color[0] as u8, color[1] as u8, color[2] as u8, 255])
+                });
+            self.renderer.write_screen_rows(0..height, &pixels);
+            self.pixel_buffer = pixels;
+        }
+        changed
+    }
+
+    pub fn render(&mut self) -> Result<(), SurfaceError> {
+        self.needs_redraw |= self.handle_screen_updates();
+        // most programs leave the screen untouched for many frames, so keep the last frame
+        if !self.needs_redraw {
+            return Ok(());
+        }
+
+        if let Err(err) = self.renderer.present(self.plane_colors[0]) {
+            self.failed_frames += 1;
+            return Err(err);
+        }
+        self.failed_frames = 0;
+        self.needs_redraw = false;
+        self.frames_presented += 1;
+
+        Ok(())
+    }
+}
+
+impl WgpuRenderer {
+    pub async fn new(
+        window: &Window,
+        integer_scale: bool,
+        grid: bool,
+        present_mode: PresentMode,
+    ) -> Result<Self, GraphicsInitError> {
+        let window_size = window.inner_size();
         let instance = Instance::new(Backends::all());
 
         // TODO: The other safety, window must be valid for entire lifetime, is not really
         // guaranteed. See whether we can provide a better guarantee.
         // SAFETY: Called on main thread.
         let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance
-            .request_adapter(&RequestAdapterOptions {
+        let request_adapter = |force_fallback_adapter| {
+            instance.request_adapter(&RequestAdapterOptions {
                 power_preference: PowerPreference::default(),
                 compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
+                force_fallback_adapter,
             })
-            .await
-            .expect("Cannot find a graphics card for render!");
+        };
+        let adapter = match request_adapter(false).await {
+            Some(adapter) => adapter,
+            None => {
+                log::warn!("No graphics adapter found, trying a software one");
+                request_adapter(true)
+                    .await
+                    .ok_or(GraphicsInitError::NoAdapter)?
+            }
+        };
+        let adapter_info = adapter.get_info();
+        log::info!(
+            "Rendering on {} ({:?}, {:?})",
+            adapter_info.name,
+            adapter_info.device_type,
+            adapter_info.backend
+        );
 
         let (device, queue) = adapter
             .request_device(
@@ -107,21 +674,29 @@ impl Graphics {
                 None,
             )
             .await
-            .expect("Cannot find a graphics device to render on!");
+            .map_err(GraphicsInitError::NoDevice)?;
 
-        if window_size.width == 0 || window_size.height == 0 {
-            panic!(
-                "Window's width or height is 0, this is not allowed. Size = {} x {}",
-                window_size.width, window_size.height
+        // every backend supports Fifo
+        let present_mode = if surface
+            .get_supported_present_modes(&adapter)
+            .contains(&present_mode)
+        {
+            present_mode
+        } else {
+            log::warn!(
+                "Present mode {:?} is not supported here, falling back to Fifo",
+                present_mode
             );
-        }
+            PresentMode::Fifo
+        };
+        log::info!("Using present mode {:?}", present_mode);
 
         let config = SurfaceConfiguration {
             usage: TextureUsages::RENDER_ATTACHMENT,
             format: surface.get_supported_formats(&adapter)[0],
             width: window_size.width,
             height: window_size.height,
-            present_mode: PresentMode::Fifo,
+            present_mode,
             alpha_mode: CompositeAlphaMode::Auto,
         };
 
@@ -158,105 +733,59 @@ impl Graphics {
 
         let ratio_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Ratio Buffer"),
-            contents: bytemuck::cast_slice(&calculate_screen_ratio(&window_size)),
+            contents: bytemuck::cast_slice(&calculate_screen_transform(
+                &window_size,
+                SCREEN_PX_WIDTH as u32,
+                SCREEN_PX_HEIGHT as u32,
+                integer_scale,
+            )),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let grid_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Grid Buffer"),
+            contents: bytemuck::cast_slice(&grid_uniform(grid)),
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
         let ratio_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            entries: &[BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::VERTEX,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+            ],
             label: Some("ratio_bind_group_layout"),
         });
         let ratio_bind_group = device.create_bind_group(&BindGroupDescriptor {
             layout: &ratio_bind_group_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: ratio_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: ratio_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: grid_buffer.as_entire_binding(),
+                },
+            ],
             label: Some("ratio_bind_group"),
         });
 
-        let screen_texture_size = Extent3d {
-            width: SCREEN_PX_WIDTH as u32,
-            height: SCREEN_PX_HEIGHT as u32,
-            depth_or_array_layers: 1,
-        };
-        let screen_texture = device.create_texture(&TextureDescriptor {
-            label: Some("Screen Texture"),
-            size: screen_texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
-        });
-
-        let mut initial_pixels = std::iter::repeat(255u8)
-            .take(
-                (SCREEN_PX_STRIDE as u32 * screen_texture_size.width * screen_texture_size.height)
-                    as usize,
-            )
-            .collect::<Vec<_>>();
-
-        initial_pixels[0] = 0;
-        initial_pixels[1] = 0;
-        initial_pixels[2] = 0;
-
-        initial_pixels[4] = 255;
-        initial_pixels[5] = 0;
-        initial_pixels[6] = 0;
-
-        initial_pixels[8] = 0;
-        initial_pixels[9] = 255;
-        initial_pixels[10] = 0;
-
-        initial_pixels[12] = 0;
-        initial_pixels[13] = 0;
-        initial_pixels[14] = 255;
-
-        initial_pixels[(SCREEN_PX_STRIDE * screen_texture_size.width as usize) - 4] = 255;
-        initial_pixels[(SCREEN_PX_STRIDE * screen_texture_size.width as usize) - 3] = 255;
-        initial_pixels[(SCREEN_PX_STRIDE * screen_texture_size.width as usize) - 2] = 0;
-
-        initial_pixels[(SCREEN_PX_STRIDE
-            * screen_texture_size.width as usize
-            * screen_texture_size.height as usize)
-            - SCREEN_PX_STRIDE] = 0;
-        initial_pixels[(SCREEN_PX_STRIDE
-            * screen_texture_size.width as usize
-            * screen_texture_size.height as usize)
-            - 3] = 255;
-        initial_pixels[(SCREEN_PX_STRIDE
-            * screen_texture_size.width as usize
-            * screen_texture_size.height as usize)
-            - 2] = 255;
-
-        queue.write_texture(
-            ImageCopyTexture {
-                texture: &screen_texture,
-                mip_level: 0,
-                origin: Origin3d::ZERO,
-                aspect: TextureAspect::All,
-            },
-            &initial_pixels,
-            ImageDataLayout {
-                offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(
-                    SCREEN_PX_STRIDE as u32 * screen_texture_size.width,
-                ),
-                rows_per_image: std::num::NonZeroU32::new(screen_texture_size.height),
-            },
-            screen_texture_size,
-        );
-
-        let screen_texture_view = screen_texture.create_view(&TextureViewDescriptor::default());
         let screen_texture_sampler = device.create_sampler(&SamplerDescriptor {
             address_mode_u: AddressMode::ClampToEdge,
             address_mode_v: AddressMode::ClampToEdge,
@@ -289,20 +818,14 @@ impl Graphics {
                     },
                 ],
             });
-        let screen_texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("screen_texture_bind_group"),
-            layout: &screen_texture_bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(&screen_texture_view),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::Sampler(&screen_texture_sampler),
-                },
-            ],
-        });
+        let (screen_texture_size, screen_texture, screen_texture_bind_group) =
+            create_screen_texture(
+                &device,
+                &screen_texture_bind_group_layout,
+                &screen_texture_sampler,
+                SCREEN_PX_WIDTH as u32,
+                SCREEN_PX_HEIGHT as u32,
+            );
 
         let shader = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Shader"),
@@ -313,138 +836,237 @@ impl Graphics {
             bind_group_layouts: &[&ratio_bind_group_layout, &screen_texture_bind_group_layout],
             push_constant_ranges: &[],
         });
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[vertex_buffer_layout],
-            },
-            fragment: Some(FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(ColorTargetState {
-                    format: config.format,
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: ColorWrites::ALL,
-                })],
-            }),
-            primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: FrontFace::Ccw,
-                cull_mode: Some(Face::Back),
-                polygon_mode: PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
+        let create_render_pipeline = |label, blend| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&render_pipeline_layout),
+                vertex: VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: std::slice::from_ref(&vertex_buffer_layout),
+                },
+                fragment: Some(FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(ColorTargetState {
+                        format: config.format,
+                        blend: Some(blend),
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: FrontFace::Ccw,
+                    cull_mode: Some(Face::Back),
+                    polygon_mode: PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: None,
+                multisample: MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+            })
+        };
+        let render_pipeline = create_render_pipeline("Render Pipeline", BlendState::REPLACE);
+        let hud_pipeline = create_render_pipeline("HUD Pipeline", BlendState::ALPHA_BLENDING);
+
+        let hud_transform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("HUD Transform Buffer"),
+            contents: bytemuck::cast_slice(&[0f32; 4]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        // the lines of the grid would cross the text
+        let hud_grid_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("HUD Grid Buffer"),
+            contents: bytemuck::cast_slice(&grid_uniform(false)),
+            usage: BufferUsages::UNIFORM,
+        });
+        let hud_transform_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &ratio_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: hud_transform_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: hud_grid_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("hud_transform_bind_group"),
         });
 
-        Self {
+        Ok(Self {
             surface,
             device,
             queue,
             config,
             window_size,
             render_pipeline,
+            hud_pipeline,
+            hud_transform_buffer,
+            hud_transform_bind_group,
+            hud_texture: None,
+            hud_visible: false,
             vertex_buffer,
             index_buffer,
             num_indices,
             ratio_buffer,
+            grid_buffer,
             ratio_bind_group,
             screen_texture_size,
             screen_texture,
             screen_texture_bind_group,
-            screen_update_receiver,
-        }
+            screen_texture_bind_group_layout,
+            screen_texture_sampler,
+            integer_scale,
+            #[cfg(feature = "egui")]
+            egui_renderer: None,
+            #[cfg(feature = "egui")]
+            overlay: None,
+            #[cfg(feature = "egui")]
+            overlay_textures: egui::TexturesDelta::default(),
+        })
     }
 
-    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
-        if new_size.width == 0 || new_size.height == 0 {
-            log::warn!(
-                "window inner size cannot be 0! Current size = {} x {}",
-                new_size.width,
-                new_size.height
-            );
-            return;
-        }
-
-        self.window_size = new_size;
-        self.config.width = new_size.width;
-        self.config.height = new_size.height;
-
-        self.surface.configure(&self.device, &self.config);
+    fn update_screen_transform(&self) {
         self.queue.write_buffer(
             &self.ratio_buffer,
             0,
-            bytemuck::cast_slice(&calculate_screen_ratio(&self.window_size)),
+            bytemuck::cast_slice(&calculate_screen_transform(
+                &self.window_size,
+                self.screen_texture_size.width,
+                self.screen_texture_size.height,
+                self.integer_scale,
+            )),
         );
     }
 
-    fn handle_screen_updates(&mut self) {
-        // TODO: Can this be improved for performance?
-        let mut final_update = None;
-
-        while let Ok(update) = self.screen_update_receiver.try_recv() {
-            final_update = Some(update);
+    fn update_hud_transform(&self) {
+        if let Some((hud_size, _, _)) = &self.hud_texture {
+            self.queue.write_buffer(
+                &self.hud_transform_buffer,
+                0,
+                bytemuck::cast_slice(&calculate_hud_transform(
+                    &self.window_size,
+                    &PhysicalSize::new(hud_size.width, hud_size.height),
+                )),
+            );
         }
+    }
 
-        if let Some(update) = final_update {
-            let mut final_pixels: Vec<u8> = Vec::with_capacity(
-                (SCREEN_PX_STRIDE as u32
-                    * self.screen_texture_size.width
-                    * self.screen_texture_size.height) as usize,
-            );
-            update.iter().for_each(|pixels| {
-                let mut mask = 1u64 << 63;
-                while mask > 0 {
-                    if pixels & mask != 0 {
-                        final_pixels.push(255);
-                        final_pixels.push(255);
-                        final_pixels.push(255);
-                        final_pixels.push(255);
-                    } else {
-                        final_pixels.push(0);
-                        final_pixels.push(0);
-                        final_pixels.push(0);
-                        final_pixels.push(255);
-                    }
-                    mask >>= 1;
-                }
-            });
+    // uploads what the overlay needs for this frame, returning what to draw in the render pass
+    // and the textures to free after it, when the overlay is shown
+    #[cfg(feature = "egui")]
+    fn prepare_overlay(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Option<(
+        Vec<egui::ClippedPrimitive>,
+        egui_wgpu::renderer::ScreenDescriptor,
+        Vec<egui::TextureId>,
+    )> {
+        let overlay = self.overlay.take()?;
+        let renderer = self.egui_renderer.get_or_insert_with(|| {
+            egui_wgpu::Renderer::new(&self.device, self.config.format, None, 1)
+        });
 
-            self.queue.write_texture(
-                ImageCopyTexture {
-                    texture: &self.screen_texture,
-                    mip_level: 0,
-                    origin: Origin3d::ZERO,
-                    aspect: TextureAspect::All,
-                },
-                &final_pixels,
-                ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: std::num::NonZeroU32::new(
-                        SCREEN_PX_STRIDE as u32 * self.screen_texture_size.width,
-                    ),
-                    rows_per_image: std::num::NonZeroU32::new(self.screen_texture_size.height),
+        let textures = std::mem::take(&mut self.overlay_textures);
+        textures.set.iter().for_each(|(id, delta)| {
+            renderer.update_texture(&self.device, &self.queue, *id, delta);
+        });
+        let screen = egui_wgpu::renderer::ScreenDescriptor {
+            size_in_pixels: [self.config.width, self.config.height],
+            pixels_per_point: overlay.pixels_per_point,
+        };
+        // the overlay has no paint callbacks, so there are no command buffers of its own
+        renderer.update_buffers(
+            &self.device,
+            &self.queue,
+            encoder,
+            &overlay.primitives,
+            &screen,
+        );
+
+        Some((overlay.primitives, screen, textures.free))
+    }
+}
+
+impl Renderer for WgpuRenderer {
+    fn set_screen_size(&mut self, width: u32, height: u32) {
+        (
+            self.screen_texture_size,
+            self.screen_texture,
+            self.screen_texture_bind_group,
+        ) = create_screen_texture(
+            &self.device,
+            &self.screen_texture_bind_group_layout,
+            &self.screen_texture_sampler,
+            width,
+            height,
+        );
+        // the integer scale depends on the resolution
+        self.update_screen_transform();
+    }
+
+    fn write_screen_rows(&mut self, rows: Range<usize>, pixels: &[u8]) {
+        self.queue.write_texture(
+            ImageCopyTexture {
+                texture: &self.screen_texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: 0,
+                    y: rows.start as u32,
+                    z: 0,
                 },
-                self.screen_texture_size,
-            );
-        }
+                aspect: TextureAspect::All,
+            },
+            pixels,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(
+                    SCREEN_PX_STRIDE as u32 * self.screen_texture_size.width,
+                ),
+                rows_per_image: std::num::NonZeroU32::new(rows.len() as u32),
+            },
+            Extent3d {
+                height: rows.len() as u32,
+                ..self.screen_texture_size
+            },
+        );
     }
 
-    pub fn render(&mut self) -> Result<(), SurfaceError> {
-        self.handle_screen_updates();
+    fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.window_size = size;
+        self.config.width = size.width;
+        self.config.height = size.height;
 
-        let output = self.surface.get_current_texture()?;
+        self.surface.configure(&self.device, &self.config);
+        self.update_screen_transform();
+        self.update_hud_transform();
+    }
+
+    fn set_grid(&mut self, grid: bool) {
+        self.queue.write_buffer(
+            &self.grid_buffer,
+            0,
+            bytemuck::cast_slice(&grid_uniform(grid)),
+        );
+    }
+
+    fn present(&mut self, background: [u8; SCREEN_PX_STRIDE]) -> Result<(), SurfaceError> {
+        let output = match self.surface.get_current_texture() {
+            Err(err) if render_error_action(&err, 0) == RenderErrorAction::Reconfigure => {
+                self.surface.configure(&self.device, &self.config);
+                self.surface.get_current_texture()
+            }
+            result => result,
+        }?;
 
         let view = output
             .texture
@@ -455,6 +1077,8 @@ impl Graphics {
             .create_command_encoder(&CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
+        #[cfg(feature = "egui")]
+        let overlay = self.prepare_overlay(&mut encoder);
 
         {
             let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
@@ -463,10 +1087,11 @@ impl Graphics {
                     view: &view,
                     resolve_target: None,
                     ops: Operations {
+                        // the bars around the screen blend in with its background
                         load: LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
+                            r: background[0] as f64 / 255.0,
+                            g: background[1] as f64 / 255.0,
+                            b: background[2] as f64 / 255.0,
                             a: 1.0,
                         }),
                         store: true,
@@ -481,11 +1106,279 @@ impl Graphics {
             render_pass.set_index_buffer(self.index_buffer.slice(..), IndexFormat::Uint16);
             render_pass.set_bind_group(1, &self.screen_texture_bind_group, &[]);
             render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+
+            if let Some((_, _, hud_texture_bind_group)) =
+                self.hud_texture.as_ref().filter(|_| self.hud_visible)
+            {
+                render_pass.set_pipeline(&self.hud_pipeline);
+                render_pass.set_bind_group(0, &self.hud_transform_bind_group, &[]);
+                render_pass.set_bind_group(1, hud_texture_bind_group, &[]);
+                render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+            }
+
+            #[cfg(feature = "egui")]
+            if let (Some((primitives, screen, _)), Some(egui_renderer)) =
+                (&overlay, &self.egui_renderer)
+            {
+                egui_renderer.render(&mut render_pass, primitives, screen);
+            }
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
 
+        #[cfg(feature = "egui")]
+        if let (Some((_, _, freed)), Some(egui_renderer)) = (overlay, &mut self.egui_renderer) {
+            freed.iter().for_each(|id| egui_renderer.free_texture(id));
+        }
+
         Ok(())
     }
+
+    fn present_mode(&self) -> PresentMode {
+        self.config.present_mode
+    }
+
+    fn set_hud(&mut self, hud: Option<&HudImage>) {
+        self.hud_visible = hud.is_some();
+        let hud = match hud {
+            Some(hud) => hud,
+            None => return,
+        };
+
+        let resized = self.hud_texture.as_ref().map_or(true, |(size, _, _)| {
+            (size.width, size.height) != (hud.width, hud.height)
+        });
+        if resized {
+            self.hud_texture = Some(create_screen_texture(
+                &self.device,
+                &self.screen_texture_bind_group_layout,
+                &self.screen_texture_sampler,
+                hud.width,
+                hud.height,
+            ));
+            self.update_hud_transform();
+        }
+
+        if let Some((size, texture, _)) = &self.hud_texture {
+            self.queue.write_texture(
+                ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                &hud.pixels,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(SCREEN_PX_STRIDE as u32 * hud.width),
+                    rows_per_image: std::num::NonZeroU32::new(hud.height),
+                },
+                *size,
+            );
+        }
+    }
+
+    #[cfg(feature = "egui")]
+    fn set_overlay(&mut self, overlay: Option<OverlayFrame>) {
+        self.overlay = overlay.map(|mut overlay| {
+            self.overlay_textures
+                .append(std::mem::take(&mut overlay.textures_delta));
+            overlay
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        cpu::{HIRES_SCREEN_HEIGHT, LORES_SCREEN_HEIGHT, LORES_SCREEN_WIDTH},
+        frontend::ScreenSink,
+        palette::PALETTES,
+    };
+
+    // keeps the pixels of the rows written to it, where the test can see them
+    struct MemoryRenderer {
+        pixels: Rc<RefCell<Vec<u8>>>,
+        width: usize,
+    }
+
+    impl Renderer for MemoryRenderer {
+        fn set_screen_size(&mut self, width: u32, height: u32) {
+            self.width = width as usize;
+            // stands out from every palette color, for the rows that are not written again
+            *self.pixels.borrow_mut() = vec![0xAB; SCREEN_PX_STRIDE * (width * height) as usize];
+        }
+
+        fn write_screen_rows(&mut self, rows: Range<usize>, pixels: &[u8]) {
+            let row_bytes = SCREEN_PX_STRIDE * self.width;
+            self.pixels.borrow_mut()[rows.start * row_bytes..rows.end * row_bytes]
+                .copy_from_slice(pixels);
+        }
+
+        fn resize(&mut self, _size: PhysicalSize<u32>) {}
+
+        fn set_grid(&mut self, _grid: bool) {}
+
+        fn present(&mut self, _background: [u8; SCREEN_PX_STRIDE]) -> Result<(), SurfaceError> {
+            Ok(())
+        }
+
+        fn present_mode(&self) -> PresentMode {
+            PresentMode::Fifo
+        }
+
+        fn set_hud(&mut self, _hud: Option<&HudImage>) {}
+    }
+
+    fn memory_graphics() -> (Graphics, Rc<RefCell<Vec<u8>>>) {
+        let pixels = Rc::new(RefCell::new(Vec::new()));
+        let mut renderer = MemoryRenderer {
+            pixels: pixels.clone(),
+            width: 0,
+        };
+        renderer.set_screen_size(LORES_SCREEN_WIDTH as u32, LORES_SCREEN_HEIGHT as u32);
+        let graphics = Graphics::with_renderer(
+            Box::new(renderer),
+            PALETTES[0].1,
+            None,
+            false,
+            PhysicalSize::new(640, 320),
+        );
+        (graphics, pixels)
+    }
+
+    // what uploading every row of the screen gives
+    fn full_upload(graphics: &Graphics) -> Vec<u8> {
+        let screen = graphics.screen();
+        let mut pixels = vec![0; SCREEN_PX_STRIDE * screen.width() * screen.height()];
+        screen.write_rgba(0..screen.height(), graphics.plane_colors(), &mut pixels);
+        pixels
+    }
+
+    fn send_screen(graphics: &mut Graphics, screen: &CpuScreenMem) {
+        graphics.latest_screen().update(screen);
+        graphics.handle_screen_updates();
+    }
+
+    // an 8x3 sprite drawn at x, y
+    fn draw_sprite(screen: &mut CpuScreenMem, x: usize, y: usize) {
+        [0xFFu128, 0x81, 0xFF]
+            .iter()
+            .enumerate()
+            .for_each(|(row, bits)| {
+                screen.planes[0][y + row] ^= (bits << (u128::BITS - 8)) >> x;
+            });
+    }
+
+    #[test]
+    fn finds_runs_of_changed_rows() {
+        let before = CpuScreenMem::new(false);
+        let mut after = before;
+        draw_sprite(&mut after, 0, 3);
+        after.planes[1][10] = 1 << (u128::BITS - 1);
+        // past the edge of the lores screen
+        after.planes[0][20] = 1;
+
+        assert_eq!(changed_row_ranges(Some(&before), &after), [3..6, 10..11]);
+        assert_eq!(changed_row_ranges(Some(&after), &after), []);
+        // a single range of every row
+        let every_row = |height| {
+            [Range {
+                start: 0,
+                end: height,
+            }]
+        };
+        assert_eq!(
+            changed_row_ranges(None, &after),
+            every_row(LORES_SCREEN_HEIGHT)
+        );
+        assert_eq!(
+            changed_row_ranges(Some(&before), &CpuScreenMem::new(true)),
+            every_row(HIRES_SCREEN_HEIGHT)
+        );
+    }
+
+    #[test]
+    fn uploading_changed_rows_matches_full_upload() {
+        let (mut graphics, pixels) = memory_graphics();
+        assert_eq!(*pixels.borrow(), full_upload(&graphics));
+
+        let mut screen = CpuScreenMem::new(false);
+        draw_sprite(&mut screen, 2, 4);
+        send_screen(&mut graphics, &screen);
+        assert_eq!(*pixels.borrow(), full_upload(&graphics));
+
+        // erases the first sprite, and draws one that is cut off at the bottom
+        draw_sprite(&mut screen, 2, 4);
+        draw_sprite(&mut screen, 60, 29);
+        send_screen(&mut graphics, &screen);
+        assert_eq!(*pixels.borrow(), full_upload(&graphics));
+
+        let mut screen = CpuScreenMem::new(true);
+        draw_sprite(&mut screen, 100, 50);
+        send_screen(&mut graphics, &screen);
+        assert_eq!(*pixels.borrow(), full_upload(&graphics));
+
+        graphics.set_palette(PALETTES[1].1);
+        assert_eq!(*pixels.borrow(), full_upload(&graphics));
+
+        screen.planes[1][0] = !0;
+        send_screen(&mut graphics, &screen);
+        assert_eq!(*pixels.borrow(), full_upload(&graphics));
+
+        send_screen(&mut graphics, &CpuScreenMem::new(false));
+        assert_eq!(*pixels.borrow(), full_upload(&graphics));
+    }
+
+    #[test]
+    fn reconfigures_lost_and_outdated_surfaces() {
+        assert_eq!(
+            render_error_action(&SurfaceError::Lost, 1),
+            RenderErrorAction::Reconfigure
+        );
+        assert_eq!(
+            render_error_action(&SurfaceError::Outdated, 1),
+            RenderErrorAction::Reconfigure
+        );
+    }
+
+    #[test]
+    fn skips_frame_on_timeout() {
+        assert_eq!(
+            render_error_action(&SurfaceError::Timeout, 1),
+            RenderErrorAction::Skip
+        );
+    }
+
+    #[test]
+    fn exits_when_out_of_memory() {
+        assert_eq!(
+            render_error_action(&SurfaceError::OutOfMemory, 1),
+            RenderErrorAction::Exit
+        );
+    }
+
+    #[test]
+    fn gives_up_after_too_many_failed_frames() {
+        assert_eq!(
+            render_error_action(&SurfaceError::Outdated, MAX_FAILED_FRAMES - 1),
+            RenderErrorAction::Reconfigure
+        );
+        [
+            SurfaceError::Lost,
+            SurfaceError::Outdated,
+            SurfaceError::Timeout,
+        ]
+        .iter()
+        .for_each(|error| {
+            assert_eq!(
+                render_error_action(error, MAX_FAILED_FRAMES),
+                RenderErrorAction::Exit
+            )
+        });
+    }
 }
@@ -0,0 +1,97 @@
+use crate::cpu::{text_glyph, CpuState, TEXT_CHAR_HEIGHT, TEXT_CHAR_WIDTH};
+
+const TEXT_COLOR: [u8; 4] = [255, 255, 255, 255];
+// dark enough to read the text over any palette, while the game still shows through
+const BACKGROUND_COLOR: [u8; 4] = [0, 0, 0, 176];
+// around the text, in font pixels
+const PADDING: usize = 2;
+const LINE_HEIGHT: usize = TEXT_CHAR_HEIGHT + 2;
+const REGISTERS_PER_LINE: usize = 4;
+// the HUD covers at most half of the window width and height
+const MAX_WINDOW_SHARE: u32 = 2;
+
+// the registers and timers, drawn in the top left corner of the window
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HudImage {
+    pub width: u32,
+    pub height: u32,
+    // RGBA, with the alpha to blend it over the screen with
+    pub pixels: Vec<u8>,
+}
+
+pub fn hud_lines(state: &CpuState) -> Vec<String> {
+    let mut lines = state
+        .variable_registers
+        .chunks(REGISTERS_PER_LINE)
+        .enumerate()
+        .map(|(line, registers)| {
+            registers
+                .iter()
+                .enumerate()
+                .map(|(i, value)| format!("V{:X} {:02X}", line * REGISTERS_PER_LINE + i, value))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>();
+    lines.push(format!(
+        "I {:04X} PC {:04X}",
+        state.index_register, state.program_counter
+    ));
+    lines.push(format!(
+        "DT {:02X} ST {:02X}",
+        state.delay_timer, state.sound_timer
+    ));
+    lines
+}
+
+// the size of the HUD in font pixels
+fn font_size(lines: &[String]) -> (usize, usize) {
+    let columns = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+    (
+        (columns * TEXT_CHAR_WIDTH).saturating_sub(1) + 2 * PADDING,
+        (lines.len() * LINE_HEIGHT).saturating_sub(LINE_HEIGHT - TEXT_CHAR_HEIGHT) + 2 * PADDING,
+    )
+}
+
+// Each font pixel becomes a scale x scale block of window pixels, which keeps the text sharp. The
+// scale is the largest that keeps most of the game visible, but at least 1.
+pub fn hud_scale(lines: &[String], window_width: u32, window_height: u32) -> u32 {
+    let (font_width, font_height) = font_size(lines);
+    (window_width / MAX_WINDOW_SHARE / font_width as u32)
+        .min(window_height / MAX_WINDOW_SHARE / font_height as u32)
+        .max(1)
+}
+
+pub fn draw_hud(lines: &[String], scale: u32) -> HudImage {
+    let (font_width, font_height) = font_size(lines);
+    let scale = scale as usize;
+    let (width, height) = (font_width * scale, font_height * scale);
+
+    let mut pixels = BACKGROUND_COLOR.repeat(width * height);
+    lines.iter().enumerate().for_each(|(line, text)| {
+        text.chars().enumerate().for_each(|(column, c)| {
+            let left = PADDING + column * TEXT_CHAR_WIDTH;
+            let top = PADDING + line * LINE_HEIGHT;
+            text_glyph(c).iter().enumerate().for_each(|(row, bits)| {
+                (0..TEXT_CHAR_WIDTH - 1)
+                    .filter(|x| bits & (0x80 >> x) != 0)
+                    .for_each(|x| {
+                        // each font pixel becomes a scale x scale block
+                        (0..scale).for_each(|dy| {
+                            let y = (top + row) * scale + dy;
+                            let start = 4 * (y * width + (left + x) * scale);
+                            pixels[start..start + 4 * scale]
+                                .chunks_exact_mut(4)
+                                .for_each(|pixel| pixel.copy_from_slice(&TEXT_COLOR));
+                        });
+                    });
+            });
+        });
+    });
+
+    HudImage {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    }
+}
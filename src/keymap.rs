@@ -0,0 +1,372 @@
+use std::{collections::HashMap, fmt, hash::Hash, path::Path};
+
+#[cfg(feature = "gamepad")]
+use gilrs::Button;
+use once_cell::sync::Lazy;
+use serde::{
+    de::{DeserializeOwned, IgnoredAny, MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use winit::event::VirtualKeyCode;
+
+const CHIP8_KEY_COUNT: u8 = 16;
+
+// the left side of a QWERTY keyboard
+static DEFAULT_KEYS: Lazy<HashMap<VirtualKeyCode, u8>> = Lazy::new(|| {
+    HashMap::from([
+        (VirtualKeyCode::Key1, 0x1),
+        (VirtualKeyCode::Key2, 0x2),
+        (VirtualKeyCode::Key3, 0x3),
+        (VirtualKeyCode::Key4, 0xC),
+        (VirtualKeyCode::Q, 0x4),
+        (VirtualKeyCode::W, 0x5),
+        (VirtualKeyCode::E, 0x6),
+        (VirtualKeyCode::R, 0xD),
+        (VirtualKeyCode::A, 0x7),
+        (VirtualKeyCode::S, 0x8),
+        (VirtualKeyCode::D, 0x9),
+        (VirtualKeyCode::F, 0xE),
+        (VirtualKeyCode::Z, 0xA),
+        (VirtualKeyCode::X, 0x0),
+        (VirtualKeyCode::C, 0xB),
+        (VirtualKeyCode::V, 0xF),
+    ])
+});
+
+// The keys that the window handles itself, with what they do. The keymap is looked up first, so a
+// keymap cannot bind them. O is left out, as only Ctrl+O opens a program.
+const HOTKEYS: [(VirtualKeyCode, &str); 19] = [
+    (VirtualKeyCode::P, "pause"),
+    (VirtualKeyCode::M, "mute"),
+    (VirtualKeyCode::H, "HUD"),
+    (VirtualKeyCode::Tab, "fast-forward"),
+    (VirtualKeyCode::Back, "rewind"),
+    (VirtualKeyCode::Home, "program list"),
+    (VirtualKeyCode::Escape, "quit"),
+    (VirtualKeyCode::F1, "debugger panel"),
+    (VirtualKeyCode::F2, "screenshot"),
+    (VirtualKeyCode::F3, "palette"),
+    (VirtualKeyCode::F4, "grid"),
+    (VirtualKeyCode::F5, "reset"),
+    (VirtualKeyCode::F6, "save state"),
+    (VirtualKeyCode::F7, "recording"),
+    (VirtualKeyCode::F8, "next frame"),
+    (VirtualKeyCode::F9, "load state"),
+    (VirtualKeyCode::F10, "step"),
+    (VirtualKeyCode::F11, "fullscreen"),
+    (VirtualKeyCode::F12, "state dump"),
+];
+
+// the d-pad covers the 2/4/6/8 movement that most games use
+#[cfg(feature = "gamepad")]
+static DEFAULT_BUTTONS: Lazy<HashMap<Button, u8>> = Lazy::new(|| {
+    HashMap::from([
+        (Button::DPadUp, 0x2),
+        (Button::DPadLeft, 0x4),
+        (Button::DPadRight, 0x6),
+        (Button::DPadDown, 0x8),
+        (Button::South, 0x5),
+        (Button::East, 0x6),
+        (Button::West, 0x4),
+        (Button::North, 0x8),
+        (Button::LeftTrigger, 0xA),
+        (Button::RightTrigger, 0xB),
+        (Button::Start, 0xF),
+        (Button::Select, 0xE),
+    ])
+});
+
+// the keys and gamepad buttons that press each CHIP-8 key
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    pub keys: HashMap<VirtualKeyCode, u8>,
+    #[cfg(feature = "gamepad")]
+    pub buttons: HashMap<Button, u8>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            keys: DEFAULT_KEYS.clone(),
+            #[cfg(feature = "gamepad")]
+            buttons: DEFAULT_BUTTONS.clone(),
+        }
+    }
+}
+
+impl Keymap {
+    // Read as TOML when the file name ends with .toml, and as JSON otherwise. A CHIP-8 key named
+    // in the file is only bound to the keys in the file, the other CHIP-8 keys keep their default
+    // keys. Returns every problem with the file, one per line.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|err| format!("{:?}", err))?;
+        let entries = if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"))
+        {
+            toml::from_str::<Entries>(&contents).map_err(|err| err.to_string())?
+        } else {
+            serde_json::from_str::<Entries>(&contents).map_err(|err| err.to_string())?
+        };
+        Self::from_entries(entries, &contents)
+    }
+
+    fn from_entries(entries: Entries, contents: &str) -> Result<Self, String> {
+        let mut errors = Vec::new();
+        let mut key_entries = Vec::new();
+        let mut button_entries = Vec::new();
+        for (name, entry) in entries.0 {
+            match (name.as_str(), entry) {
+                ("Gamepad", Entry::Table(entries)) => button_entries.extend(entries.0),
+                ("Gamepad", _) => errors.push(located(
+                    contents,
+                    &name,
+                    "expected Gamepad to be a table of button names",
+                )),
+                (_, entry) => key_entries.push((name, entry)),
+            }
+        }
+
+        #[cfg(not(feature = "gamepad"))]
+        if !button_entries.is_empty() {
+            log::warn!("This build has no gamepad support, ignoring the Gamepad table");
+        }
+        let keymap = Self {
+            keys: remap(&DEFAULT_KEYS, key_entries, contents, &mut errors),
+            #[cfg(feature = "gamepad")]
+            buttons: remap(&DEFAULT_BUTTONS, button_entries, contents, &mut errors),
+        };
+
+        HOTKEYS
+            .iter()
+            .filter(|(key, _)| keymap.keys.contains_key(key))
+            .for_each(|(key, action)| {
+                let name = format!("{:?}", key);
+                let message = format!("{} is the {} hotkey, bind another key", name, action);
+                errors.push(located(contents, &name, &message));
+            });
+
+        // A default key that the file binds to another CHIP-8 key leaves its old one without a
+        // key. The gamepad leaves some out by default, so only the keyboard has to cover them.
+        (0..CHIP8_KEY_COUNT)
+            .filter(|chip8_key| !keymap.keys.values().any(|value| value == chip8_key))
+            .for_each(|chip8_key| {
+                let taken_from = DEFAULT_KEYS
+                    .iter()
+                    .filter(|(_, value)| **value == chip8_key)
+                    .map(|(key, _)| format!("{:?}", key))
+                    .collect::<Vec<_>>();
+                taken_from.iter().for_each(|name| {
+                    errors.push(located(
+                        contents,
+                        name,
+                        &format!(
+                            "{} was the only key for CHIP-8 key {:X}, bind another one to it",
+                            name, chip8_key
+                        ),
+                    ))
+                });
+            });
+
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
+        Ok(keymap)
+    }
+}
+
+// the entries in the order of the file, including any names that appear twice
+struct Entries(Vec<(String, Entry)>);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    Number(u64),
+    // hex, with or without 0x
+    Text(String),
+    Table(Entries),
+    // reported along with the other errors, on the line of its name
+    Other(IgnoredAny),
+}
+
+impl<'de> Deserialize<'de> for Entries {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor;
+
+        impl<'de> Visitor<'de> for EntriesVisitor {
+            type Value = Entries;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a table of key names to CHIP-8 keys")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Entries, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Entries(entries))
+            }
+        }
+
+        deserializer.deserialize_map(EntriesVisitor)
+    }
+}
+
+// a CHIP-8 key named in the entries loses its default bindings, the other keys keep theirs
+fn remap<K: DeserializeOwned + Copy + Eq + Hash>(
+    defaults: &HashMap<K, u8>,
+    entries: Vec<(String, Entry)>,
+    contents: &str,
+    errors: &mut Vec<String>,
+) -> HashMap<K, u8> {
+    let mut remapped = HashMap::new();
+    for (name, entry) in entries {
+        // the names are the ones that serde uses for the winit and gilrs enums
+        let key = serde_json::from_value::<K>(serde_json::Value::String(name.clone()));
+        let chip8_key = match &entry {
+            Entry::Number(number) => Some(*number),
+            Entry::Text(hex) => u64::from_str_radix(hex.trim_start_matches("0x"), 16).ok(),
+            Entry::Table(_) | Entry::Other(_) => None,
+        };
+        let error = match (key, chip8_key) {
+            (Err(_), _) => format!("{:?} is not a key or button name", name),
+            (Ok(key), Some(chip8_key)) if chip8_key < CHIP8_KEY_COUNT as u64 => {
+                match remapped.insert(key, chip8_key as u8) {
+                    Some(_) => format!("{:?} is bound more than once", name),
+                    None => continue,
+                }
+            }
+            _ => format!("{:?} is not bound to a CHIP-8 key between 0 and F", name),
+        };
+        errors.push(located(contents, &name, &error));
+    }
+
+    let mut keymap = defaults.clone();
+    keymap.retain(|_, chip8_key| !remapped.values().any(|remapped| remapped == chip8_key));
+    keymap.extend(remapped);
+    keymap
+}
+
+// prefixes the message with the lines that bind the name, if it can find them
+fn located(contents: &str, name: &str, message: &str) -> String {
+    let lines = lines_binding(contents, name)
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>();
+    match lines.len() {
+        0 => message.to_string(),
+        1 => format!("line {}: {}", lines[0], message),
+        _ => format!("lines {}: {}", lines.join(", "), message),
+    }
+}
+
+// the lines where the name is followed by = or :, quoted as in JSON and TOML, or bare as in TOML
+fn lines_binding(contents: &str, name: &str) -> Vec<usize> {
+    let quoted_name = format!("\"{}\"", name);
+    let is_binding = |rest: &str| rest.trim_start().starts_with([':', '=']);
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            let bare = line.trim_start().strip_prefix(name).is_some_and(is_binding);
+            let quoted = line
+                .match_indices(&quoted_name)
+                .any(|(i, _)| is_binding(&line[i + quoted_name.len()..]));
+            bare || quoted
+        })
+        .map(|(i, _)| i + 1)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_toml(contents: &str) -> Result<Keymap, String> {
+        Keymap::from_entries(toml::from_str(contents).unwrap(), contents)
+    }
+
+    fn from_json(contents: &str) -> Result<Keymap, String> {
+        Keymap::from_entries(serde_json::from_str(contents).unwrap(), contents)
+    }
+
+    #[test]
+    fn reads_hex_as_text_or_number() {
+        // swaps the CHIP-8 keys of Q and W
+        let keymap = from_toml("Q = 5\nW = \"0x4\"").unwrap();
+        assert_eq!(keymap.keys[&VirtualKeyCode::Q], 0x5);
+        assert_eq!(keymap.keys[&VirtualKeyCode::W], 0x4);
+
+        let keymap = from_json(r#"{ "Q": "5", "W": 4 }"#).unwrap();
+        assert_eq!(keymap.keys[&VirtualKeyCode::Q], 0x5);
+        assert_eq!(keymap.keys[&VirtualKeyCode::W], 0x4);
+    }
+
+    #[test]
+    fn other_chip8_keys_keep_their_default_keys() {
+        let keymap = from_toml("Q = 5\nW = 4").unwrap();
+        assert_eq!(keymap.keys.len(), DEFAULT_KEYS.len());
+        assert_eq!(keymap.keys[&VirtualKeyCode::X], 0x0);
+    }
+
+    #[test]
+    fn reports_key_bound_more_than_once() {
+        let errors = from_json("{\n  \"Q\": 4,\n  \"Q\": 4\n}").unwrap_err();
+        assert_eq!(errors, "lines 2, 3: \"Q\" is bound more than once");
+    }
+
+    #[test]
+    fn reports_chip8_key_left_without_a_key() {
+        // X was the only key for 0
+        let errors = from_toml("# moved\nX = 0x1").unwrap_err();
+        assert_eq!(
+            errors,
+            "line 2: X was the only key for CHIP-8 key 0, bind another one to it"
+        );
+    }
+
+    #[test]
+    fn reports_every_problem_on_its_line() {
+        let errors = from_toml("Q = 4\n\nNotAKey = 0x1\nW = 16\nE = \"G\"\nR = true").unwrap_err();
+        assert_eq!(
+            errors.lines().collect::<Vec<_>>(),
+            [
+                "line 3: \"NotAKey\" is not a key or button name",
+                "line 4: \"W\" is not bound to a CHIP-8 key between 0 and F",
+                "line 5: \"E\" is not bound to a CHIP-8 key between 0 and F",
+                "line 6: \"R\" is not bound to a CHIP-8 key between 0 and F",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_hotkeys() {
+        let errors = from_json("{\n  \"P\": \"D\",\n  \"F5\": 1,\n  \"R\": \"D\"\n}").unwrap_err();
+        assert_eq!(
+            errors.lines().collect::<Vec<_>>(),
+            [
+                "line 2: P is the pause hotkey, bind another key",
+                "line 3: F5 is the reset hotkey, bind another key",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_dvorak_keymap() {
+        let keymap =
+            Keymap::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("keymaps/dvorak.toml"))
+                .unwrap();
+        assert_eq!(keymap.keys.len(), CHIP8_KEY_COUNT as usize);
+        assert_eq!(keymap.keys[&VirtualKeyCode::Apostrophe], 0x4);
+        assert_eq!(keymap.keys[&VirtualKeyCode::Semicolon], 0xA);
+        assert_eq!(keymap.keys[&VirtualKeyCode::K], 0xF);
+        // P pauses
+        assert_eq!(keymap.keys[&VirtualKeyCode::Y], 0xD);
+        assert!(!keymap.keys.contains_key(&VirtualKeyCode::P));
+        // QWERTY keys that Dvorak puts elsewhere
+        assert!(!keymap.keys.contains_key(&VirtualKeyCode::W));
+        #[cfg(feature = "gamepad")]
+        assert_eq!(keymap.buttons, *DEFAULT_BUTTONS);
+    }
+}
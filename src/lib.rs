@@ -1,3 +1,42 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod audio;
 pub mod cpu;
+pub mod debugger;
+#[cfg(feature = "dialog")]
+pub mod dialog;
+pub mod disasm;
+pub mod frontend;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
+#[cfg(feature = "gui")]
 pub mod graphics;
+#[cfg(feature = "gui")]
+pub mod hud;
+#[cfg(feature = "gui")]
+pub mod keymap;
+pub mod opcode;
+#[cfg(feature = "egui")]
+pub mod overlay;
+pub mod palette;
+pub mod quirks;
+#[cfg(feature = "gui")]
+pub mod recorder;
+#[cfg(feature = "std")]
+pub mod rom_picker;
+#[cfg(feature = "std")]
+pub mod savestate;
+#[cfg(feature = "softbuffer")]
+pub mod software_renderer;
+pub mod sync;
+#[cfg(feature = "tui")]
+pub mod terminal;
+#[cfg(feature = "std")]
 pub mod timers;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "web")]
+pub mod web;
@@ -1,97 +1,721 @@
-use std::{collections::HashMap, fs::File, io::Read, sync::mpsc::Sender};
+#[cfg(feature = "tui")]
+use std::collections::HashMap;
+#[cfg(feature = "dialog")]
+use std::sync::mpsc::TryRecvError;
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+#[cfg(feature = "dialog")]
+use chip8_rust::dialog::{pick_program, pick_program_in_background};
+#[cfg(feature = "gamepad")]
+use chip8_rust::gamepad::{Gamepads, DEFAULT_STICK_DEADZONE};
+#[cfg(feature = "egui")]
+use chip8_rust::overlay::DebugOverlay;
 use chip8_rust::{
-    cpu::{Cpu, CpuIoEvents},
-    graphics::Graphics,
+    audio::{Beep, RodioBuzzer, Waveform, DEFAULT_BEEP_HZ, MAX_BEEP_HZ, MIN_BEEP_HZ},
+    cpu::{
+        validate_program, AudioPattern, Cpu, CpuConfig, CpuIoEvents, CpuStatusEvents, InitCpuError,
+        FONT_SIZE, LORES_SCREEN_HEIGHT, LORES_SCREEN_WIDTH, PROGRAM_INIT_LOAD_POS,
+        TICKS_PER_SECOND,
+    },
+    debugger::{parse_command, DebugCommand, HELP},
+    disasm::disassemble,
+    frontend::{KeySource, LatestState, NoFrontend, ScreenSink, StatusSink},
+    graphics::{
+        render_error_action, save_screen_image, Graphics, GraphicsInitError, RenderErrorAction,
+        RendererChoice, DEFAULT_FADE_RATE,
+    },
+    hud::hud_lines,
+    keymap::Keymap,
+    palette::{find_palette, Palette, DEFAULT_PALETTE_NAME, PALETTES},
+    quirks::Quirks,
+    recorder::GifRecorder,
+    rom_picker::RomPicker,
+    savestate::{program_hash, read_state_file},
     timers::Timer,
+    trace::Tracer,
+};
+#[cfg(feature = "tui")]
+use chip8_rust::{
+    cpu::ExecError,
+    frontend::LatestScreen,
+    terminal::{Terminal, TerminalEvent},
 };
-use clap::Parser;
-use once_cell::sync::Lazy;
-use wgpu::SurfaceError;
+use clap::{Parser, Subcommand};
+#[cfg(feature = "tui")]
+use crossterm::event::KeyCode;
+use wgpu::{PresentMode, SurfaceError};
+#[cfg(feature = "dialog")]
+use winit::event::ModifiersState;
 use winit::{
     dpi::PhysicalSize,
     event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
 #[derive(Parser, Debug)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a program
+    Run(Box<Args>),
+    /// Print the disassembled program
+    Disasm(DisasmArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct DisasmArgs {
+    rom: PathBuf,
+}
+
+#[derive(clap::Args, Clone, Debug)]
 struct Args {
+    /// Program to run, or - to read it from stdin. Without one, the window waits for a program to
+    /// be dropped onto it, or when built with the dialog feature, a file dialog asks for one
     #[arg(short, long)]
-    path: String,
+    path: Option<PathBuf>,
+
+    /// Directory to pick the program from, in a list of its .ch8 files drawn on the screen. Home
+    /// goes back to the list. Without it or --path, the current directory is listed when it has any
+    #[arg(long)]
+    roms: Option<PathBuf>,
+
+    /// Start paused, so the program can be stepped through one instruction at a time
+    #[arg(long)]
+    debug: bool,
+
+    /// Close the window when the program exits with 00FD, instead of showing its last frame
+    #[arg(long)]
+    close_on_exit: bool,
+
+    /// Scroll by half the requested amount in lores mode, like the original SUPER-CHIP
+    #[arg(long)]
+    lores_half_scroll: bool,
+
+    /// Set VF to the number of collided sprite rows in hires mode, like the original SUPER-CHIP
+    #[arg(long)]
+    hires_collision_rows: bool,
+
+    /// Wrap the parts of sprites that go past an edge of the screen around to the opposite edge,
+    /// instead of cutting them off
+    #[arg(long)]
+    wrap_sprites: bool,
+
+    /// Enable the XO-CHIP extensions, such as drawing to two bit planes and audio patterns
+    #[arg(long)]
+    xochip: bool,
+
+    /// Built-in display colors to use
+    #[arg(long, value_parser = parse_palette, default_value = DEFAULT_PALETTE_NAME)]
+    palette: Palette,
+
+    /// Color of lit pixels, as a hex RGB value such as 33FF66, overriding the palette
+    #[arg(long, value_parser = parse_color)]
+    fg: Option<[u8; 3]>,
+
+    /// Color of unlit pixels, as a hex RGB value such as 001100, overriding the palette
+    #[arg(long, value_parser = parse_color)]
+    bg: Option<[u8; 3]>,
+
+    /// Scale the screen by whole multiples only, so that every pixel is equally sized
+    #[arg(long)]
+    integer_scale: bool,
+
+    /// Keep running while the window is in the background, instead of pausing
+    #[arg(long)]
+    no_pause_on_blur: bool,
+
+    /// How frames are shown: fifo waits for vsync, mailbox and immediate have less latency, and
+    /// immediate can tear. Falls back to fifo when not supported
+    #[arg(long, default_value = "fifo")]
+    present_mode: PresentModeArg,
+
+    /// What draws the window: wgpu needs a graphics adapter, softbuffer (only when built with the
+    /// softbuffer feature) draws on the cpu, and auto picks softbuffer when wgpu cannot find one
+    #[arg(long, default_value = "auto")]
+    renderer: RendererArg,
+
+    /// Size of the square that each pixel becomes in the window when it opens
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=40))]
+    scale: u32,
+
+    /// Start in fullscreen, which F11 toggles
+    #[arg(long)]
+    fullscreen: bool,
+
+    /// Draw lines between the pixels, which can also be toggled with F4
+    #[arg(long)]
+    grid: bool,
+
+    /// File with the 80-byte font that FX29 points at, 5 bytes for each hex digit, instead of the
+    /// built-in one
+    #[arg(long, value_parser = parse_font)]
+    font: Option<[u8; FONT_SIZE]>,
+
+    /// Let pixels that turn off fade out over a few frames, like on a phosphor screen, which hides
+    /// the flicker of redrawn sprites
+    #[arg(long)]
+    fade: bool,
+
+    /// How much of the way to the background color a pixel fades each frame with --fade, from
+    /// 0.0 (never) to 1.0 (right away)
+    #[arg(long, default_value_t = DEFAULT_FADE_RATE, value_parser = parse_fade_rate, requires = "fade")]
+    fade_rate: f32,
+
+    /// Size of the square that each pixel becomes in screenshots
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..))]
+    screenshot_scale: u32,
+
+    /// Most frames kept in a GIF recording, at up to 30 frames per second
+    #[arg(long, default_value_t = 1800)]
+    max_recording_frames: usize,
+
+    /// Record a GIF from the start, saved to this file on exit or when F7 stops it. Headless runs
+    /// record the emulated time, and the terminal does not record
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// How many nested calls the program can make
+    #[arg(long, default_value_t = 16)]
+    max_stack_depth: usize,
+
+    /// TOML or JSON file mapping key names to CHIP-8 keys, such as Up = 0x5 or {"Up": "5"}, and
+    /// gamepad buttons under Gamepad. Keys it leaves out keep their default binding
+    #[arg(long)]
+    keymap: Option<PathBuf>,
+
+    /// Seed for the random numbers of CXNN, so that runs with the same inputs turn out the same
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// How far a gamepad stick has to be pushed to press the key of its direction, from 0.0 to
+    /// 1.0
+    #[cfg(feature = "gamepad")]
+    #[arg(long, default_value_t = DEFAULT_STICK_DEADZONE, value_parser = parse_stick_deadzone)]
+    stick_deadzone: f32,
+
+    /// Stop with an error when the program jumps below 0x200, into the interpreter area
+    #[arg(long)]
+    strict_jumps: bool,
+
+    /// Keep the SUPER-CHIP flags saved by FX75 in <path>.flags, so that they survive restarts
+    #[arg(long)]
+    persist_flags: bool,
+
+    /// Pitch of the beep played by the sound timer, in Hz
+    #[arg(long, default_value_t = DEFAULT_BEEP_HZ, value_parser = parse_beep_hz)]
+    beep_hz: f32,
+
+    /// Volume of the beep, from 0.0 to 1.0
+    #[arg(long, default_value_t = 1.0, value_parser = parse_volume)]
+    volume: f32,
+
+    /// Shape of the beep: sine, square or triangle
+    #[arg(long, default_value = "sine")]
+    beep_wave: Waveform,
+
+    /// How many times faster the program runs while Tab is held
+    #[arg(long, default_value_t = 8.0)]
+    turbo_multiplier: f32,
+
+    /// How far back rewinding can go
+    #[arg(long, default_value_t = 10)]
+    rewind_seconds: usize,
+
+    /// Write every executed instruction, key event and timer change to a file
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    /// Stop tracing after this many instructions
+    #[arg(long, requires = "trace")]
+    trace_limit: Option<u64>,
+
+    /// Log every executed instruction at trace level, along with the registers it changed
+    #[arg(long)]
+    trace_log: bool,
+
+    /// Where to show the program: window, or terminal when built with the tui feature
+    #[arg(long, value_enum, default_value_t = Frontend::Window)]
+    frontend: Frontend,
+
+    /// Show the program in the terminal, short for --frontend terminal
+    #[arg(long, conflicts_with = "frontend")]
+    terminal: bool,
+
+    /// Where to write the registers, the last executed instructions and the memory when the
+    /// program crashes
+    #[arg(long, default_value = "crash.txt")]
+    crash_dump: PathBuf,
+
+    /// Run without a window, then print the final screen
+    #[arg(long, requires = "path")]
+    headless: bool,
+
+    /// Number of instructions to execute in headless mode
+    #[arg(long, default_value_t = 10000, requires = "headless")]
+    cycles: u64,
+
+    /// Write the final screen of a headless run to a file instead of stdout, as a PBM bitmap if
+    /// the file name ends with .pbm, or as a PNG image in the palette colors if it ends with .png
+    #[arg(long, requires = "headless")]
+    dump_screen: Option<PathBuf>,
+
+    /// Fail unless the final screen of a headless run matches this file. Setting UPDATE_GOLDEN
+    /// writes the file instead.
+    #[arg(long, requires = "headless")]
+    expect_screen: Option<PathBuf>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Frontend {
+    Window,
+    Terminal,
+}
+
+// the wgpu present modes that can be chosen
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum PresentModeArg {
+    // waits for vsync
+    Fifo,
+    // replaces the waiting frame, without tearing
+    Mailbox,
+    // shows frames right away, which can tear
+    Immediate,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum RendererArg {
+    // wgpu, falling back to softbuffer when there is no graphics adapter
+    Auto,
+    Wgpu,
+    #[cfg(feature = "softbuffer")]
+    Softbuffer,
+}
+
+impl From<RendererArg> for RendererChoice {
+    fn from(renderer: RendererArg) -> Self {
+        match renderer {
+            RendererArg::Auto => RendererChoice::Auto,
+            RendererArg::Wgpu => RendererChoice::Wgpu,
+            #[cfg(feature = "softbuffer")]
+            RendererArg::Softbuffer => RendererChoice::Softbuffer,
+        }
+    }
+}
+
+impl From<PresentModeArg> for PresentMode {
+    fn from(present_mode: PresentModeArg) -> Self {
+        match present_mode {
+            PresentModeArg::Fifo => PresentMode::Fifo,
+            PresentModeArg::Mailbox => PresentMode::Mailbox,
+            PresentModeArg::Immediate => PresentMode::Immediate,
+        }
+    }
 }
 
 struct Application {
     window_size: PhysicalSize<u32>,
+    // the size to go back to when leaving fullscreen, None when not fullscreen or when started in
+    // fullscreen
+    windowed_size: Option<PhysicalSize<u32>>,
     graphics: Graphics,
     cpu_io_sender: Sender<CpuIoEvents>,
+    cpu_status_receiver: Receiver<CpuStatusEvents>,
+    // None until a program is loaded
+    cpu_thread: Option<JoinHandle<()>>,
+    paused: bool,
+    // set when the program was paused because the window lost focus or was minimized, so that it
+    // only resumes then, and not when the user paused it
+    paused_in_background: bool,
+    // while Tab is held
+    fast_forward: bool,
+    focused: bool,
+    // nothing is rendered while minimized, as the window has no size
+    minimized: bool,
+    exited: bool,
+    crashed: bool,
+    close_on_exit: bool,
+    // None until a program is loaded
+    program_path: Option<PathBuf>,
+    program_hash: u64,
+    load_error: Option<String>,
+    // the file being dragged over the window
+    hovered_file: Option<PathBuf>,
+    args: Args,
+    // shared by every loaded program, so that the timer threads keep running
+    timers: Timers,
+    screenshot_scale: u32,
+    max_recording_frames: usize,
+    recorder: Option<GifRecorder>,
+    // where the recording started with --record goes, other recordings get a timestamped file
+    record_path: Option<PathBuf>,
+    keymap: Keymap,
+    // None when gamepads are not supported on this system
+    #[cfg(feature = "gamepad")]
+    gamepads: Option<Gamepads>,
+    // the commands typed at the debugger prompt, None without --debug
+    debugger_receiver: Option<Receiver<DebugCommand>>,
+    instructions_executed_arc: Arc<AtomicU64>,
+    // when the rates were last measured, and the counts at that time
+    rates_start: (Instant, u64, u64),
+    // instructions and frames per second, None until first measured
+    rates: Option<(f64, f64)>,
+    // how long to wait between redraws, None when presenting frames waits for vsync
+    redraw_interval: Option<Duration>,
+    next_redraw: Instant,
+    // the entry of PALETTES that F3 switches away from, None for colors that are not in the table
+    palette_index: Option<usize>,
+    // the list from --roms, None without one
+    rom_picker: Option<RomPicker>,
+    // while the list is shown instead of a program, taking the CHIP-8 keys
+    picking: bool,
+    // the registers and timers over the game, toggled with H
+    hud_visible: bool,
+    hud_state: LatestState,
+    #[cfg(feature = "egui")]
+    overlay: DebugOverlay,
+    // for Ctrl+O
+    #[cfg(feature = "dialog")]
+    modifiers: ModifiersState,
+    // the program picked in the open file dialog arrives here, None while it is not shown
+    #[cfg(feature = "dialog")]
+    open_dialog: Option<Receiver<Option<PathBuf>>>,
+}
+
+// the timer threads, along with what the cpu of each program shares with them
+struct Timers {
+    delay_timer_arc: Arc<Mutex<u8>>,
+    sound_timer_arc: Arc<Mutex<u8>>,
+    paused_arc: Arc<AtomicBool>,
+    muted_arc: Arc<AtomicBool>,
+    audio_pattern_arc: Arc<Mutex<Option<AudioPattern>>>,
+    stopped_arcs: [Arc<AtomicBool>; 2],
+    threads: Vec<JoinHandle<()>>,
 }
 
-static KEYMAP: Lazy<HashMap<VirtualKeyCode, u8>> = Lazy::new(|| {
-    HashMap::from([
-        (VirtualKeyCode::Key1, 0x1),
-        (VirtualKeyCode::Key2, 0x2),
-        (VirtualKeyCode::Key3, 0x3),
-        (VirtualKeyCode::Key4, 0xC),
-        (VirtualKeyCode::Q, 0x4),
-        (VirtualKeyCode::W, 0x5),
-        (VirtualKeyCode::E, 0x6),
-        (VirtualKeyCode::R, 0xD),
-        (VirtualKeyCode::A, 0x7),
-        (VirtualKeyCode::S, 0x8),
-        (VirtualKeyCode::D, 0x9),
-        (VirtualKeyCode::F, 0xE),
-        (VirtualKeyCode::Z, 0xA),
-        (VirtualKeyCode::X, 0x0),
-        (VirtualKeyCode::C, 0xB),
-        (VirtualKeyCode::V, 0xF),
-    ])
-});
+const WINDOW_TITLE: &str = "chip8-rust";
+// without vsync nothing else keeps the event loop from redrawing as fast as it can
+const MAX_REDRAWS_PER_SECOND: u32 = 240;
+// how often the rates in the title are measured
+const RATES_INTERVAL: Duration = Duration::from_secs(1);
 
 impl Application {
-    async fn new(window: &Window, program: Vec<u8>) -> Self {
+    async fn new(
+        window: &Window,
+        program: Option<(PathBuf, Vec<u8>)>,
+        rom_picker: Option<RomPicker>,
+        args: &Args,
+        keymap: Keymap,
+    ) -> Result<Self, GraphicsInitError> {
         let window_size = window.inner_size();
 
-        let (screen_update_sender, screen_update_receiver) = std::sync::mpsc::channel();
-        let (cpu_io_sender, cpu_io_receiver) = std::sync::mpsc::channel();
-
-        let graphics = Graphics::new(window, screen_update_receiver).await;
+        // replaced by the channels of each loaded program
+        let (cpu_io_sender, _) = std::sync::mpsc::channel();
+        let (_, cpu_status_receiver) = std::sync::mpsc::channel();
 
-        let delay_timer = Timer::new(false);
-        let sound_timer = Timer::new(true);
-        // TODO: A better way of handling the program, rather than just using unwrap?
-        let mut cpu = Cpu::new(
-            program,
-            screen_update_sender,
-            cpu_io_receiver,
-            delay_timer.get_value_arc(),
-            sound_timer.get_value_arc(),
+        let graphics = Graphics::new(
+            window,
+            palette(args),
+            args.integer_scale,
+            args.fade.then_some(args.fade_rate),
+            args.grid,
+            args.present_mode.into(),
+            args.renderer.into(),
         )
-        .unwrap();
+        .await?;
 
-        std::thread::spawn(move || {
-            cpu.run();
-        });
-        std::thread::spawn(move || {
-            delay_timer.run();
-        });
-        std::thread::spawn(move || {
-            sound_timer.run();
-        });
+        let redraw_interval = (graphics.present_mode() != PresentMode::Fifo)
+            .then(|| Duration::from_secs(1) / MAX_REDRAWS_PER_SECOND);
 
-        Self {
+        let mut application = Self {
             window_size,
+            windowed_size: None,
             graphics,
             cpu_io_sender,
+            cpu_status_receiver,
+            cpu_thread: None,
+            paused: args.debug,
+            paused_in_background: false,
+            fast_forward: false,
+            focused: true,
+            minimized: false,
+            exited: false,
+            crashed: false,
+            close_on_exit: args.close_on_exit,
+            program_path: None,
+            program_hash: 0,
+            load_error: None,
+            hovered_file: None,
+            args: args.clone(),
+            timers: Timers::spawn(args),
+            screenshot_scale: args.screenshot_scale,
+            max_recording_frames: args.max_recording_frames,
+            recorder: args
+                .record
+                .is_some()
+                .then(|| GifRecorder::new(args.max_recording_frames)),
+            record_path: args.record.clone(),
+            keymap,
+            #[cfg(feature = "gamepad")]
+            gamepads: Gamepads::new(args.stick_deadzone),
+            debugger_receiver: args.debug.then(spawn_debugger_prompt),
+            instructions_executed_arc: Arc::new(AtomicU64::new(0)),
+            rates_start: (Instant::now(), 0, 0),
+            rates: None,
+            redraw_interval,
+            next_redraw: Instant::now(),
+            palette_index: PALETTES
+                .iter()
+                .position(|(_, entry)| *entry == palette(args)),
+            rom_picker,
+            picking: false,
+            hud_visible: false,
+            hud_state: LatestState::new(),
+            #[cfg(feature = "egui")]
+            overlay: DebugOverlay::new(window),
+            #[cfg(feature = "dialog")]
+            modifiers: ModifiersState::empty(),
+            #[cfg(feature = "dialog")]
+            open_dialog: None,
+        };
+        match program {
+            Some((path, program)) => application.load_program(path, program),
+            None => application.show_rom_picker(),
+        }
+        Ok(application)
+    }
+
+    // runs the program on a new cpu, replacing the previous one
+    fn load_program(&mut self, path: PathBuf, program: Vec<u8>) {
+        let (cpu_io_sender, cpu_io_receiver) = std::sync::mpsc::channel();
+        let (cpu_status_sender, cpu_status_receiver) = std::sync::mpsc::channel();
+
+        let program_hash = program_hash(&program);
+        let mut cpu = match self.timers.create_cpu(
+            &path,
+            program,
+            &self.args,
+            self.graphics.latest_screen(),
+            cpu_io_receiver,
+            cpu_status_sender,
+        ) {
+            Ok(cpu) => cpu,
+            Err(err) => {
+                log::error!("Cannot load program {:?}: {:?}", path, err);
+                self.load_error = Some(format!("cannot load {}: {:?}", path.display(), err));
+                return;
+            }
+        };
+
+        self.stop_cpu();
+        self.cpu_io_sender = cpu_io_sender;
+        self.cpu_status_receiver = cpu_status_receiver;
+        // clears what the previous program left on the screen and in the timers
+        cpu.reset();
+        self.instructions_executed_arc = cpu.get_instructions_executed_arc();
+        self.rates_start.1 = 0;
+        cpu.add_latest_state(self.hud_state.clone());
+        #[cfg(feature = "egui")]
+        cpu.add_latest_state(self.overlay.latest_state());
+        self.cpu_thread = Some(std::thread::spawn(move || {
+            cpu.run();
+        }));
+
+        self.paused = self.args.debug;
+        self.paused_in_background = false;
+        self.exited = false;
+        self.crashed = false;
+        self.program_path = Some(path);
+        self.program_hash = program_hash;
+        self.load_error = None;
+        self.picking = false;
+    }
+
+    // waits for the cpu thread to finish, so that it does not touch the timers anymore
+    fn stop_cpu(&mut self) {
+        if let Some(cpu_thread) = self.cpu_thread.take() {
+            // fails when the cpu already stopped on its own
+            let _ = self.cpu_io_sender.send(CpuIoEvents::Shutdown);
+            if cpu_thread.join().is_err() {
+                log::error!("Cpu thread panicked");
+            }
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.stop_cpu();
+        self.timers.shutdown();
+        if let Some(recorder) = self.recorder.take() {
+            self.save_recording(recorder);
         }
     }
 
+    // for programs picked while running, such as by dropping them onto the window
+    fn load_program_file(&mut self, path: &Path) {
+        match std::fs::read(path) {
+            Ok(program) => self.load_program(path.to_path_buf(), program),
+            Err(err) => {
+                log::error!("Cannot open program {:?}: {:?}", path, err);
+                self.load_error = Some(format!("cannot open {}: {}", path.display(), err));
+            }
+        }
+    }
+
+    // stops the program to show the list from --roms in its place, if there is one
+    fn show_rom_picker(&mut self) {
+        let screen = match &self.rom_picker {
+            Some(rom_picker) => rom_picker.screen(),
+            None => return,
+        };
+        self.stop_cpu();
+        self.paused = false;
+        self.exited = false;
+        self.crashed = false;
+        self.program_path = None;
+        self.picking = true;
+        self.graphics.latest_screen().update(&screen);
+    }
+
+    fn rom_picker_key_pressed(&mut self, window: &Window, key: u8) {
+        let picked = match &mut self.rom_picker {
+            Some(rom_picker) => rom_picker.key_pressed(key),
+            None => return,
+        };
+        match picked {
+            // the list stays when the program cannot be loaded, with the reason in the title
+            Some(path) => self.load_program_file(&path),
+            None => {
+                if let Some(rom_picker) = &self.rom_picker {
+                    self.graphics.latest_screen().update(&rom_picker.screen());
+                }
+            }
+        }
+        window.set_title(&self.title());
+    }
+
+    fn state_path(&self) -> Option<PathBuf> {
+        self.program_path
+            .as_ref()
+            .map(|path| with_added_extension(path, "state"))
+    }
+
+    fn title(&self) -> String {
+        let mut title = match &self.program_path {
+            Some(path) => format!(
+                "{} - {}",
+                WINDOW_TITLE,
+                path.file_name()
+                    .unwrap_or(path.as_os_str())
+                    .to_string_lossy()
+            ),
+            None => WINDOW_TITLE.to_string(),
+        };
+        if self.crashed {
+            title.push_str(" [CRASHED]");
+        } else if self.exited {
+            title.push_str(" [EXITED]");
+        } else if self.paused {
+            title.push_str(" [PAUSED]");
+        } else if self.fast_forward {
+            title.push_str(" [FAST-FORWARD]");
+        }
+        if self.timers.muted_arc.load(Ordering::Relaxed) {
+            title.push_str(" [MUTED]");
+        }
+        if self.recorder.is_some() {
+            title.push_str(" [REC]");
+        }
+        if let Some((instructions_per_second, frames_per_second)) = self.rates {
+            title.push_str(&format!(
+                " ({:.0} IPS, {:.0} FPS)",
+                instructions_per_second, frames_per_second
+            ));
+        }
+        if let Some(path) = &self.hovered_file {
+            title.push_str(&format!(
+                " - drop to run {}",
+                path.file_name()
+                    .unwrap_or(path.as_os_str())
+                    .to_string_lossy()
+            ));
+        } else if let Some(load_error) = &self.load_error {
+            title.push_str(&format!(" - {}", load_error));
+        } else if self.picking {
+            title.push_str(" - pick a program");
+        } else if self.program_path.is_none() {
+            title.push_str(" - drop a program here to run it");
+        }
+        title
+    }
+
     fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        // minimizing makes the window 0x0 on some platforms, which the surface cannot take
+        let minimized = new_size.width == 0 || new_size.height == 0;
+        if minimized != self.minimized {
+            self.minimized = minimized;
+            self.pause_in_background();
+        }
+        if minimized {
+            return;
+        }
+
         self.window_size = new_size;
+        // also redraws, so that the screen comes back right away when restored
         self.graphics.resize(new_size);
     }
 
-    fn input(&mut self, event: &WindowEvent) -> bool {
+    fn input(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        #[cfg(feature = "egui")]
+        if self.overlay.on_event(event) {
+            return true;
+        }
+
+        if let WindowEvent::DroppedFile(path) = event {
+            self.hovered_file = None;
+            self.load_program_file(path);
+            window.set_title(&self.title());
+            return true;
+        }
+
+        if let WindowEvent::HoveredFile(path) = event {
+            self.hovered_file = Some(path.clone());
+            window.set_title(&self.title());
+            return true;
+        }
+
+        if let WindowEvent::HoveredFileCancelled = event {
+            self.hovered_file = None;
+            window.set_title(&self.title());
+            return true;
+        }
+
+        if let WindowEvent::Focused(focused) = event {
+            self.focus_changed(*focused);
+            window.set_title(&self.title());
+            return true;
+        }
+
+        #[cfg(feature = "dialog")]
+        if let WindowEvent::ModifiersChanged(modifiers) = event {
+            self.modifiers = *modifiers;
+            return true;
+        }
+
         if let WindowEvent::KeyboardInput {
             input:
                 KeyboardInput {
@@ -102,53 +726,1120 @@ impl Application {
             ..
         } = event
         {
-            match KEYMAP.get(virtual_keycode) {
+            // before the keymap, which may have taken O for a CHIP-8 key, unlike the other hotkeys
+            #[cfg(feature = "dialog")]
+            if (virtual_keycode, state) == (&VirtualKeyCode::O, &ElementState::Pressed)
+                && self.modifiers.ctrl()
+            {
+                self.show_open_dialog(window);
+                return true;
+            }
+
+            match self.keymap.keys.get(virtual_keycode) {
+                Some(value) if self.picking => {
+                    if *state == ElementState::Pressed {
+                        self.rom_picker_key_pressed(window, *value);
+                    }
+                    true
+                }
                 Some(value) => {
-                    self.cpu_io_sender
-                        .send(match state {
-                            ElementState::Pressed => CpuIoEvents::KeyPressed(*value),
-                            ElementState::Released => CpuIoEvents::KeyReleased(*value),
-                        })
-                        .expect("Cannot send IO to cpu");
+                    self.send_cpu_io_event(match state {
+                        ElementState::Pressed => CpuIoEvents::KeyPressed(*value),
+                        ElementState::Released => CpuIoEvents::KeyReleased(*value),
+                    });
                     true
                 }
-                None => false,
+                None => match (virtual_keycode, state) {
+                    (VirtualKeyCode::P, ElementState::Pressed) => {
+                        self.send_cpu_io_event(CpuIoEvents::TogglePause);
+                        self.paused = !self.paused;
+                        window.set_title(&self.title());
+                        true
+                    }
+                    (VirtualKeyCode::M, ElementState::Pressed) => {
+                        self.timers.muted_arc.fetch_xor(true, Ordering::Relaxed);
+                        window.set_title(&self.title());
+                        true
+                    }
+                    (VirtualKeyCode::F8, ElementState::Pressed) => {
+                        self.send_cpu_io_event(CpuIoEvents::AdvanceFrame);
+                        true
+                    }
+                    (VirtualKeyCode::F10, ElementState::Pressed) => {
+                        self.send_cpu_io_event(CpuIoEvents::Step);
+                        true
+                    }
+                    (VirtualKeyCode::Tab, state) => {
+                        self.send_cpu_io_event(CpuIoEvents::SetSpeedMultiplier(match state {
+                            ElementState::Pressed => self.args.turbo_multiplier,
+                            ElementState::Released => 1f32,
+                        }));
+                        // held keys repeat their presses
+                        let fast_forward = *state == ElementState::Pressed;
+                        if fast_forward != self.fast_forward {
+                            self.fast_forward = fast_forward;
+                            window.set_title(&self.title());
+                        }
+                        true
+                    }
+                    (VirtualKeyCode::Back, state) => {
+                        self.send_cpu_io_event(CpuIoEvents::Rewind(
+                            *state == ElementState::Pressed,
+                        ));
+                        true
+                    }
+                    (VirtualKeyCode::F5, ElementState::Pressed) => {
+                        self.send_cpu_io_event(CpuIoEvents::Reset);
+                        true
+                    }
+                    (VirtualKeyCode::Home, ElementState::Pressed) if self.rom_picker.is_some() => {
+                        self.show_rom_picker();
+                        window.set_title(&self.title());
+                        true
+                    }
+                    #[cfg(feature = "egui")]
+                    (VirtualKeyCode::F1, ElementState::Pressed) => {
+                        self.overlay.toggle();
+                        if !self.overlay.is_visible() {
+                            self.graphics.set_overlay(None);
+                        }
+                        true
+                    }
+                    (VirtualKeyCode::H, ElementState::Pressed) => {
+                        self.hud_visible = !self.hud_visible;
+                        true
+                    }
+                    (VirtualKeyCode::F2, ElementState::Pressed) => {
+                        self.save_screenshot();
+                        true
+                    }
+                    (VirtualKeyCode::F3, ElementState::Pressed) => {
+                        self.cycle_palette();
+                        true
+                    }
+                    (VirtualKeyCode::F4, ElementState::Pressed) => {
+                        self.graphics.toggle_grid();
+                        true
+                    }
+                    (VirtualKeyCode::F7, ElementState::Pressed) => {
+                        self.toggle_recording();
+                        window.set_title(&self.title());
+                        true
+                    }
+                    (VirtualKeyCode::F11, ElementState::Pressed) => {
+                        self.toggle_fullscreen(window);
+                        true
+                    }
+                    // only quits once out of fullscreen
+                    (VirtualKeyCode::Escape, ElementState::Pressed)
+                        if window.fullscreen().is_some() =>
+                    {
+                        self.toggle_fullscreen(window);
+                        true
+                    }
+                    (VirtualKeyCode::F12, ElementState::Pressed) => {
+                        self.send_cpu_io_event(CpuIoEvents::DumpState);
+                        true
+                    }
+                    (VirtualKeyCode::F6, ElementState::Pressed) => {
+                        if let Some(state_path) = self.state_path() {
+                            self.send_cpu_io_event(CpuIoEvents::SaveState(state_path));
+                        }
+                        true
+                    }
+                    (VirtualKeyCode::F9, ElementState::Pressed) => {
+                        if let Some(state_path) = self.state_path() {
+                            match read_state_file(&state_path, self.program_hash) {
+                                Ok(state) => {
+                                    self.send_cpu_io_event(CpuIoEvents::LoadState(Box::new(state)))
+                                }
+                                Err(err) => log::error!(
+                                    "Cannot read state file {:?}: {:?}",
+                                    state_path,
+                                    err
+                                ),
+                            }
+                        }
+                        true
+                    }
+                    _ => false,
+                },
             }
         } else {
             false
         }
     }
 
+    #[cfg(feature = "dialog")]
+    fn show_open_dialog(&mut self, window: &Window) {
+        if self.open_dialog.is_none() {
+            self.open_dialog = Some(pick_program_in_background(window));
+        }
+    }
+
+    #[cfg(feature = "dialog")]
+    fn handle_open_dialog(&mut self, window: &Window) {
+        let picked = match &self.open_dialog {
+            Some(receiver) => receiver.try_recv(),
+            None => return,
+        };
+        match picked {
+            Ok(Some(path)) => {
+                self.load_program_file(&path);
+                window.set_title(&self.title());
+            }
+            // cancelled, the program keeps running
+            Ok(None) | Err(TryRecvError::Disconnected) => {}
+            Err(TryRecvError::Empty) => return,
+        }
+        self.open_dialog = None;
+    }
+
+    fn save_screenshot(&self) {
+        let path = timestamped_path("screenshot", "png");
+        match self.graphics.save_screenshot(&path, self.screenshot_scale) {
+            Ok(_) => log::info!("Saved screenshot to {:?}", path),
+            Err(err) => log::error!("Cannot save screenshot to {:?}: {:?}", path, err),
+        }
+    }
+
+    fn focus_changed(&mut self, focused: bool) {
+        if !focused {
+            // the window does not get the releases of keys that are held while switching away
+            self.keymap
+                .keys
+                .values()
+                .for_each(|value| self.send_cpu_io_event(CpuIoEvents::KeyReleased(*value)));
+            if self.fast_forward {
+                self.send_cpu_io_event(CpuIoEvents::SetSpeedMultiplier(1f32));
+                self.fast_forward = false;
+            }
+        }
+
+        self.focused = focused;
+        self.pause_in_background();
+    }
+
+    fn pause_in_background(&mut self) {
+        if self.args.no_pause_on_blur {
+            return;
+        }
+        let in_background = !self.focused || self.minimized;
+        if in_background && !self.paused {
+            self.send_cpu_io_event(CpuIoEvents::TogglePause);
+            self.paused = true;
+            self.paused_in_background = true;
+        } else if !in_background && self.paused_in_background {
+            self.send_cpu_io_event(CpuIoEvents::TogglePause);
+            self.paused = false;
+            self.paused_in_background = false;
+        }
+    }
+
+    fn toggle_fullscreen(&mut self, window: &Window) {
+        if window.fullscreen().is_some() {
+            window.set_fullscreen(None);
+            if let Some(windowed_size) = self.windowed_size.take() {
+                window.set_inner_size(windowed_size);
+            }
+        } else {
+            self.windowed_size = Some(window.inner_size());
+            window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+        }
+        // not every platform sends a Resized event right away
+        self.resize(window.inner_size());
+    }
+
+    fn cycle_palette(&mut self) {
+        let index = match self.palette_index {
+            Some(index) => (index + 1) % PALETTES.len(),
+            None => 0,
+        };
+        let (name, palette) = PALETTES[index];
+        log::info!("Switched to the {} palette", name);
+        self.graphics.set_palette(palette);
+        self.palette_index = Some(index);
+    }
+
+    fn toggle_recording(&mut self) {
+        match self.recorder.take() {
+            Some(recorder) => self.save_recording(recorder),
+            None => {
+                log::info!("Recording started");
+                self.recorder = Some(GifRecorder::new(self.max_recording_frames));
+            }
+        }
+    }
+
+    fn save_recording(&mut self, recorder: GifRecorder) {
+        let path = self
+            .record_path
+            .take()
+            .unwrap_or_else(|| timestamped_path("recording", "gif"));
+        match recorder.save(
+            &path,
+            self.graphics.plane_colors(),
+            self.screenshot_scale,
+            Instant::now(),
+        ) {
+            Ok(_) => log::info!("Saved recording to {:?}", path),
+            Err(err) => log::error!("Cannot save recording to {:?}: {:?}", path, err),
+        }
+    }
+
+    fn send_cpu_io_event(&self, event: CpuIoEvents) {
+        // the cpu thread is gone once the program exited or crashed, so there is nobody to tell
+        if self.cpu_io_sender.send(event).is_err() {
+            log::debug!("Cpu stopped, ignoring input");
+        }
+    }
+
+    fn handle_debugger_commands(&mut self, window: &Window) {
+        let commands = match &self.debugger_receiver {
+            Some(receiver) => receiver.try_iter().collect::<Vec<_>>(),
+            None => return,
+        };
+        commands.into_iter().for_each(|command| match command {
+            DebugCommand::Step => {
+                if self.paused {
+                    self.send_cpu_io_event(CpuIoEvents::Step);
+                } else {
+                    println!("Pause the program first");
+                }
+            }
+            DebugCommand::Continue | DebugCommand::Pause => {
+                if self.paused == matches!(command, DebugCommand::Continue) {
+                    self.send_cpu_io_event(CpuIoEvents::TogglePause);
+                    self.paused = !self.paused;
+                    self.paused_in_background = false;
+                    window.set_title(&self.title());
+                }
+            }
+            DebugCommand::Help => println!("{}", HELP),
+            DebugCommand::Break(address) => {
+                self.send_cpu_io_event(CpuIoEvents::AddBreakpoint(address))
+            }
+            DebugCommand::Unbreak(address) => {
+                self.send_cpu_io_event(CpuIoEvents::RemoveBreakpoint(address))
+            }
+            DebugCommand::Watch(watchpoint) => {
+                self.send_cpu_io_event(CpuIoEvents::AddWatchpoint(watchpoint))
+            }
+            DebugCommand::Unwatch(watchpoint) => {
+                self.send_cpu_io_event(CpuIoEvents::RemoveWatchpoint(watchpoint))
+            }
+            DebugCommand::Inspect(inspection) => {
+                self.send_cpu_io_event(CpuIoEvents::Inspect(inspection))
+            }
+        });
+    }
+
+    #[cfg(feature = "gamepad")]
+    fn handle_gamepad_events(&mut self, window: &Window) {
+        let events = match &mut self.gamepads {
+            Some(gamepads) => gamepads.poll(&self.keymap.buttons),
+            None => return,
+        };
+        events.into_iter().for_each(|event| match event {
+            CpuIoEvents::KeyPressed(key) if self.picking => {
+                self.rom_picker_key_pressed(window, key)
+            }
+            _ => self.send_cpu_io_event(event),
+        });
+    }
+
+    // shows how fast the cpu and the rendering actually run
+    fn update_rates(&mut self, window: &Window) {
+        let (start, start_instructions, start_frames) = self.rates_start;
+        let elapsed = start.elapsed();
+        if elapsed < RATES_INTERVAL {
+            return;
+        }
+
+        let instructions = self.instructions_executed_arc.load(Ordering::Relaxed);
+        let frames = self.graphics.frames_presented();
+        self.rates = Some((
+            instructions.saturating_sub(start_instructions) as f64 / elapsed.as_secs_f64(),
+            (frames - start_frames) as f64 / elapsed.as_secs_f64(),
+        ));
+        self.rates_start = (Instant::now(), instructions, frames);
+        window.set_title(&self.title());
+    }
+
+    fn handle_cpu_status_events(&mut self, window: &Window, control_flow: &mut ControlFlow) {
+        while let Ok(event) = self.cpu_status_receiver.try_recv() {
+            match event {
+                CpuStatusEvents::Exited => {
+                    log::info!("Program exited");
+                    self.exited = true;
+                    window.set_title(&self.title());
+                    if self.close_on_exit {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+                CpuStatusEvents::Crashed(err) => {
+                    eprintln!("Program crashed: {:?}", err);
+                    self.crashed = true;
+                    window.set_title(&self.title());
+                }
+                CpuStatusEvents::Paused(reason) => {
+                    println!("{}", reason);
+                    self.paused = true;
+                    self.paused_in_background = false;
+                    window.set_title(&self.title());
+                }
+                CpuStatusEvents::Output(text) => println!("{}", text),
+            }
+        }
+    }
+
+    fn request_redraw(&mut self, window: &Window, control_flow: &mut ControlFlow) {
+        match self.redraw_interval {
+            None => window.request_redraw(),
+            Some(redraw_interval) => {
+                let now = Instant::now();
+                if now >= self.next_redraw {
+                    window.request_redraw();
+                    self.next_redraw = now + redraw_interval;
+                }
+                // unless the program already asked to exit
+                if *control_flow == ControlFlow::Poll {
+                    *control_flow = ControlFlow::WaitUntil(self.next_redraw);
+                }
+            }
+        }
+    }
+
+    // lays out the overlay for the next frame, and sends the clicks on its buttons to the cpu
+    #[cfg(feature = "egui")]
+    fn update_overlay(&mut self, window: &Window) {
+        if !self.overlay.is_visible() || self.minimized {
+            return;
+        }
+        let (frame, events) = self.overlay.run(window, self.paused);
+        events.into_iter().for_each(|event| {
+            if matches!(event, CpuIoEvents::TogglePause) {
+                self.paused = !self.paused;
+                window.set_title(&self.title());
+            }
+            self.send_cpu_io_event(event);
+        });
+        self.graphics.set_overlay(Some(frame));
+    }
+
+    // the registers of the last state the cpu published, which stay while it is paused
+    fn update_hud(&mut self) {
+        // nothing to show without a program
+        if !self.hud_visible || self.cpu_thread.is_none() {
+            self.graphics.set_hud(None);
+            return;
+        }
+        if let Some(state) = self.hud_state.take() {
+            self.graphics.set_hud(Some(hud_lines(&state)));
+        }
+    }
+
     fn render(&mut self) -> Result<(), SurfaceError> {
-        self.graphics.render()
+        if self.minimized {
+            return Ok(());
+        }
+        let result = self.graphics.render();
+        if let Some(recorder) = &mut self.recorder {
+            recorder.capture(self.graphics.screen());
+        }
+        result
     }
 }
 
-async fn run() {
-    let args = Args::parse();
+impl Timers {
+    fn spawn(args: &Args) -> Self {
+        let paused_arc = Arc::new(AtomicBool::new(false));
+        let audio_pattern_arc = Arc::new(Mutex::new(None));
+        let beep = Beep {
+            frequency: args.beep_hz,
+            waveform: args.beep_wave,
+        };
+        // the XO-CHIP pattern replaces the beep once the program loads one
+        let buzzer_audio_pattern = args.xochip.then(|| audio_pattern_arc.clone());
+        let muted_arc = Arc::new(AtomicBool::new(false));
+        let buzzer_muted_arc = muted_arc.clone();
+        let volume = args.volume;
+        let delay_timer = Timer::new(paused_arc.clone());
+        let sound_timer = Timer::new(paused_arc.clone());
+        let delay_timer_arc = delay_timer.get_value_arc();
+        let sound_timer_arc = sound_timer.get_value_arc();
+        let stopped_arcs = [delay_timer.get_stopped_arc(), sound_timer.get_stopped_arc()];
 
-    env_logger::init();
+        let threads = vec![
+            std::thread::spawn(move || {
+                delay_timer.run(None);
+            }),
+            std::thread::spawn(move || {
+                // the sound timer still counts down without a sound device
+                match RodioBuzzer::new(beep, buzzer_audio_pattern, volume, buzzer_muted_arc) {
+                    Ok(mut buzzer) => sound_timer.run(Some(&mut buzzer)),
+                    Err(err) => {
+                        log::warn!("Cannot play sound: {:?}", err);
+                        sound_timer.run(None);
+                    }
+                }
+            }),
+        ];
 
-    let mut program_file = match File::open(&args.path) {
-        Ok(file) => file,
+        Self {
+            delay_timer_arc,
+            sound_timer_arc,
+            paused_arc,
+            muted_arc,
+            audio_pattern_arc,
+            stopped_arcs,
+            threads,
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.stopped_arcs
+            .iter()
+            .for_each(|stopped| stopped.store(true, Ordering::Relaxed));
+        self.threads.drain(..).for_each(|thread| {
+            if thread.join().is_err() {
+                log::error!("Timer thread panicked");
+            }
+        });
+    }
+
+    // a cpu for the program that runs on these timers, ready to be spawned
+    fn create_cpu(
+        &self,
+        path: &Path,
+        program: Vec<u8>,
+        args: &Args,
+        screen_sink: impl ScreenSink + 'static,
+        key_source: impl KeySource + 'static,
+        status_sink: impl StatusSink + 'static,
+    ) -> Result<Cpu, InitCpuError> {
+        self.paused_arc.store(false, Ordering::Relaxed);
+        let mut cpu = Cpu::new(
+            program,
+            cpu_config(args),
+            screen_sink,
+            key_source,
+            status_sink,
+            self.delay_timer_arc.clone(),
+            self.sound_timer_arc.clone(),
+            self.paused_arc.clone(),
+        )?;
+        cpu.set_audio_pattern_arc(self.audio_pattern_arc.clone());
+        attach_font(&mut cpu, args);
+        attach_crash_dump(&mut cpu, args);
+        attach_tracer(&mut cpu, args);
+        attach_flags_file(&mut cpu, args, path);
+        Ok(cpu)
+    }
+}
+
+// such as roms/game.ch8.state, keeping the original extension
+fn with_added_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+// timestamped, so that earlier files are kept
+fn timestamped_path(prefix: &str, extension: &str) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    PathBuf::from(format!("{}-{}.{}", prefix, timestamp, extension))
+}
+
+fn palette(args: &Args) -> Palette {
+    Palette {
+        foreground: args.fg.unwrap_or(args.palette.foreground),
+        background: args.bg.unwrap_or(args.palette.background),
+    }
+}
+
+fn parse_color(value: &str) -> Result<[u8; 3], String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{:?} is not a color in the RRGGBB format", value));
+    }
+
+    let mut color = [0; 3];
+    (0..3).for_each(|c| color[c] = u8::from_str_radix(&hex[(c * 2)..(c * 2 + 2)], 16).unwrap());
+    Ok(color)
+}
+
+fn parse_beep_hz(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(frequency) if (MIN_BEEP_HZ..=MAX_BEEP_HZ).contains(&frequency) => Ok(frequency),
+        _ => Err(format!(
+            "expected a frequency between {} and {} Hz",
+            MIN_BEEP_HZ, MAX_BEEP_HZ
+        )),
+    }
+}
+
+fn parse_volume(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(volume) if (0f32..=1f32).contains(&volume) => Ok(volume),
+        _ => Err("expected a volume between 0.0 and 1.0".to_string()),
+    }
+}
+
+fn parse_fade_rate(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(rate) if (0f32..=1f32).contains(&rate) => Ok(rate),
+        _ => Err("expected a fade rate between 0.0 and 1.0".to_string()),
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn parse_stick_deadzone(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(deadzone) if (0f32..=1f32).contains(&deadzone) => Ok(deadzone),
+        _ => Err("expected a deadzone between 0.0 and 1.0".to_string()),
+    }
+}
+
+fn parse_font(value: &str) -> Result<[u8; FONT_SIZE], String> {
+    let font = std::fs::read(value).map_err(|err| format!("cannot read {}: {}", value, err))?;
+    let length = font.len();
+    font.try_into().map_err(|_| {
+        format!(
+            "a font must be exactly {} bytes, {} is {} bytes",
+            FONT_SIZE, value, length
+        )
+    })
+}
+
+fn parse_palette(value: &str) -> Result<Palette, String> {
+    find_palette(value).ok_or_else(|| {
+        let names = PALETTES
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("unknown palette, valid palettes are: {}", names)
+    })
+}
+
+fn cpu_config(args: &Args) -> CpuConfig {
+    CpuConfig {
+        quirks: Quirks {
+            lores_half_scroll: args.lores_half_scroll,
+            hires_collision_rows: args.hires_collision_rows,
+            clip_sprites: !args.wrap_sprites,
+        },
+        xochip: args.xochip,
+        max_stack_depth: args.max_stack_depth,
+        strict_jumps: args.strict_jumps,
+        rewind_capacity: args.rewind_seconds * TICKS_PER_SECOND,
+        start_paused: args.debug,
+        rng_seed: args.seed,
+    }
+}
+
+fn attach_font(cpu: &mut Cpu, args: &Args) {
+    if let Some(font) = args.font {
+        cpu.set_font(font);
+    }
+}
+
+fn attach_crash_dump(cpu: &mut Cpu, args: &Args) {
+    cpu.set_crash_dump_path(args.crash_dump.clone());
+}
+
+fn explain_graphics_error(err: &GraphicsInitError) -> String {
+    let alternatives = if cfg!(feature = "tui") {
+        "--terminal or --headless"
+    } else {
+        "--headless"
+    };
+    // with the feature, only --renderer wgpu does not fall back to softbuffer
+    let software = if cfg!(feature = "softbuffer") {
+        "leave out --renderer wgpu to draw on the cpu"
+    } else {
+        "build with the softbuffer feature to draw on the cpu"
+    };
+    match err {
+        GraphicsInitError::NoAdapter => format!(
+            "No compatible graphics adapter was found. Make sure that Vulkan, Metal, DirectX 12 \
+             or OpenGL drivers are installed, {}, or run without a window with {}.",
+            software, alternatives
+        ),
+        GraphicsInitError::NoDevice(err) => format!(
+            "The graphics adapter cannot be used ({}). Updating the graphics drivers may help, \
+             or run without a window with {}.",
+            err, alternatives
+        ),
+        GraphicsInitError::ZeroSizedWindow => {
+            "The window was created without a size, so there is nothing to draw on.".to_string()
+        }
+        #[cfg(feature = "softbuffer")]
+        GraphicsInitError::Softbuffer(err) => format!(
+            "The window cannot be drawn on without a graphics adapter either ({}). Run without a \
+             window with {}.",
+            err, alternatives
+        ),
+    }
+}
+
+// reads the commands typed into stdin on a thread of its own, as reading blocks
+fn spawn_debugger_prompt() -> Receiver<DebugCommand> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        println!("Debugger ready, type help for the list of commands");
+        for line in std::io::stdin().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            match parse_command(&line) {
+                Ok(command) => {
+                    // the window was closed
+                    if sender.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => println!("{}", err),
+            }
+        }
+    });
+    receiver
+}
+
+fn attach_tracer(cpu: &mut Cpu, args: &Args) {
+    if let Some(trace_path) = &args.trace {
+        match Tracer::create(trace_path, args.trace_limit) {
+            Ok(tracer) => cpu.set_tracer(tracer),
+            Err(err) => log::error!("Cannot create trace file {:?}: {:?}", trace_path, err),
+        }
+    }
+}
+
+fn attach_flags_file(cpu: &mut Cpu, args: &Args, program_path: &Path) {
+    if args.persist_flags {
+        cpu.set_rpl_flags_path(with_added_extension(program_path, "flags"));
+    }
+}
+
+fn run_headless(path: &Path, program: Vec<u8>, args: &Args) {
+    // nothing listens to the screen updates, nor sends any input
+    let mut cpu = match Cpu::new(
+        program,
+        cpu_config(args),
+        NoFrontend,
+        NoFrontend,
+        NoFrontend,
+        Arc::new(Mutex::new(0)),
+        Arc::new(Mutex::new(0)),
+        Arc::new(AtomicBool::new(false)),
+    ) {
+        Ok(cpu) => cpu,
         Err(err) => {
-            eprintln!("Cannot open program {:?}: {:?}", args.path, err);
+            eprintln!("Cannot load program {:?}: {:?}", path, err);
+            std::process::exit(1);
+        }
+    };
+    attach_font(&mut cpu, args);
+    attach_crash_dump(&mut cpu, args);
+    attach_tracer(&mut cpu, args);
+    attach_flags_file(&mut cpu, args, path);
+
+    // the frames are timed as if the program ran in real time
+    let started_at = Instant::now();
+    let tick_time = |ticks: u64| started_at + Duration::from_secs(ticks) / TICKS_PER_SECOND as u32;
+    let mut recorder = args
+        .record
+        .as_ref()
+        .map(|_| GifRecorder::new(args.max_recording_frames));
+    let mut ticks = 0;
+    let result = cpu.run_headless_with(args.cycles, |tick, screen| {
+        ticks = tick;
+        if let Some(recorder) = &mut recorder {
+            recorder.capture_at(screen, tick_time(tick));
+        }
+    });
+    // the recording and the screen are still written, as they show where the program stopped,
+    // but scripts have to be able to tell that it did
+    let mut failed = false;
+    if let Err(err) = result {
+        eprintln!("Headless run stopped early: {:?}", err);
+        failed = true;
+    }
+
+    if let (Some(recorder), Some(path)) = (recorder, &args.record) {
+        // the last frame lasts for one tick
+        if let Err(err) = recorder.save(
+            path,
+            &palette(args).plane_colors(),
+            args.screenshot_scale,
+            tick_time(ticks + 1),
+        ) {
+            eprintln!("Cannot save recording to {:?}: {:?}", path, err);
+            failed = true;
+        }
+    }
+
+    let screen = cpu.screen().ascii_art();
+    match &args.dump_screen {
+        Some(path) => {
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            let result = match extension.map(str::to_ascii_lowercase).as_deref() {
+                Some("png") => save_screen_image(
+                    cpu.screen(),
+                    &palette(args).plane_colors(),
+                    path,
+                    args.screenshot_scale,
+                )
+                .map_err(|err| format!("{:?}", err)),
+                Some("pbm") => {
+                    std::fs::write(path, cpu.screen().pbm()).map_err(|err| format!("{:?}", err))
+                }
+                _ => std::fs::write(path, &screen).map_err(|err| format!("{:?}", err)),
+            };
+            if let Err(err) = result {
+                eprintln!("Cannot write screen to {:?}: {}", path, err);
+                failed = true;
+            }
+        }
+        None if args.expect_screen.is_none() => print!("{}", screen),
+        None => {}
+    }
+
+    if let Some(path) = &args.expect_screen {
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            if let Err(err) = std::fs::write(path, &screen) {
+                eprintln!("Cannot write screen to {:?}: {:?}", path, err);
+                std::process::exit(1);
+            }
+        } else {
+            match std::fs::read_to_string(path) {
+                Ok(expected) if expected == screen => {}
+                Ok(expected) => {
+                    eprintln!(
+                        "Screen does not match {:?}\nExpected:\n{}Actual:\n{}",
+                        path, expected, screen
+                    );
+                    std::process::exit(1);
+                }
+                Err(err) => {
+                    eprintln!("Cannot read expected screen {:?}: {:?}", path, err);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(feature = "tui")]
+fn run_terminal(path: &Path, program: Vec<u8>, args: &Args, keymap: &Keymap) {
+    let mut timers = Timers::spawn(args);
+    let latest_screen = LatestScreen::new();
+    let (cpu_io_sender, cpu_io_receiver) = std::sync::mpsc::channel();
+    let (cpu_status_sender, cpu_status_receiver) = std::sync::mpsc::channel();
+    let mut cpu = match timers.create_cpu(
+        path,
+        program,
+        args,
+        latest_screen.clone(),
+        cpu_io_receiver,
+        cpu_status_sender,
+    ) {
+        Ok(cpu) => cpu,
+        Err(err) => {
+            eprintln!("Cannot load program {:?}: {:?}", path, err);
             return;
         }
     };
+    let cpu_thread = std::thread::spawn(move || {
+        cpu.run();
+    });
+
+    // the terminal goes back to normal once it is dropped at the end of the match, before
+    // anything is printed
+    let result = match Terminal::new(palette(args), terminal_keys(&keymap.keys)) {
+        Ok(mut terminal) => run_terminal_loop(
+            &mut terminal,
+            args,
+            &timers,
+            &cpu_io_sender,
+            &latest_screen,
+            &cpu_status_receiver,
+        ),
+        Err(err) => Err(err),
+    };
+
+    let _ = cpu_io_sender.send(CpuIoEvents::Shutdown);
+    if cpu_thread.join().is_err() {
+        eprintln!("Cpu thread panicked");
+    }
+    timers.shutdown();
+
+    match result {
+        Ok(Some(err)) => eprintln!("Program crashed: {:?}", err),
+        Ok(None) => {}
+        Err(err) => eprintln!("Terminal error: {:?}", err),
+    }
+}
+
+// returns the error that the program crashed with, if it did
+#[cfg(feature = "tui")]
+fn run_terminal_loop(
+    terminal: &mut Terminal,
+    args: &Args,
+    timers: &Timers,
+    cpu_io_sender: &Sender<CpuIoEvents>,
+    latest_screen: &LatestScreen,
+    cpu_status_receiver: &Receiver<CpuStatusEvents>,
+) -> std::io::Result<Option<ExecError>> {
+    let frame = std::time::Duration::from_secs_f32(1f32 / TICKS_PER_SECOND as f32);
+    let mut paused = args.debug;
+    let mut exited = false;
+    let mut crash = None;
+
+    loop {
+        for event in terminal.poll(frame)? {
+            match event {
+                TerminalEvent::Quit => return Ok(crash),
+                TerminalEvent::Keypad(event) => {
+                    let _ = cpu_io_sender.send(event);
+                }
+                TerminalEvent::Key(KeyCode::Char('p')) => {
+                    let _ = cpu_io_sender.send(CpuIoEvents::TogglePause);
+                    paused = !paused;
+                }
+                TerminalEvent::Key(KeyCode::Char('m')) => {
+                    timers.muted_arc.fetch_xor(true, Ordering::Relaxed);
+                }
+                TerminalEvent::Key(_) => {}
+            }
+        }
+
+        if let Some(screen) = latest_screen.take() {
+            terminal.draw(&screen)?;
+        }
+
+        while let Ok(event) = cpu_status_receiver.try_recv() {
+            match event {
+                CpuStatusEvents::Exited if args.close_on_exit => return Ok(crash),
+                CpuStatusEvents::Exited => exited = true,
+                CpuStatusEvents::Crashed(err) => crash = Some(err),
+                CpuStatusEvents::Paused(_) => paused = true,
+                // there is no room for it next to the screen
+                CpuStatusEvents::Output(_) => {}
+            }
+        }
+
+        let mut status = if crash.is_some() {
+            "[CRASHED]"
+        } else if exited {
+            "[EXITED]"
+        } else if paused {
+            "[PAUSED]"
+        } else {
+            ""
+        }
+        .to_string();
+        if timers.muted_arc.load(Ordering::Relaxed) {
+            status.push_str(" [MUTED]");
+        }
+        status.push_str(" Esc: quit, P: pause, M: mute");
+        terminal.set_status(status.trim_start())?;
+    }
+}
+
+// the terminal only sees the characters that keys type, so only the letter and digit keys of the
+// keymap carry over
+#[cfg(feature = "tui")]
+fn terminal_keys(keys: &HashMap<VirtualKeyCode, u8>) -> HashMap<char, u8> {
+    keys.iter()
+        .filter_map(|(virtual_keycode, value)| {
+            let name = format!("{:?}", virtual_keycode);
+            let mut chars = name.strip_prefix("Key").unwrap_or(&name).chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphanumeric() => {
+                    Some((c.to_ascii_lowercase(), *value))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn read_program(path: &Path) -> Option<Vec<u8>> {
+    if path == Path::new("-") {
+        let mut program = Vec::new();
+        if let Err(err) = std::io::stdin().read_to_end(&mut program) {
+            eprintln!("Read program from stdin failed: {:?}", err);
+            return None;
+        }
+        return Some(program);
+    }
+
+    let mut program_file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Cannot open program {:?}: {:?}", path, err);
+            return None;
+        }
+    };
 
     let mut program = Vec::new();
     if let Err(err) = program_file.read_to_end(&mut program) {
-        eprintln!("Read program {:?} failed: {:?}", args.path, err);
+        eprintln!("Read program {:?} failed: {:?}", path, err);
+        return None;
+    }
+
+    Some(program)
+}
+
+fn disasm(args: &DisasmArgs) {
+    let program = match read_program(&args.rom) {
+        Some(program) => program,
+        None => return,
+    };
+
+    disassemble(&program, PROGRAM_INIT_LOAD_POS as u16)
+        .iter()
+        .for_each(|line| {
+            if line.is_jump_target {
+                println!("L{:03X}:", line.address);
+            }
+            let bytes = line
+                .bytes
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<_>>()
+                .join(" ");
+            println!("{:03X}: {:<11}  {}", line.address, bytes, line.text);
+        });
+}
+
+async fn run() {
+    let args = match Cli::parse().command {
+        Command::Run(args) if args.terminal => Args {
+            frontend: Frontend::Terminal,
+            ..*args
+        },
+        Command::Run(args) => *args,
+        Command::Disasm(args) => {
+            disasm(&args);
+            return;
+        }
+    };
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if args.trace_log {
+        logger.filter_module("chip8_rust::cpu", log::LevelFilter::Trace);
+    }
+    logger.init();
+
+    let rom_picker = match &args.roms {
+        Some(dir) => match RomPicker::open(dir) {
+            Ok(rom_picker) => Some(rom_picker),
+            Err(err) => {
+                eprintln!("Cannot list the programs in {:?}: {:?}", dir, err);
+                std::process::exit(1);
+            }
+        },
+        // launched with nothing to run, such as from a directory full of programs
+        None if args.path.is_none() && args.frontend == Frontend::Window => {
+            RomPicker::open(Path::new("."))
+                .ok()
+                .filter(|rom_picker| !rom_picker.is_empty())
+        }
+        None => None,
+    };
+
+    // shown before the window is created, so that it cannot hold up the event loop
+    #[cfg(feature = "dialog")]
+    let picked_path = match &args.path {
+        None if args.frontend == Frontend::Window && rom_picker.is_none() => match pick_program() {
+            Some(path) => Some(path),
+            None => {
+                eprintln!("No program was picked, quitting");
+                return;
+            }
+        },
+        _ => None,
+    };
+    #[cfg(not(feature = "dialog"))]
+    let picked_path: Option<PathBuf> = None;
+
+    let program = match args.path.as_ref().or(picked_path.as_ref()) {
+        Some(path) => match read_program(path) {
+            Some(program) => Some((path.clone(), program)),
+            None => std::process::exit(1),
+        },
+        None => None,
+    };
+    // rather than opening a window that never shows anything
+    if let Some((path, program)) = &program {
+        if let Err(err) = validate_program(program) {
+            eprintln!("Cannot load program {:?}: {:?}", path, err);
+            std::process::exit(1);
+        }
+    }
+
+    if args.headless {
+        // clap makes sure that there is a program to run
+        if let Some((path, program)) = program {
+            run_headless(&path, program, &args);
+        }
+        return;
+    }
+
+    let keymap = match &args.keymap {
+        Some(path) => match Keymap::load(path) {
+            Ok(keymap) => keymap,
+            Err(err) => {
+                eprintln!("Cannot load keymap {:?}:\n{}", path, err);
+                return;
+            }
+        },
+        None => Keymap::default(),
+    };
+
+    if args.frontend == Frontend::Terminal {
+        #[cfg(feature = "tui")]
+        match program {
+            Some((path, program)) => run_terminal(&path, program, &args, &keymap),
+            None => eprintln!("The terminal needs a program to run, pass one with --path"),
+        }
+        #[cfg(not(feature = "tui"))]
+        eprintln!("This build has no terminal frontend, rebuild it with the tui feature");
         return;
     }
 
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
+        .with_title(WINDOW_TITLE)
+        .with_inner_size(PhysicalSize::new(
+            LORES_SCREEN_WIDTH as u32 * args.scale,
+            LORES_SCREEN_HEIGHT as u32 * args.scale,
+        ))
+        .with_fullscreen(args.fullscreen.then_some(Fullscreen::Borderless(None)))
         .build(&event_loop)
         .expect("Failed to build window");
 
-    let mut application = Application::new(&window, program).await;
+    let mut application = match Application::new(&window, program, rom_picker, &args, keymap).await
+    {
+        Ok(application) => application,
+        Err(err) => {
+            eprintln!("{}", explain_graphics_error(&err));
+            std::process::exit(1);
+        }
+    };
+    window.set_title(&application.title());
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
@@ -157,49 +1848,58 @@ async fn run() {
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == window.id() => {
-                if !application.input(event) {
-                    match event {
-                        WindowEvent::CloseRequested
-                        | WindowEvent::KeyboardInput {
-                            input:
-                                KeyboardInput {
-                                    state: ElementState::Pressed,
-                                    virtual_keycode: Some(VirtualKeyCode::Escape),
-                                    ..
-                                },
+            } if window_id == window.id() && !application.input(&window, event) => match event {
+                WindowEvent::CloseRequested
+                | WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::Escape),
                             ..
-                        } => *control_flow = ControlFlow::Exit,
-                        WindowEvent::Resized(physical_size) => {
-                            application.resize(*physical_size);
-                        }
-                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            application.resize(**new_inner_size);
-                        }
-                        _ => {}
-                    }
+                        },
+                    ..
+                } => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(physical_size) => {
+                    application.resize(*physical_size);
                 }
-            }
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    application.resize(**new_inner_size);
+                }
+                _ => {}
+            },
             Event::RedrawRequested(window_id) if window_id == window.id() => {
-                match application.render() {
-                    Ok(_) => {}
-                    Err(SurfaceError::Lost) => application.resize(application.window_size),
-                    Err(SurfaceError::OutOfMemory) => {
-                        log::error!("Surface ran out of memory!");
-                        *control_flow = ControlFlow::Exit;
-                    }
-                    Err(e) => {
-                        // all other errors (Outdated, Timeout) should be resolved by the next
-                        // frame
-                        log::error!("Render error: {:?}", e);
+                #[cfg(feature = "egui")]
+                application.update_overlay(&window);
+                application.update_hud();
+                if let Err(err) = application.render() {
+                    match render_error_action(&err, application.graphics.failed_frames()) {
+                        // configuring the surface again did not help, so the window probably
+                        // changed size before its Resized event arrived, such as when toggling
+                        // fullscreen quickly
+                        RenderErrorAction::Reconfigure => application.resize(window.inner_size()),
+                        RenderErrorAction::Skip => log::warn!("Render error: {:?}", err),
+                        RenderErrorAction::Exit => {
+                            eprintln!("Cannot render to the window, quitting: {:?}", err);
+                            *control_flow = ControlFlow::Exit;
+                        }
                     }
                 }
             }
             Event::MainEventsCleared => {
+                #[cfg(feature = "gamepad")]
+                application.handle_gamepad_events(&window);
+                application.handle_debugger_commands(&window);
+                #[cfg(feature = "dialog")]
+                application.handle_open_dialog(&window);
+                application.handle_cpu_status_events(&window, control_flow);
+                application.update_rates(&window);
+
                 // while the library docs say that a redraw always happens after this event, my
                 // experiment so far contradicts that claim. So just request redraw always.
-                window.request_redraw();
+                application.request_redraw(&window, control_flow);
             }
+            // every way of exiting the event loop ends up here
+            Event::LoopDestroyed => application.shutdown(),
             _ => {}
         }
     });
@@ -0,0 +1,24 @@
+#[derive(Clone, Copy, Debug)]
+pub struct Opcode {
+    pub raw: u16,
+    pub op: u8,
+    pub x: usize,
+    pub y: usize,
+    pub n: u8,
+    pub nn: u8,
+    pub nnn: u16,
+}
+
+impl Opcode {
+    pub fn decode(raw: u16) -> Self {
+        Self {
+            raw,
+            op: ((raw & 0xF000) >> 12) as u8,
+            x: ((raw & 0x0F00) >> 8) as usize,
+            y: ((raw & 0x00F0) >> 4) as usize,
+            n: (raw & 0x000F) as u8,
+            nn: (raw & 0x00FF) as u8,
+            nnn: raw & 0x0FFF,
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use egui::{ClippedPrimitive, Color32, Context, Grid, RichText, TexturesDelta};
+use winit::{event::WindowEvent, window::Window};
+
+use crate::{
+    cpu::{CpuIoEvents, CpuState},
+    disasm::disassemble,
+    frontend::LatestState,
+};
+
+// instructions shown before and after the one at the program counter
+const DISASSEMBLY_CONTEXT: usize = 6;
+
+// the keys in the layout of the COSMAC VIP keypad
+const KEYPAD: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// what the renderer draws on top of the screen for a single frame
+pub struct OverlayFrame {
+    pub primitives: Vec<ClippedPrimitive>,
+    pub textures_delta: TexturesDelta,
+    pub pixels_per_point: f32,
+}
+
+// a panel with the registers, stack, timers, keypad and the code around the program counter,
+// shown over the screen with F1
+pub struct DebugOverlay {
+    context: Context,
+    state: egui_winit::State,
+    visible: bool,
+    latest_state: LatestState,
+    // shown until the cpu publishes the next one
+    cpu_state: Option<CpuState>,
+}
+
+impl DebugOverlay {
+    pub fn new(window: &Window) -> Self {
+        // the overlay has no text to copy, so it does without the clipboard that the wayland
+        // display would give it
+        let mut state = egui_winit::State::new_with_wayland_display(None);
+        state.set_pixels_per_point(egui_winit::native_pixels_per_point(window));
+
+        Self {
+            context: Context::default(),
+            state,
+            visible: false,
+            latest_state: LatestState::new(),
+            cpu_state: None,
+        }
+    }
+
+    // for the cpu to publish its state to
+    pub fn latest_state(&self) -> LatestState {
+        self.latest_state.clone()
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    // returns whether the overlay used the event, such as a click on one of its buttons. Events
+    // only go to the overlay while it is shown.
+    pub fn on_event(&mut self, event: &WindowEvent) -> bool {
+        self.visible && self.state.on_event(&self.context, event).consumed
+    }
+
+    // lays out the panel for the next frame, returning it along with the events for the cpu from
+    // the buttons that were clicked
+    pub fn run(&mut self, window: &Window, paused: bool) -> (OverlayFrame, Vec<CpuIoEvents>) {
+        if let Some(cpu_state) = self.latest_state.take() {
+            self.cpu_state = Some(cpu_state);
+        }
+
+        let mut events = Vec::new();
+        let input = self.state.take_egui_input(window);
+        let output = self.context.run(input, |context| {
+            egui::Window::new("Debugger")
+                .default_pos([8.0, 8.0])
+                .resizable(false)
+                .show(context, |ui| {
+                    ui.horizontal(|ui| {
+                        if ui.button(if paused { "Resume" } else { "Pause" }).clicked() {
+                            events.push(CpuIoEvents::TogglePause);
+                        }
+                        if ui.add_enabled(paused, egui::Button::new("Step")).clicked() {
+                            events.push(CpuIoEvents::Step);
+                        }
+                        if ui.button("Reset").clicked() {
+                            events.push(CpuIoEvents::Reset);
+                        }
+                    });
+                    ui.separator();
+
+                    match &self.cpu_state {
+                        Some(cpu_state) => show_cpu_state(ui, cpu_state),
+                        None => {
+                            ui.label("Waiting for a program");
+                        }
+                    }
+                });
+        });
+        self.state
+            .handle_platform_output(window, &self.context, output.platform_output);
+
+        let frame = OverlayFrame {
+            primitives: self.context.tessellate(output.shapes),
+            textures_delta: output.textures_delta,
+            pixels_per_point: self.context.pixels_per_point(),
+        };
+        (frame, events)
+    }
+}
+
+fn show_cpu_state(ui: &mut egui::Ui, cpu_state: &CpuState) {
+    Grid::new("registers").show(ui, |ui| {
+        cpu_state
+            .variable_registers
+            .iter()
+            .enumerate()
+            .for_each(|(x, value)| {
+                ui.monospace(format!("V{:X}: {:02X}", x, value));
+                if x % 4 == 3 {
+                    ui.end_row();
+                }
+            });
+    });
+    ui.monospace(format!(
+        "PC: {:03X}  I: {:03X}",
+        cpu_state.program_counter, cpu_state.index_register
+    ));
+    ui.monospace(format!(
+        "Delay: {:02X}  Sound: {:02X}",
+        cpu_state.delay_timer, cpu_state.sound_timer
+    ));
+    let stack = cpu_state
+        .stack
+        .iter()
+        .map(|address| format!("{:03X}", address))
+        .collect::<Vec<_>>();
+    ui.monospace(format!("Stack: {}", stack.join(" ")));
+    ui.separator();
+
+    Grid::new("keypad").show(ui, |ui| {
+        KEYPAD.iter().for_each(|row| {
+            row.iter().for_each(|key| {
+                let text = RichText::new(format!("{:X}", key)).monospace();
+                if cpu_state.keypad_state & (1 << key) != 0 {
+                    ui.label(text.strong().color(Color32::LIGHT_GREEN));
+                } else {
+                    ui.label(text.weak());
+                }
+            });
+            ui.end_row();
+        });
+    });
+    ui.separator();
+
+    let program_counter = cpu_state.program_counter as usize;
+    let start = program_counter.saturating_sub(DISASSEMBLY_CONTEXT * 2);
+    let end = (program_counter + (DISASSEMBLY_CONTEXT + 1) * 2).min(cpu_state.memory.len());
+    disassemble(&cpu_state.memory[start..end], start as u16)
+        .iter()
+        .for_each(|line| {
+            let text = format!("{:03X}: {}", line.address, line.text);
+            if line.address as usize == program_counter {
+                ui.label(RichText::new(format!("> {}", text)).monospace().strong());
+            } else {
+                ui.monospace(format!("  {}", text));
+            }
+        });
+}
@@ -0,0 +1,84 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub foreground: [u8; 3],
+    pub background: [u8; 3],
+}
+
+impl Palette {
+    // RGBA for each combination of lit planes: none, plane 0 only, plane 1 only and both. The
+    // colors that only XO-CHIP programs can produce are shades between the background and the
+    // foreground.
+    pub fn plane_colors(&self) -> [[u8; 4]; 4] {
+        let shade = |thirds: i32| {
+            let mut color = [255; 4];
+            (0..3).for_each(|c| {
+                color[c] = (self.background[c] as i32
+                    + (self.foreground[c] as i32 - self.background[c] as i32) * thirds / 3)
+                    as u8;
+            });
+            color
+        };
+        [shade(0), shade(3), shade(2), shade(1)]
+    }
+}
+
+pub const DEFAULT_PALETTE_NAME: &str = "classic";
+
+pub const PALETTES: [(&str, Palette); 7] = [
+    (
+        DEFAULT_PALETTE_NAME,
+        Palette {
+            foreground: [0xFF, 0xFF, 0xFF],
+            background: [0x00, 0x00, 0x00],
+        },
+    ),
+    (
+        "phosphor",
+        Palette {
+            foreground: [0x33, 0xFF, 0x66],
+            background: [0x00, 0x11, 0x00],
+        },
+    ),
+    (
+        "amber",
+        Palette {
+            foreground: [0xFF, 0xB0, 0x00],
+            background: [0x1A, 0x0F, 0x00],
+        },
+    ),
+    (
+        "lcd",
+        Palette {
+            foreground: [0x22, 0x2A, 0x1E],
+            background: [0xA7, 0xB5, 0x94],
+        },
+    ),
+    (
+        "gameboy",
+        Palette {
+            foreground: [0x0F, 0x38, 0x0F],
+            background: [0x9B, 0xBC, 0x0F],
+        },
+    ),
+    (
+        "ibm",
+        Palette {
+            foreground: [0xFF, 0xFF, 0xFF],
+            background: [0x00, 0x00, 0xAA],
+        },
+    ),
+    (
+        "c64",
+        Palette {
+            foreground: [0x78, 0x69, 0xC4],
+            background: [0x40, 0x31, 0x8D],
+        },
+    ),
+];
+
+pub fn find_palette(name: &str) -> Option<Palette> {
+    PALETTES
+        .iter()
+        .find(|(palette_name, _)| palette_name.eq_ignore_ascii_case(name))
+        .map(|(_, palette)| *palette)
+}
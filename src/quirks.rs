@@ -0,0 +1,24 @@
+// Behaviors that differ between interpreters. The defaults follow the modern (Octo-style)
+// interpretation, where the behavior is ambiguous.
+#[derive(Clone, Copy, Debug)]
+pub struct Quirks {
+    // The original SUPER-CHIP scrolls by half the requested amount in lores mode, as it scrolls
+    // in hires pixels.
+    pub lores_half_scroll: bool,
+    // The original SUPER-CHIP sets VF to the number of sprite rows that collided in hires mode,
+    // instead of just 1.
+    pub hires_collision_rows: bool,
+    // Sprites are cut off at the edges of the screen. Some programs expect the parts past an edge
+    // to wrap around to the opposite one instead.
+    pub clip_sprites: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            lores_half_scroll: false,
+            hires_collision_rows: false,
+            clip_sprites: true,
+        }
+    }
+}
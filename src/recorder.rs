@@ -0,0 +1,122 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use gif::{Encoder, EncodingError, Frame, Repeat};
+
+use crate::cpu::{CpuScreenMem, LORES_SCREEN_HEIGHT, LORES_SCREEN_WIDTH};
+
+const FRAME_INTERVAL: Duration = Duration::from_millis(1000 / 30);
+
+struct RecordedFrame {
+    screen: CpuScreenMem,
+    captured_at: Instant,
+}
+
+pub struct GifRecorder {
+    frames: Vec<RecordedFrame>,
+    max_frames: usize,
+    last_capture_at: Option<Instant>,
+    dropping_frames: bool,
+}
+
+impl GifRecorder {
+    pub fn new(max_frames: usize) -> Self {
+        Self {
+            frames: Vec::new(),
+            max_frames,
+            last_capture_at: None,
+            dropping_frames: false,
+        }
+    }
+
+    // called on every redraw, only keeps a frame ~30 times per second and when the screen changed
+    pub fn capture(&mut self, screen: &CpuScreenMem) {
+        self.capture_at(screen, Instant::now());
+    }
+
+    // for recordings that do not run in real time, such as headless ones
+    pub fn capture_at(&mut self, screen: &CpuScreenMem, now: Instant) {
+        if let Some(last_capture_at) = self.last_capture_at {
+            if now - last_capture_at < FRAME_INTERVAL {
+                return;
+            }
+        }
+        self.last_capture_at = Some(now);
+
+        if let Some(frame) = self.frames.last() {
+            if frame.screen == *screen {
+                return;
+            }
+        }
+        if self.frames.len() >= self.max_frames {
+            if !self.dropping_frames {
+                log::warn!(
+                    "Recording is limited to {} frames, later frames are dropped",
+                    self.max_frames
+                );
+                self.dropping_frames = true;
+            }
+            return;
+        }
+
+        self.frames.push(RecordedFrame {
+            screen: *screen,
+            captured_at: now,
+        });
+    }
+
+    // each lores pixel becomes a scale x scale block, hires frames are scaled to the same size.
+    // The last frame lasts until stopped_at.
+    pub fn save(
+        &self,
+        path: &Path,
+        plane_colors: &[[u8; 4]; 4],
+        scale: u32,
+        stopped_at: Instant,
+    ) -> Result<(), EncodingError> {
+        let (width, height) = (
+            LORES_SCREEN_WIDTH * scale as usize,
+            LORES_SCREEN_HEIGHT * scale as usize,
+        );
+        let palette = plane_colors
+            .iter()
+            .flat_map(|color| color[0..3].to_vec())
+            .collect::<Vec<_>>();
+
+        let mut encoder = Encoder::new(File::create(path)?, width as u16, height as u16, &palette)?;
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        for (i, recorded) in self.frames.iter().enumerate() {
+            let next_captured_at = self
+                .frames
+                .get(i + 1)
+                .map_or(stopped_at, |next| next.captured_at);
+            let screen = &recorded.screen;
+
+            let mut frame = Frame {
+                width: width as u16,
+                height: height as u16,
+                // in hundredths of a second, and most viewers treat anything below 2 as 10
+                delay: ((next_captured_at - recorded.captured_at).as_millis() / 10)
+                    .clamp(2, u16::MAX as u128) as u16,
+                ..Frame::default()
+            };
+            frame.buffer = Cow::Owned(
+                (0..height)
+                    .flat_map(|y| {
+                        (0..width).map(move |x| {
+                            screen.pixel(x * screen.width() / width, y * screen.height() / height)
+                        })
+                    })
+                    .collect(),
+            );
+            encoder.write_frame(&frame)?;
+        }
+
+        Ok(())
+    }
+}
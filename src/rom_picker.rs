@@ -0,0 +1,124 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::cpu::{CpuScreenMem, HIRES_SCREEN_WIDTH, TEXT_CHAR_HEIGHT, TEXT_CHAR_WIDTH};
+
+pub const PROGRAM_EXTENSION: &str = "ch8";
+
+// the CHIP-8 keys that move through the list, like the movement keys of most games
+const KEY_UP: u8 = 0x2;
+const KEY_DOWN: u8 = 0x8;
+const KEY_PAGE_UP: u8 = 0x4;
+const KEY_PAGE_DOWN: u8 = 0x6;
+const KEY_SELECT: u8 = 0x5;
+
+const TITLE: &str = "PICK A PROGRAM";
+const LINE_HEIGHT: usize = TEXT_CHAR_HEIGHT + 1;
+// the programs start below the title and the line under it
+const LIST_TOP: usize = LINE_HEIGHT + 3;
+const VISIBLE_PROGRAMS: usize = 9;
+// the selected program is marked with > in the first column
+const NAME_LEFT: usize = TEXT_CHAR_WIDTH + 1;
+const MAX_NAME_LENGTH: usize = (HIRES_SCREEN_WIDTH - NAME_LEFT + 1) / TEXT_CHAR_WIDTH;
+
+// A list of the programs in a directory, drawn on the CHIP-8 screen and moved through with the
+// CHIP-8 keys, for picking the program to run without leaving the window.
+pub struct RomPicker {
+    programs: Vec<PathBuf>,
+    selected: usize,
+    // the first program shown, which follows the selected one once it leaves the screen
+    scroll: usize,
+}
+
+impl RomPicker {
+    // the .ch8 files in the directory, sorted by name
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let mut programs = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_program = path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case(PROGRAM_EXTENSION));
+            if is_program && path.is_file() {
+                programs.push(path);
+            }
+        }
+        programs.sort_by_key(|path| path.to_string_lossy().to_lowercase());
+
+        Ok(Self {
+            programs,
+            selected: 0,
+            scroll: 0,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.programs.is_empty()
+    }
+
+    // moves through the list, returning the program to run once one is selected
+    pub fn key_pressed(&mut self, key: u8) -> Option<PathBuf> {
+        let last = self.programs.len().checked_sub(1)?;
+        self.selected = match key {
+            // wraps around at either end, so that the last programs of a long list are close
+            KEY_UP if self.selected == 0 => last,
+            KEY_UP => self.selected - 1,
+            KEY_DOWN if self.selected == last => 0,
+            KEY_DOWN => self.selected + 1,
+            KEY_PAGE_UP => self.selected.saturating_sub(VISIBLE_PROGRAMS),
+            KEY_PAGE_DOWN => (self.selected + VISIBLE_PROGRAMS).min(last),
+            KEY_SELECT => return Some(self.programs[self.selected].clone()),
+            _ => return None,
+        };
+
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + VISIBLE_PROGRAMS {
+            self.scroll = self.selected + 1 - VISIBLE_PROGRAMS;
+        }
+        None
+    }
+
+    pub fn screen(&self) -> CpuScreenMem {
+        let mut screen = CpuScreenMem::new(true);
+        screen.draw_text(1, 1, TITLE);
+        // where in the list the selected program is, as the list may go on past the screen
+        let position = match self.programs.len() {
+            0 => String::new(),
+            count => format!("{}/{}", self.selected + 1, count),
+        };
+        screen.draw_text(
+            HIRES_SCREEN_WIDTH - position.len() * TEXT_CHAR_WIDTH,
+            1,
+            &position,
+        );
+        screen.planes[0][LINE_HEIGHT + 1] = screen.row_mask();
+
+        if self.programs.is_empty() {
+            screen.draw_text(NAME_LEFT, LIST_TOP, "NO .CH8 FILES HERE");
+            return screen;
+        }
+        self.programs
+            .iter()
+            .enumerate()
+            .skip(self.scroll)
+            .take(VISIBLE_PROGRAMS)
+            .for_each(|(i, path)| {
+                let top = LIST_TOP + (i - self.scroll) * LINE_HEIGHT;
+                if i == self.selected {
+                    screen.draw_text(1, top, ">");
+                }
+                let name = path
+                    .file_stem()
+                    .unwrap_or(path.as_os_str())
+                    .to_string_lossy()
+                    .chars()
+                    .take(MAX_NAME_LENGTH)
+                    .collect::<String>();
+                screen.draw_text(NAME_LEFT, top, &name);
+            });
+        screen
+    }
+}
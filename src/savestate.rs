@@ -0,0 +1,146 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::cpu::CpuState;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u16 = 7;
+const HEADER_SIZE: usize = MAGIC.len() + 2 + 8;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(io::Error),
+    Format(bincode::Error),
+    NotAStateFile,
+    UnsupportedVersion { actual: u16, supported: u16 },
+    DifferentProgram,
+}
+
+// FNV-1a, used to tell apart states saved from different programs. std's hashers are not
+// guaranteed to be stable across releases, so they cannot be written to disk.
+pub fn program_hash(program: &[u8]) -> u64 {
+    program.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn write_state_file(
+    path: &Path,
+    program_hash: u64,
+    state: &CpuState,
+) -> Result<(), SaveStateError> {
+    let payload = bincode::serialize(state).map_err(SaveStateError::Format)?;
+
+    let mut file = fs::File::create(path).map_err(SaveStateError::Io)?;
+    file.write_all(MAGIC)
+        .and_then(|_| file.write_all(&VERSION.to_le_bytes()))
+        .and_then(|_| file.write_all(&program_hash.to_le_bytes()))
+        .and_then(|_| file.write_all(&payload))
+        .map_err(SaveStateError::Io)
+}
+
+pub fn read_state_file(path: &Path, program_hash: u64) -> Result<CpuState, SaveStateError> {
+    let bytes = fs::read(path).map_err(SaveStateError::Io)?;
+
+    if bytes.len() < HEADER_SIZE || &bytes[0..MAGIC.len()] != MAGIC {
+        return Err(SaveStateError::NotAStateFile);
+    }
+
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(SaveStateError::UnsupportedVersion {
+            actual: version,
+            supported: VERSION,
+        });
+    }
+
+    let mut hash_bytes = [0; 8];
+    hash_bytes.copy_from_slice(&bytes[6..HEADER_SIZE]);
+    if u64::from_le_bytes(hash_bytes) != program_hash {
+        return Err(SaveStateError::DifferentProgram);
+    }
+
+    bincode::deserialize(&bytes[HEADER_SIZE..]).map_err(SaveStateError::Format)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const PROGRAM: [u8; 4] = [0x60, 0x2A, 0x12, 0x02];
+
+    fn state() -> CpuState {
+        CpuState {
+            memory: (0..4096).map(|i| i as u8).collect(),
+            screen_hires: true,
+            screen_planes: vec![vec![0x55; 64], vec![0; 64]],
+            selected_planes: 1,
+            keypad_state: 0x8001,
+            delay_timer: 30,
+            sound_timer: 4,
+            program_counter: 0x202,
+            index_register: 0x300,
+            stack: vec![0x204, 0x210],
+            variable_registers: [0x2A; 16],
+            waiting_for_key: None,
+            audio_pattern: None,
+            rpl_flags: [7; 16],
+        }
+    }
+
+    // a file of its own for each test, as they run in parallel
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("chip8-{}-{}.state", std::process::id(), name))
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let path = temp_path("round-trip");
+        write_state_file(&path, program_hash(&PROGRAM), &state()).unwrap();
+        let read = read_state_file(&path, program_hash(&PROGRAM));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap(), state());
+    }
+
+    #[test]
+    fn rejects_state_of_different_program() {
+        let path = temp_path("different-program");
+        write_state_file(&path, program_hash(&PROGRAM), &state()).unwrap();
+        let read = read_state_file(&path, program_hash(&[0x12, 0x00]));
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(read, Err(SaveStateError::DifferentProgram)));
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let path = temp_path("bad-magic");
+        write_state_file(&path, program_hash(&PROGRAM), &state()).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[0] = b'X';
+        fs::write(&path, bytes).unwrap();
+        let read = read_state_file(&path, program_hash(&PROGRAM));
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(read, Err(SaveStateError::NotAStateFile)));
+    }
+
+    #[test]
+    fn rejects_unsupported_version() {
+        let path = temp_path("unsupported-version");
+        write_state_file(&path, program_hash(&PROGRAM), &state()).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+        let read = read_state_file(&path, program_hash(&PROGRAM));
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            read,
+            Err(SaveStateError::UnsupportedVersion { actual, supported: VERSION })
+                if actual == VERSION + 1
+        ));
+    }
+}
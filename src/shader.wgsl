@@ -9,14 +9,18 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
 }
 
+// xy scales the screen quad, zw then moves it
 @group(0) @binding(0)
-var<uniform> ratios: vec2<f32>;
+var<uniform> ratios: vec4<f32>;
+// x is 1.0 when the grid between the pixels is shown, the rest is padding
+@group(0) @binding(1)
+var<uniform> grid: vec4<f32>;
 
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
     var out: VertexOutput;
     out.tex_coords = in.tex_coords;
-    out.clip_position = vec4<f32>(in.position.x * ratios.x, in.position.y * ratios.y, 0.0, 1.0);
+    out.clip_position = vec4<f32>(in.position.x * ratios.x + ratios.z, in.position.y * ratios.y + ratios.w, 0.0, 1.0);
     return out;
 }
 
@@ -28,5 +32,16 @@ var screen_texture_sampler: sampler;
 // Fragment shader
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(screen_texture, screen_texture_sampler, in.tex_coords);
+    let color = textureSample(screen_texture, screen_texture_sampler, in.tex_coords);
+
+    // where in its pixel the fragment is, and how much of a pixel a fragment covers, which keeps
+    // the lines one fragment wide at any window size and resolution
+    let texel = in.tex_coords * vec2<f32>(textureDimensions(screen_texture));
+    let fragment_size = fwidth(texel);
+    // the lines would cover most of the screen when the pixels are tiny
+    let on_line = any(fract(texel) < fragment_size) && all(fragment_size < vec2<f32>(0.34));
+    if (grid.x > 0.5 && on_line) {
+        return vec4<f32>(color.rgb * 0.5, color.a);
+    }
+    return color;
 }
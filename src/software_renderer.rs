@@ -0,0 +1,153 @@
+use std::ops::Range;
+
+use softbuffer::{GraphicsContext, SoftBufferError};
+use wgpu::{PresentMode, SurfaceError};
+use winit::{dpi::PhysicalSize, window::Window};
+
+use crate::{
+    graphics::{
+        calculate_screen_rect, Renderer, SCREEN_PX_HEIGHT, SCREEN_PX_STRIDE, SCREEN_PX_WIDTH,
+    },
+    hud::HudImage,
+};
+
+// like in the shader, the grid lines would cover most of the screen when the pixels are smaller
+const MIN_GRID_PIXEL_SIZE: u32 = 3;
+
+// Draws the screen on the cpu and hands the finished frame to the window, for when wgpu cannot
+// find a graphics adapter, such as in virtual machines or over X forwarding. Much slower than
+// wgpu at large window sizes, but the screen is tiny.
+pub struct SoftbufferRenderer {
+    context: GraphicsContext,
+    window_size: PhysicalSize<u32>,
+    screen_width: u32,
+    screen_height: u32,
+    // the RGBA pixels of the screen
+    screen_pixels: Vec<u8>,
+    // the 0RGB pixels of the whole window, reused between frames
+    frame: Vec<u32>,
+    integer_scale: bool,
+    grid: bool,
+    hud: Option<HudImage>,
+}
+
+impl SoftbufferRenderer {
+    pub fn new(window: &Window, integer_scale: bool, grid: bool) -> Result<Self, SoftBufferError> {
+        // SAFETY: Called on main thread, and like the wgpu surface, the window outlives the
+        // renderer.
+        let context = unsafe { GraphicsContext::new(window, window) }?;
+        log::info!("Rendering on the cpu with softbuffer");
+
+        Ok(Self {
+            context,
+            window_size: window.inner_size(),
+            screen_width: SCREEN_PX_WIDTH as u32,
+            screen_height: SCREEN_PX_HEIGHT as u32,
+            screen_pixels: vec![0; SCREEN_PX_STRIDE * SCREEN_PX_WIDTH * SCREEN_PX_HEIGHT],
+            frame: Vec::new(),
+            integer_scale,
+            grid,
+            hud: None,
+        })
+    }
+}
+
+fn to_0rgb(color: [u8; 3]) -> u32 {
+    u32::from_be_bytes([0, color[0], color[1], color[2]])
+}
+
+impl Renderer for SoftbufferRenderer {
+    fn set_screen_size(&mut self, width: u32, height: u32) {
+        self.screen_width = width;
+        self.screen_height = height;
+        self.screen_pixels = vec![0; SCREEN_PX_STRIDE * (width * height) as usize];
+    }
+
+    fn write_screen_rows(&mut self, rows: Range<usize>, pixels: &[u8]) {
+        let row_length = SCREEN_PX_STRIDE * self.screen_width as usize;
+        self.screen_pixels[rows.start * row_length..rows.end * row_length]
+            .copy_from_slice(&pixels[..rows.len() * row_length]);
+    }
+
+    fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.window_size = size;
+    }
+
+    fn set_grid(&mut self, grid: bool) {
+        self.grid = grid;
+    }
+
+    fn present(&mut self, background: [u8; SCREEN_PX_STRIDE]) -> Result<(), SurfaceError> {
+        let PhysicalSize { width, height } = self.window_size;
+        let (screen_width, screen_height) = (self.screen_width, self.screen_height);
+        let [left, top, quad_width, quad_height] = calculate_screen_rect(
+            &self.window_size,
+            screen_width,
+            screen_height,
+            self.integer_scale,
+        );
+        let grid = self.grid && quad_width >= screen_width * MIN_GRID_PIXEL_SIZE;
+
+        // the bars around the screen blend in with its background
+        self.frame.clear();
+        self.frame.resize(
+            (width * height) as usize,
+            to_0rgb([background[0], background[1], background[2]]),
+        );
+
+        // nearest neighbour, like the sampler of the wgpu renderer
+        let screen_x = |x: u32| x * screen_width / quad_width;
+        let screen_y = |y: u32| y * screen_height / quad_height;
+        (0..quad_height).for_each(|y| {
+            let pixel_y = screen_y(y);
+            let row_on_line = y == 0 || screen_y(y - 1) != pixel_y;
+            (0..quad_width).for_each(|x| {
+                let pixel_x = screen_x(x);
+                let i = SCREEN_PX_STRIDE * (pixel_y * screen_width + pixel_x) as usize;
+                let mut color = [
+                    self.screen_pixels[i],
+                    self.screen_pixels[i + 1],
+                    self.screen_pixels[i + 2],
+                ];
+                // the lines run along the top and left edges of each pixel
+                if grid && (row_on_line || x == 0 || screen_x(x - 1) != pixel_x) {
+                    color = color.map(|c| c / 2);
+                }
+                self.frame[((top + y) * width + left + x) as usize] = to_0rgb(color);
+            });
+        });
+
+        // blended over the top left corner, cut off where the window is smaller than the HUD
+        if let Some(hud) = &self.hud {
+            (0..hud.height.min(height)).for_each(|y| {
+                (0..hud.width.min(width)).for_each(|x| {
+                    let i = SCREEN_PX_STRIDE * (y * hud.width + x) as usize;
+                    let [red, green, blue, alpha] = [0, 1, 2, 3].map(|c| hud.pixels[i + c] as u32);
+                    let pixel = &mut self.frame[(y * width + x) as usize];
+                    let [_, frame_red, frame_green, frame_blue] = pixel.to_be_bytes();
+                    let blend = |color: u32, frame: u8| {
+                        ((color * alpha + frame as u32 * (255 - alpha)) / 255) as u8
+                    };
+                    *pixel = to_0rgb([
+                        blend(red, frame_red),
+                        blend(green, frame_green),
+                        blend(blue, frame_blue),
+                    ]);
+                });
+            });
+        }
+
+        self.context
+            .set_buffer(&self.frame, width as u16, height as u16);
+        Ok(())
+    }
+
+    fn set_hud(&mut self, hud: Option<&HudImage>) {
+        self.hud = hud.cloned();
+    }
+
+    // set_buffer draws right away
+    fn present_mode(&self) -> PresentMode {
+        PresentMode::Immediate
+    }
+}
@@ -0,0 +1,25 @@
+// The lock that the cpu shares with the timers and the frontends, such as for the timer values.
+// Without std there are no threads to put to sleep while waiting, so it spins instead.
+
+#[cfg(feature = "std")]
+pub use std::sync::{Mutex, MutexGuard};
+
+#[cfg(not(feature = "std"))]
+pub use spin::MutexGuard;
+
+#[cfg(not(feature = "std"))]
+#[derive(Default)]
+pub struct Mutex<T>(spin::Mutex<T>);
+
+#[cfg(not(feature = "std"))]
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self(spin::Mutex::new(value))
+    }
+
+    // never fails, as without std a panic cannot leave the lock poisoned. Returns a Result
+    // anyway, so that locking looks the same with and without std.
+    pub fn lock(&self) -> Result<MutexGuard<'_, T>, core::convert::Infallible> {
+        Ok(self.0.lock())
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    collections::HashMap,
+    io::{self, Stdout, Write},
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{
+        self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute, queue,
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+    terminal::{
+        self, disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen,
+        LeaveAlternateScreen,
+    },
+};
+
+use crate::{
+    cpu::{CpuIoEvents, CpuScreenMem},
+    palette::Palette,
+};
+
+// Most terminals only report key presses, repeating them while the key is held, so a key counts
+// as released once it has not been repeated for this long. It has to outlast the delay before the
+// first repeat, which is usually 250 to 600ms, so a short tap is held for a bit longer than it
+// really was. Terminals that support the kitty keyboard protocol report releases, and do not need
+// this.
+const KEY_RELEASE_TIMEOUT: Duration = Duration::from_millis(600);
+
+// each cell shows two pixels on top of each other, the upper one in the foreground color
+const HALF_BLOCK: char = '\u{2580}';
+
+pub enum TerminalEvent {
+    // a CHIP-8 key was pressed or released
+    Keypad(CpuIoEvents),
+    // any other key that was pressed
+    Key(KeyCode),
+    Quit,
+}
+
+// must be dropped before the process exits, so that the terminal goes back to normal
+pub struct Terminal {
+    stdout: Stdout,
+    keys: HashMap<char, u8>,
+    plane_colors: [Color; 4],
+    // when each held key was last reported
+    held_keys: HashMap<u8, Instant>,
+    reports_releases: bool,
+    screen: Option<CpuScreenMem>,
+    status: String,
+}
+
+impl Terminal {
+    pub fn new(palette: Palette, keys: HashMap<char, u8>) -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, Hide)?;
+
+        let reports_releases = matches!(terminal::supports_keyboard_enhancement(), Ok(true));
+        if reports_releases {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        // a panic would otherwise leave the terminal in raw mode, with its message garbled
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore(reports_releases);
+            default_hook(info);
+        }));
+
+        Ok(Self {
+            stdout,
+            keys,
+            plane_colors: palette
+                .plane_colors()
+                .map(|[r, g, b, _]| Color::Rgb { r, g, b }),
+            held_keys: HashMap::new(),
+            reports_releases,
+            screen: None,
+            status: String::new(),
+        })
+    }
+
+    // waits up to timeout for input, then returns everything that happened
+    pub fn poll(&mut self, timeout: Duration) -> io::Result<Vec<TerminalEvent>> {
+        let mut events = Vec::new();
+        let mut timeout = timeout;
+
+        while event::poll(timeout)? {
+            timeout = Duration::ZERO;
+            match event::read()? {
+                Event::Key(key_event) => {
+                    if key_event.code == KeyCode::Esc
+                        || (key_event.code == KeyCode::Char('c')
+                            && key_event.modifiers.contains(KeyModifiers::CONTROL))
+                    {
+                        events.push(TerminalEvent::Quit);
+                        continue;
+                    }
+
+                    let chip8_key = match key_event.code {
+                        KeyCode::Char(c) => self.keys.get(&c.to_ascii_lowercase()).copied(),
+                        _ => None,
+                    };
+                    match (chip8_key, key_event.kind) {
+                        (Some(key), KeyEventKind::Release) => {
+                            if self.held_keys.remove(&key).is_some() {
+                                events.push(TerminalEvent::Keypad(CpuIoEvents::KeyReleased(key)));
+                            }
+                        }
+                        (Some(key), _) => {
+                            if self.held_keys.insert(key, Instant::now()).is_none() {
+                                events.push(TerminalEvent::Keypad(CpuIoEvents::KeyPressed(key)));
+                            }
+                        }
+                        (None, KeyEventKind::Press) => {
+                            events.push(TerminalEvent::Key(key_event.code))
+                        }
+                        (None, _) => {}
+                    }
+                }
+                Event::Resize(_, _) => {
+                    execute!(self.stdout, Clear(ClearType::All))?;
+                    self.redraw()?;
+                }
+                _ => {}
+            }
+        }
+
+        if !self.reports_releases {
+            let now = Instant::now();
+            self.held_keys.retain(|key, last_seen| {
+                let held = now.duration_since(*last_seen) < KEY_RELEASE_TIMEOUT;
+                if !held {
+                    events.push(TerminalEvent::Keypad(CpuIoEvents::KeyReleased(*key)));
+                }
+                held
+            });
+        }
+
+        Ok(events)
+    }
+
+    pub fn draw(&mut self, screen: &CpuScreenMem) -> io::Result<()> {
+        // the lores screen does not cover all of the hires one
+        if matches!(self.screen, Some(previous) if previous.hires != screen.hires) {
+            queue!(self.stdout, ResetColor, Clear(ClearType::All))?;
+        }
+        self.screen = Some(*screen);
+        self.redraw()
+    }
+
+    // shown below the screen
+    pub fn set_status(&mut self, status: &str) -> io::Result<()> {
+        if self.status != status {
+            self.status = status.to_string();
+            self.redraw()?;
+        }
+        Ok(())
+    }
+
+    fn redraw(&mut self) -> io::Result<()> {
+        let screen = match &self.screen {
+            Some(screen) => *screen,
+            None => CpuScreenMem::new(false),
+        };
+        let (width, height) = (screen.width(), screen.height());
+        let rows = (height + 1) / 2;
+        let (columns, lines) = terminal::size()?;
+
+        if (columns as usize) < width || (lines as usize) < rows + 1 {
+            queue!(
+                self.stdout,
+                ResetColor,
+                Clear(ClearType::All),
+                MoveTo(0, 0),
+                Print(format!(
+                    "The terminal needs to be at least {}x{} to show the screen",
+                    width,
+                    rows + 1
+                ))
+            )?;
+            return self.stdout.flush();
+        }
+
+        for row in 0..rows {
+            queue!(self.stdout, MoveTo(0, row as u16))?;
+            // only changing the colors where they differ keeps the output small
+            let mut colors = None;
+            for x in 0..width {
+                let cell_colors = (
+                    screen.pixel(x, row * 2) as usize,
+                    screen.pixel(x, row * 2 + 1) as usize,
+                );
+                if colors != Some(cell_colors) {
+                    colors = Some(cell_colors);
+                    queue!(
+                        self.stdout,
+                        SetForegroundColor(self.plane_colors[cell_colors.0]),
+                        SetBackgroundColor(self.plane_colors[cell_colors.1])
+                    )?;
+                }
+                queue!(self.stdout, Print(HALF_BLOCK))?;
+            }
+        }
+        queue!(
+            self.stdout,
+            ResetColor,
+            MoveTo(0, rows as u16),
+            Clear(ClearType::CurrentLine),
+            Print(&self.status)
+        )?;
+        self.stdout.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        restore(self.reports_releases);
+    }
+}
+
+fn restore(reports_releases: bool) {
+    let mut stdout = io::stdout();
+    if reports_releases {
+        let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+    }
+    let _ = execute!(stdout, ResetColor, Show, LeaveAlternateScreen);
+    let _ = disable_raw_mode();
+}
@@ -1,23 +1,27 @@
 use std::{
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     time::Duration,
 };
 
-use rodio::{source::SineWave, OutputStream, Sink};
+use crate::frontend::Buzzer;
 
 const FREQUENCY: u32 = 60;
-const SINE_WAVE_HZ: f32 = 250f32;
 
 pub struct Timer {
     value: Arc<Mutex<u8>>,
-    sound: bool,
+    paused: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
 }
 
 impl Timer {
-    pub fn new(sound: bool) -> Self {
+    pub fn new(paused: Arc<AtomicBool>) -> Self {
         Self {
             value: Arc::new(Mutex::new(0)),
-            sound,
+            paused,
+            stopped: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -25,42 +29,36 @@ impl Timer {
         self.value.clone()
     }
 
-    pub fn run(&self) {
+    // setting it makes run return within a tick
+    pub fn get_stopped_arc(&self) -> Arc<AtomicBool> {
+        self.stopped.clone()
+    }
+
+    // the sound timer comes with a buzzer, the delay timer stays silent
+    pub fn run(&self, mut buzzer: Option<&mut dyn Buzzer>) {
         let delay_count = Duration::from_secs_f32(1f32 / FREQUENCY as f32);
 
-        // NOTE: We must keep both values in the tuple returned by try_default().
-        // Dropping the first value will cause the second value to be invalid, as
-        // it will cause the sound device to be dropped.
-        let sound = if self.sound {
-            let sound_stream =
-                OutputStream::try_default().expect("Cannot create sound output stream");
-            let sink = Sink::try_new(&sound_stream.1).expect("Cannot create sound sink");
-            sink.append(SineWave::new(SINE_WAVE_HZ));
-            Some((sound_stream, sink))
-        } else {
-            None
-        };
+        while !self.stopped.load(Ordering::Relaxed) {
+            let paused = self.paused.load(Ordering::Relaxed);
 
-        loop {
             let current_value;
             {
                 let mut value = self.value.lock().unwrap();
-                if *value != 0 {
+                if *value != 0 && !paused {
                     *value -= 1;
                 }
                 current_value = *value;
             }
 
-            if let Some((_, sound_sink)) = &sound {
-                if current_value == 0 && !sound_sink.is_paused() {
-                    sound_sink.pause();
-                }
-                if current_value != 0 && sound_sink.is_paused() {
-                    sound_sink.play();
-                }
+            if let Some(buzzer) = &mut buzzer {
+                buzzer.set_playing(current_value != 0 && !paused);
             }
 
             std::thread::sleep(delay_count);
         }
+
+        if let Some(buzzer) = &mut buzzer {
+            buzzer.set_playing(false);
+        }
     }
 }
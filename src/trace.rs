@@ -0,0 +1,80 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+pub struct Tracer {
+    // None once the limit is reached or writing failed
+    writer: Option<BufWriter<File>>,
+    limit: Option<u64>,
+    instructions_traced: u64,
+    timers: Option<(u8, u8)>,
+}
+
+impl Tracer {
+    pub fn create(path: &Path, limit: Option<u64>) -> io::Result<Self> {
+        Ok(Self {
+            writer: Some(BufWriter::new(File::create(path)?)),
+            limit,
+            instructions_traced: 0,
+            timers: None,
+        })
+    }
+
+    pub fn instruction(&mut self, line: &str) {
+        self.write_line(line);
+        self.instructions_traced += 1;
+
+        if self.writer.is_some() && Some(self.instructions_traced) == self.limit {
+            log::info!(
+                "Trace limit of {} instructions reached",
+                self.instructions_traced
+            );
+            self.flush();
+            self.writer = None;
+        }
+    }
+
+    pub fn event(&mut self, line: &str) {
+        self.write_line(&format!("---  {}", line));
+    }
+
+    // the timers count down on their own threads, so only the changes that the cpu gets to
+    // observe between its instruction batches are recorded
+    pub fn timers(&mut self, delay_timer: u8, sound_timer: u8) {
+        if let Some((last_delay_timer, last_sound_timer)) = self.timers {
+            if last_delay_timer != delay_timer {
+                self.event(&format!(
+                    "DT: {:#04x} -> {:#04x}",
+                    last_delay_timer, delay_timer
+                ));
+            }
+            if last_sound_timer != sound_timer {
+                self.event(&format!(
+                    "ST: {:#04x} -> {:#04x}",
+                    last_sound_timer, sound_timer
+                ));
+            }
+        }
+        self.timers = Some((delay_timer, sound_timer));
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(writer) = &mut self.writer {
+            if let Err(err) = writer.flush() {
+                log::error!("Cannot write trace, tracing stopped: {:?}", err);
+                self.writer = None;
+            }
+        }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if let Some(writer) = &mut self.writer {
+            if let Err(err) = writeln!(writer, "{}", line) {
+                log::error!("Cannot write trace, tracing stopped: {:?}", err);
+                self.writer = None;
+            }
+        }
+    }
+}
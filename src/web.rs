@@ -0,0 +1,119 @@
+use std::sync::{
+    atomic::AtomicBool,
+    mpsc::{self, Sender},
+    Arc, Mutex,
+};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    cpu::{Cpu, CpuConfig, CpuIoEvents},
+    frontend::NoFrontend,
+    palette::{find_palette, DEFAULT_PALETTE_NAME},
+};
+
+// browsers give the cpu and the timers no threads of their own, so the page calls run_frame 60
+// times a second (such as from requestAnimationFrame), draws the frame onto a canvas and plays
+// the beep itself
+#[wasm_bindgen]
+pub struct WebEmulator {
+    cpu: Cpu,
+    cpu_io_sender: Sender<CpuIoEvents>,
+    delay_timer_arc: Arc<Mutex<u8>>,
+    sound_timer_arc: Arc<Mutex<u8>>,
+    plane_colors: [[u8; 4]; 4],
+    running: bool,
+}
+
+#[wasm_bindgen(start)]
+pub fn start() {
+    console_error_panic_hook::set_once();
+    let _ = console_log::init_with_level(log::Level::Info);
+}
+
+#[wasm_bindgen]
+impl WebEmulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        program: &[u8],
+        xochip: bool,
+        palette: Option<String>,
+    ) -> Result<WebEmulator, JsError> {
+        let palette_name = palette.unwrap_or_else(|| DEFAULT_PALETTE_NAME.to_string());
+        let palette = find_palette(&palette_name)
+            .ok_or_else(|| JsError::new(&format!("Unknown palette {:?}", palette_name)))?;
+
+        let (cpu_io_sender, cpu_io_receiver) = mpsc::channel();
+        let delay_timer_arc = Arc::new(Mutex::new(0));
+        let sound_timer_arc = Arc::new(Mutex::new(0));
+        let cpu = Cpu::new(
+            program.to_vec(),
+            CpuConfig {
+                xochip,
+                ..CpuConfig::default()
+            },
+            NoFrontend,
+            cpu_io_receiver,
+            NoFrontend,
+            delay_timer_arc.clone(),
+            sound_timer_arc.clone(),
+            Arc::new(AtomicBool::new(false)),
+        )
+        .map_err(|err| JsError::new(&format!("Cannot load program: {:?}", err)))?;
+
+        Ok(Self {
+            cpu,
+            cpu_io_sender,
+            delay_timer_arc,
+            sound_timer_arc,
+            plane_colors: palette.plane_colors(),
+            running: true,
+        })
+    }
+
+    pub fn key_down(&self, key: u8) {
+        let _ = self.cpu_io_sender.send(CpuIoEvents::KeyPressed(key & 0xF));
+    }
+
+    pub fn key_up(&self, key: u8) {
+        let _ = self.cpu_io_sender.send(CpuIoEvents::KeyReleased(key & 0xF));
+    }
+
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    // returns false once the program exited or crashed
+    pub fn run_frame(&mut self) -> bool {
+        if self.running {
+            self.running = self.cpu.run_tick();
+            [&self.delay_timer_arc, &self.sound_timer_arc]
+                .iter()
+                .for_each(|timer| {
+                    let mut value = timer.lock().unwrap();
+                    *value = value.saturating_sub(1);
+                });
+        }
+        self.running
+    }
+
+    pub fn sound_playing(&self) -> bool {
+        *self.sound_timer_arc.lock().unwrap() != 0
+    }
+
+    pub fn width(&self) -> usize {
+        self.cpu.screen().width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.cpu.screen().height()
+    }
+
+    // RGBA, row by row, as ImageData expects
+    pub fn frame(&self) -> Vec<u8> {
+        let screen = self.cpu.screen();
+        let mut frame = vec![0; screen.width() * screen.height() * 4];
+        screen.write_rgba(0..screen.height(), &self.plane_colors, &mut frame);
+        frame
+    }
+}
@@ -0,0 +1,148 @@
+// Runs small programs headlessly through the library and checks the screen and the registers that
+// they leave behind. A screen that differs is printed along with the expected one.
+
+use std::sync::{atomic::AtomicBool, Arc, Mutex};
+
+use chip8_rust::{
+    cpu::{Cpu, CpuConfig, CpuScreenMem},
+    frontend::NoFrontend,
+};
+
+fn run(program: &[u8], config: CpuConfig, cycles: u64) -> Cpu {
+    let mut cpu = Cpu::new(
+        program.to_vec(),
+        config,
+        NoFrontend,
+        NoFrontend,
+        NoFrontend,
+        Arc::new(Mutex::new(0)),
+        Arc::new(Mutex::new(0)),
+        Arc::new(AtomicBool::new(false)),
+    )
+    .unwrap();
+    cpu.run_headless(cycles).unwrap();
+    cpu
+}
+
+// The rows start in the top left corner, the rest of the screen is unlit. Returns both screens
+// when they differ.
+fn screen_mismatch(screen: &CpuScreenMem, rows: &[&str]) -> Option<String> {
+    let expected = (0..screen.height())
+        .map(|y| {
+            let row = rows.get(y).copied().unwrap_or("");
+            format!("{:.<width$}\n", row, width = screen.width())
+        })
+        .collect::<String>();
+    let actual = screen.ascii_art();
+    (actual != expected).then(|| format!("Expected:\n{}Actual:\n{}", expected, actual))
+}
+
+fn assert_screen(screen: &CpuScreenMem, rows: &[&str]) {
+    if let Some(mismatch) = screen_mismatch(screen, rows) {
+        panic!("Screen does not match\n{}", mismatch);
+    }
+}
+
+// the flags of an addition, a subtraction and a shift, drawn as digits
+const FLAGS: [u8; 44] = [
+    0x60, 0xFF, // 200: V0 = FF
+    0x61, 0x02, // 202: V1 = 2
+    0x80, 0x14, // 204: V0 += V1
+    0x8A, 0xF0, // 206: VA = VF
+    0x60, 0x03, // 208: V0 = 3
+    0x61, 0x05, // 20A: V1 = 5
+    0x80, 0x15, // 20C: V0 -= V1
+    0x8B, 0xF0, // 20E: VB = VF
+    0x62, 0x81, // 210: V2 = 81
+    0x82, 0x0E, // 212: V2 <<= 1
+    0x8C, 0xF0, // 214: VC = VF
+    0x64, 0x00, // 216: V4 = 0
+    0x65, 0x00, // 218: V5 = 0
+    0xFA, 0x29, // 21A: I = digit VA
+    0xD4, 0x55, // 21C: draw 8x5 at V4, V5
+    0x74, 0x05, // 21E: V4 += 5
+    0xFB, 0x29, // 220: I = digit VB
+    0xD4, 0x55, // 222: draw 8x5 at V4, V5
+    0x74, 0x05, // 224: V4 += 5
+    0xFC, 0x29, // 226: I = digit VC
+    0xD4, 0x55, // 228: draw 8x5 at V4, V5
+    0x00, 0xFD, // 22A: exit
+];
+
+#[test]
+fn flags_program_draws_its_flags() {
+    let cpu = run(&FLAGS, CpuConfig::default(), 1000);
+
+    assert_screen(
+        cpu.screen(),
+        &[
+            "..#..####...#.",
+            ".##..#..#..##.",
+            "..#..#..#...#.",
+            "..#..#..#...#.",
+            ".###.####..###",
+        ],
+    );
+    assert_eq!(
+        [0x0, 0x2, 0xA, 0xB, 0xC].map(|x| cpu.variable_register(x)),
+        [0xFE, 0x02, 1, 0, 1]
+    );
+    // stopped at the exit, long before running out of cycles
+    assert_eq!(cpu.program_counter(), 0x22C);
+}
+
+// the digits 0 to 9 at random positions
+const RANDOM_DIGITS: [u8; 16] = [
+    0xC0, 0x3F, // 200: V0 = random & 3F
+    0xC1, 0x1F, // 202: V1 = random & 1F
+    0xF2, 0x29, // 204: I = digit V2
+    0xD0, 0x15, // 206: draw 8x5 at V0, V1
+    0x72, 0x01, // 208: V2 += 1
+    0x32, 0x0A, // 20A: skip if V2 == 10
+    0x12, 0x00, // 20C: jump to 200
+    0x00, 0xFD, // 20E: exit
+];
+
+fn run_random_digits(seed: u64) -> Cpu {
+    run(
+        &RANDOM_DIGITS,
+        CpuConfig {
+            rng_seed: Some(seed),
+            ..CpuConfig::default()
+        },
+        1000,
+    )
+}
+
+#[test]
+fn seeded_random_program_repeats_its_screen() {
+    let cpu = run_random_digits(42);
+    assert_eq!(cpu.variable_register(2), 10);
+
+    let again = run_random_digits(42);
+    assert_screen(
+        again.screen(),
+        &cpu.screen().ascii_art().lines().collect::<Vec<_>>(),
+    );
+    assert_eq!(
+        (0..16)
+            .map(|x| again.variable_register(x))
+            .collect::<Vec<_>>(),
+        (0..16)
+            .map(|x| cpu.variable_register(x))
+            .collect::<Vec<_>>()
+    );
+
+    let other = run_random_digits(43);
+    assert_ne!(other.screen().ascii_art(), cpu.screen().ascii_art());
+}
+
+#[test]
+fn mismatched_screen_shows_both_screens() {
+    let cpu = run(&FLAGS, CpuConfig::default(), 1000);
+    let mismatch = screen_mismatch(cpu.screen(), &["#"]).unwrap();
+
+    let (expected, actual) = mismatch.split_once("Actual:\n").unwrap();
+    assert!(expected.starts_with(&format!("Expected:\n#{}\n", ".".repeat(63))));
+    assert_eq!(actual, cpu.screen().ascii_art());
+}
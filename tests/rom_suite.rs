@@ -0,0 +1,132 @@
+// Runs the test ROMs in tests/roms headlessly and compares their final screens with the .screen
+// files next to them, through --expect-screen. Prefix cargo test with UPDATE_GOLDEN=1 to store
+// the current screens instead, after checking that they look right.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const CYCLES: &str = "1000";
+// for the same CXNN numbers on every run
+const SEED: &str = "1";
+
+// the ROM, the screen that it leaves behind and the options that it needs
+const ROMS: [(&str, &str, &[&str]); 9] = [
+    ("errors", "errors", &[]),
+    ("opcodes", "opcodes", &[]),
+    ("flags", "flags", &[]),
+    ("quirks", "quirks", &[]),
+    (
+        "quirks",
+        "quirks-schip",
+        &[
+            "--wrap-sprites",
+            "--lores-half-scroll",
+            "--hires-collision-rows",
+        ],
+    ),
+    ("font", "font", &[]),
+    ("bcd", "bcd", &[]),
+    ("hires", "hires", &[]),
+    ("xochip", "xochip", &["--xochip"]),
+];
+
+fn roms_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("roms")
+}
+
+fn check_rom(name: &str, screen: &str, options: &[&str]) {
+    let rom = roms_dir().join(name).with_extension("ch8");
+    // rather than leaving a crash.txt in the working directory
+    let crash_dump =
+        std::env::temp_dir().join(format!("chip8-{}-{}.crash.txt", std::process::id(), screen));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_chip8_rust"))
+        .arg("run")
+        .arg("--path")
+        .arg(&rom)
+        .args(["--headless", "--cycles", CYCLES, "--seed", SEED])
+        .arg("--expect-screen")
+        .arg(roms_dir().join(screen).with_extension("screen"))
+        .arg("--crash-dump")
+        .arg(&crash_dump)
+        .args(options)
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success() && output.stderr.is_empty(),
+        "{} failed:\n{}",
+        screen,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn roms_match_their_screens() {
+    ROMS.iter()
+        .for_each(|(name, screen, options)| check_rom(name, screen, options));
+}
+
+// the file stems in tests/roms with the extension, sorted
+fn files_in_roms_dir(extension: &str) -> Vec<String> {
+    let mut names = std::fs::read_dir(roms_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|found| found == extension))
+        .map(|path| path.file_stem().unwrap().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[test]
+fn every_rom_and_screen_is_checked() {
+    let mut roms = ROMS
+        .iter()
+        .map(|(name, _, _)| name.to_string())
+        .collect::<Vec<_>>();
+    roms.sort();
+    roms.dedup();
+    assert_eq!(files_in_roms_dir("ch8"), roms);
+
+    let mut screens = ROMS
+        .iter()
+        .map(|(_, screen, _)| screen.to_string())
+        .collect::<Vec<_>>();
+    screens.sort();
+    assert_eq!(files_in_roms_dir("screen"), screens);
+}
+
+#[test]
+fn crashing_rom_fails() {
+    let temp = |name: &str| {
+        std::env::temp_dir().join(format!("chip8-{}-crash-{}", std::process::id(), name))
+    };
+    let (rom, screen, crash_dump) = (temp("rom.ch8"), temp("screen.txt"), temp("dump.txt"));
+    // returns without a subroutine to return from
+    std::fs::write(&rom, [0x00, 0xEE]).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_chip8_rust"))
+        .arg("run")
+        .arg("--path")
+        .arg(&rom)
+        .args(["--headless", "--cycles", CYCLES])
+        .arg("--dump-screen")
+        .arg(&screen)
+        .arg("--crash-dump")
+        .arg(&crash_dump)
+        .output()
+        .unwrap();
+    [rom, crash_dump].iter().for_each(|path| {
+        let _ = std::fs::remove_file(path);
+    });
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("StackUnderflow"));
+    // still written, as it shows where the program stopped
+    assert!(std::fs::remove_file(&screen).is_ok());
+}